@@ -0,0 +1,514 @@
+use crate::nmea;
+use std::collections::HashMap;
+
+pub mod targets;
+
+// AIS messages come as !AIVDM (other ships) and !AIVDO (our own ship), with
+// the binary message "armoured" into six bits a character, and the longer
+// messages split over two or more sentences:
+//
+// !AIVDM,1,1,,B,15MgK45P3@G?fl0E`JbR0OwT0@MS,0*4E
+//        | | | |  |                          +- fill bits on the end
+//        | | | |  +- payload
+//        | | | +- radio channel
+//        | | +- sequence id, to tell groups of fragments apart
+//        | +- fragment number
+//        +- number of fragments
+//
+// The bits are kept one to a byte, which is wasteful but keeps the field
+// getting and setting simple.
+
+// Turn the payload characters into bits
+pub fn unarmor(payload: &str, fill: usize) -> Option<Vec<u8>> {
+    let mut bits = Vec::with_capacity(payload.len() * 6);
+    for c in payload.bytes() {
+        if !(48..=119).contains(&c) || (88..96).contains(&c) {
+            return None;
+        }
+        let mut v = c - 48;
+        if v > 40 {
+            v -= 8;
+        }
+        bits.extend((0..6).rev().map(|i| (v >> i) & 1));
+    }
+    bits.truncate(bits.len().saturating_sub(fill));
+    Some(bits)
+}
+
+// Turn bits back into payload characters and the number of fill bits needed
+pub fn armor(bits: &[u8]) -> (String, usize) {
+    let fill = (6 - bits.len() % 6) % 6;
+    let mut payload = String::with_capacity(bits.len() / 6 + 1);
+    for chunk in bits.chunks(6) {
+        let mut v = 0u8;
+        for i in 0..6 {
+            v = (v << 1) | chunk.get(i).copied().unwrap_or(0);
+        }
+        payload.push((if v < 40 { v + 48 } else { v + 56 }) as char);
+    }
+    (payload, fill)
+}
+
+pub fn uint(bits: &[u8], start: usize, len: usize) -> u64 {
+    (start..start + len).fold(0, |v, i| (v << 1) | bits.get(i).copied().unwrap_or(0) as u64)
+}
+
+pub fn int(bits: &[u8], start: usize, len: usize) -> i64 {
+    let v = uint(bits, start, len) as i64;
+    if len > 0 && bits.get(start) == Some(&1) {
+        v - (1 << len)
+    } else {
+        v
+    }
+}
+
+// Fields past the end of a short message are left alone
+pub fn set_uint(bits: &mut [u8], start: usize, len: usize, value: u64) {
+    for i in 0..len {
+        if let Some(b) = bits.get_mut(start + i) {
+            *b = ((value >> (len - 1 - i)) & 1) as u8;
+        }
+    }
+}
+
+pub fn set_int(bits: &mut [u8], start: usize, len: usize, value: i64) {
+    set_uint(bits, start, len, (value as u64) & ((1u64 << len) - 1));
+}
+
+// Six bit text, with the '@' padding and trailing spaces taken off
+pub fn text(bits: &[u8], start: usize, len: usize) -> String {
+    let mut s = String::with_capacity(len / 6);
+    for i in (start..start + len).step_by(6) {
+        if i + 6 > bits.len() {
+            break;
+        }
+        let v = uint(bits, i, 6) as u8;
+        if v == 0 {
+            break;
+        }
+        s.push((if v < 32 { v + 64 } else { v }) as char);
+    }
+    s.trim_end().to_string()
+}
+
+// Six bit text, padded out with '@'
+pub fn set_text(bits: &mut [u8], start: usize, len: usize, value: &str) {
+    let mut chars = value.bytes().map(|c| c.to_ascii_uppercase());
+    for i in (start..start + len).step_by(6) {
+        let c = chars.next().unwrap_or(b'@');
+        let v = if (64..96).contains(&c) { c - 64 } else if (32..64).contains(&c) { c } else { 0 };
+        set_uint(bits, i, 6, v as u64);
+    }
+}
+
+// Positions are in 1/10000 of a minute, with 181 and 91 meaning "not available"
+pub const LON_NOT_AVAILABLE: i64 = 181 * 600_000;
+pub const LAT_NOT_AVAILABLE: i64 = 91 * 600_000;
+
+pub fn lat_lon(bits: &[u8], lon_at: usize, lat_at: usize) -> Option<(f64, f64)> {
+    let lon = int(bits, lon_at, 28);
+    let lat = int(bits, lat_at, 27);
+    if bits.len() < lat_at + 27 || lon.abs() > 180 * 600_000 || lat.abs() > 90 * 600_000 {
+        return None;
+    }
+    Some((lat as f64 / 600_000.0, lon as f64 / 600_000.0))
+}
+
+pub fn set_lat_lon(bits: &mut [u8], lon_at: usize, lat_at: usize, pos: Option<(f64, f64)>) {
+    let (lat, lon) = match pos {
+        Some((lat, lon)) => ((lat * 600_000.0).round() as i64, (lon * 600_000.0).round() as i64),
+        None => (LAT_NOT_AVAILABLE, LON_NOT_AVAILABLE),
+    };
+    set_int(bits, lon_at, 28, lon);
+    set_int(bits, lat_at, 27, lat);
+}
+
+// Where the position, course and heading are in the messages that have them:
+// class A (1, 2, 3) and class B (18, 19) position reports, base stations (4),
+// UTC replies (11) and aids to navigation (21)
+pub fn motion_at(kind: u64) -> (Option<(usize, usize)>, Option<usize>, Option<usize>) {
+    match kind {
+        1..=3 => (Some((61, 89)), Some(116), Some(128)),
+        4 | 11 => (Some((79, 107)), None, None),
+        18 | 19 => (Some((57, 85)), Some(112), Some(124)),
+        21 => (Some((164, 192)), None, None),
+        _ => (None, None, None),
+    }
+}
+
+// Put the position in a message wherever map says (or "not available" if it
+// says None) and turn the course and heading by rotate. Anything that was
+// already not available stays that way.
+pub fn move_motion(bits: &mut [u8], map: impl FnOnce(f64, f64) -> Option<(f64, f64)>, rotate: f64) {
+    let (pos, cog, heading) = motion_at(uint(bits, 0, 6));
+    if let Some((lon_at, lat_at)) = pos {
+        if let Some((lat, lon)) = lat_lon(bits, lon_at, lat_at) {
+            set_lat_lon(bits, lon_at, lat_at, map(lat, lon));
+        }
+    }
+    if rotate != 0.0 {
+        // 3600 and 511 mean not available
+        if let Some(at) = cog.filter(|at| uint(bits, *at, 12) < 3600) {
+            let c = uint(bits, at, 12) as f64 / 10.0;
+            set_uint(bits, at, 12, ((c + rotate).rem_euclid(360.0) * 10.0).round() as u64 % 3600);
+        }
+        if let Some(at) = heading.filter(|at| uint(bits, *at, 9) < 360) {
+            let h = uint(bits, at, 9) as f64;
+            set_uint(bits, at, 9, (h + rotate).rem_euclid(360.0).round() as u64 % 360);
+        }
+    }
+}
+
+// Gathers up the fragments of multi-sentence messages. Hand it every AIS
+// line; it gives back all of the lines of a message once the last one is in.
+#[derive(Default)]
+pub struct Assembler {
+    pending: HashMap<String, Vec<String>>,
+}
+
+impl Assembler {
+    pub fn new() -> Assembler {
+        Assembler::default()
+    }
+
+    pub fn add(&mut self, line: &str) -> Option<Vec<String>> {
+        let fields: Vec<&str> = line.split(',').collect();
+        if fields.len() < 7 {
+            return None;
+        }
+        let count: usize = fields[1].parse().ok()?;
+        let number: usize = fields[2].parse().ok()?;
+        if count <= 1 {
+            return Some(vec![line.to_string()]);
+        }
+        // Groups are told apart by the sequence id and channel
+        let key = format!("{},{},{}", fields[0].get(3..).unwrap_or(""), fields[3], fields[4]);
+        if number == 1 {
+            self.pending.insert(key.clone(), vec![]);
+        }
+        let group = self.pending.get_mut(&key)?;
+        if group.len() + 1 != number {
+            self.pending.remove(&key);
+            return None;
+        }
+        group.push(line.to_string());
+        if number == count {
+            self.pending.remove(&key)
+        } else {
+            None
+        }
+    }
+
+    // Fragments still waiting for the rest of their message
+    pub fn incomplete(&self) -> usize {
+        self.pending.values().map(|g| g.len()).sum()
+    }
+}
+
+// The bits of a whole message from all of its sentences
+pub fn message_bits(lines: &[String]) -> Option<Vec<u8>> {
+    let mut bits = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        let (fields, _) = nmea::split_fields(line)?;
+        let payload = fields.get(5)?;
+        // Only the last fragment has fill bits
+        let fill = if i + 1 == lines.len() { fields.get(6)?.parse().ok()? } else { 0 };
+        bits.extend(unarmor(payload, fill)?);
+    }
+    Some(bits)
+}
+
+// Put changed bits back into the same sentences, keeping each fragment the
+// same length it was so nothing else about them changes.
+pub fn with_bits(lines: &[String], bits: &[u8]) -> Vec<String> {
+    let mut out = vec![];
+    let mut at = 0;
+    for (i, line) in lines.iter().enumerate() {
+        let (mut fields, with_sum) = match nmea::split_fields(line) {
+            Some(split) if split.0.len() >= 7 => split,
+            _ => return lines.to_vec(),
+        };
+        let end = if i + 1 == lines.len() { bits.len() } else { at + fields[5].len() * 6 };
+        let (payload, fill) = armor(&bits[at.min(bits.len())..end.min(bits.len())]);
+        fields[5] = payload;
+        fields[6] = fill.to_string();
+        at = end;
+        out.push(nmea::rebuild(line, &fields, with_sum));
+    }
+    out
+}
+
+// What we understand of one message: who sent it, and whatever it says about
+// where they are or who they are. Anything the message doesn't have, or has
+// as "not available", is None (or empty for the names).
+#[derive(Default)]
+pub struct Report {
+    pub kind: u64,
+    pub mmsi: u32,
+    pub pos: Option<(f64, f64)>,
+    pub cog: Option<f64>,
+    pub sog: Option<f64>,
+    pub heading: Option<f64>,
+    pub name: String,
+    pub call_sign: String,
+    pub ship_type: Option<u8>,
+}
+
+// Position reports from class A (1, 2, 3) and class B (18, 19), static and
+// voyage data (5) and class B static data (19 and 24)
+pub fn decode(bits: &[u8]) -> Option<Report> {
+    if bits.len() < 38 {
+        return None;
+    }
+    let mut r = Report {
+        kind: uint(bits, 0, 6),
+        mmsi: uint(bits, 8, 30) as u32,
+        ..Report::default()
+    };
+    // Where speed, position, course and heading are in each position report
+    let motion = match r.kind {
+        1..=3 => Some((50, 61, 89, 116, 128)),
+        18 | 19 => Some((46, 57, 85, 112, 124)),
+        _ => None,
+    };
+    if let Some((sog_at, lon_at, lat_at, cog_at, heading_at)) = motion {
+        if bits.len() < heading_at + 9 {
+            return None;
+        }
+        // 1023, 3600 and 511 mean not available
+        r.sog = Some(uint(bits, sog_at, 10)).filter(|v| *v < 1023).map(|v| v as f64 / 10.0);
+        r.pos = lat_lon(bits, lon_at, lat_at);
+        r.cog = Some(uint(bits, cog_at, 12)).filter(|v| *v < 3600).map(|v| v as f64 / 10.0);
+        r.heading = Some(uint(bits, heading_at, 9)).filter(|v| *v < 360).map(|v| v as f64);
+    }
+    match r.kind {
+        5 if bits.len() >= 240 => {
+            r.call_sign = text(bits, 70, 42);
+            r.name = text(bits, 112, 120);
+            r.ship_type = Some(uint(bits, 232, 8) as u8);
+        }
+        19 if bits.len() >= 271 => {
+            r.name = text(bits, 143, 120);
+            r.ship_type = Some(uint(bits, 263, 8) as u8);
+        }
+        // Part A has the name, part B the ship type and call sign
+        24 if bits.len() >= 160 => match uint(bits, 38, 2) {
+            0 => r.name = text(bits, 40, 120),
+            _ => {
+                r.ship_type = Some(uint(bits, 40, 8) as u8);
+                r.call_sign = text(bits, 90, 42);
+            }
+        },
+        1..=3 | 5 | 18 | 19 | 24 => {}
+        _ => return None,
+    }
+    Some(r)
+}
+
+// Ship type 36 is a sailing vessel
+pub const SAILING: u8 = 36;
+
+// Sentences for a report of our own making: a class A (1) or class B (18)
+// position report, class A static and voyage data (5), or both parts of a
+// class B static data report (24). Only the fields in Report are filled in;
+// the rest are "not available" or zero.
+pub fn encode(r: &Report) -> Vec<String> {
+    let mut messages = vec![];
+    match r.kind {
+        1..=3 | 18 => {
+            let mut bits = vec![0u8; 168];
+            set_uint(&mut bits, 0, 6, r.kind);
+            set_uint(&mut bits, 8, 30, r.mmsi as u64);
+            let class_a = r.kind != 18;
+            if class_a {
+                // Under way sailing, and no rate of turn
+                set_uint(&mut bits, 38, 4, 8);
+                set_int(&mut bits, 42, 8, -128);
+            } else {
+                // A carrier sense unit, the usual sort of class B
+                set_uint(&mut bits, 141, 1, 1);
+            }
+            let (sog_at, lon_at, lat_at, cog_at, heading_at) =
+                if class_a { (50, 61, 89, 116, 128) } else { (46, 57, 85, 112, 124) };
+            set_uint(&mut bits, sog_at, 10, r.sog.map_or(1023, |s| ((s * 10.0).round() as u64).min(1022)));
+            set_lat_lon(&mut bits, lon_at, lat_at, r.pos);
+            set_uint(&mut bits, cog_at, 12, r.cog.map_or(3600, |c| (c.rem_euclid(360.0) * 10.0).round() as u64 % 3600));
+            set_uint(&mut bits, heading_at, 9, r.heading.map_or(511, |h| h.rem_euclid(360.0).round() as u64 % 360));
+            // No time stamp
+            set_uint(&mut bits, heading_at + 9, 6, 60);
+            messages.push(bits);
+        }
+        5 => {
+            let mut bits = vec![0u8; 424];
+            set_uint(&mut bits, 0, 6, 5);
+            set_uint(&mut bits, 8, 30, r.mmsi as u64);
+            set_text(&mut bits, 70, 42, &r.call_sign);
+            set_text(&mut bits, 112, 120, &r.name);
+            set_uint(&mut bits, 232, 8, r.ship_type.unwrap_or(0) as u64);
+            // A GPS fix, and no ETA (month and day 0, hour 24 and minute 60 mean not available)
+            set_uint(&mut bits, 270, 4, 1);
+            set_uint(&mut bits, 283, 5, 24);
+            set_uint(&mut bits, 288, 6, 60);
+            set_text(&mut bits, 302, 120, "");
+            messages.push(bits);
+        }
+        24 => {
+            let mut part_a = vec![0u8; 160];
+            set_uint(&mut part_a, 0, 6, 24);
+            set_uint(&mut part_a, 8, 30, r.mmsi as u64);
+            set_text(&mut part_a, 40, 120, &r.name);
+            let mut part_b = vec![0u8; 168];
+            set_uint(&mut part_b, 0, 6, 24);
+            set_uint(&mut part_b, 8, 30, r.mmsi as u64);
+            set_uint(&mut part_b, 38, 2, 1);
+            set_uint(&mut part_b, 40, 8, r.ship_type.unwrap_or(0) as u64);
+            set_text(&mut part_b, 90, 42, &r.call_sign);
+            messages.push(part_a);
+            messages.push(part_b);
+        }
+        _ => {}
+    }
+    let mut lines = vec![];
+    for bits in messages {
+        let (payload, fill) = armor(&bits);
+        // Long messages are split into fragments of 60 characters, each group
+        // with a sequence id to keep it apart from any others
+        let chunks: Vec<&str> = payload.as_bytes().chunks(60).map(|c| std::str::from_utf8(c).unwrap_or("")).collect();
+        let sequence = if chunks.len() > 1 { (r.mmsi % 10).to_string() } else { String::new() };
+        for (i, chunk) in chunks.iter().enumerate() {
+            let last = i + 1 == chunks.len();
+            let body = format!(
+                "AIVDM,{},{},{},A,{},{}",
+                chunks.len(),
+                i + 1,
+                sequence,
+                chunk,
+                if last { fill } else { 0 }
+            );
+            lines.push(format!("!{}*{:02X}", body, nmea::checksum(&body)));
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Decode the sentences encode made, the same way a receiver would
+    fn decode_lines(lines: &[String]) -> Vec<Vec<u8>> {
+        let mut assembler = Assembler::new();
+        lines.iter().filter_map(|line| assembler.add(line)).filter_map(|group| message_bits(&group)).collect()
+    }
+
+    #[test]
+    fn static_and_voyage_data_round_trip() {
+        let lines = encode(&Report {
+            kind: 5,
+            mmsi: 235_000_001,
+            name: "NORMAN".to_string(),
+            call_sign: "MABC1".to_string(),
+            ship_type: Some(70),
+            ..Report::default()
+        });
+        assert_eq!(lines.len(), 2);
+        let messages = decode_lines(&lines);
+        assert_eq!(messages.len(), 1);
+        let bits = &messages[0];
+        assert_eq!(bits.len(), 424);
+        let r = decode(bits).unwrap();
+        assert_eq!((r.kind, r.mmsi), (5, 235_000_001));
+        assert_eq!((r.name.as_str(), r.call_sign.as_str(), r.ship_type), ("NORMAN", "MABC1", Some(70)));
+        // ETA month, day, hour and minute all "not available"
+        assert_eq!(uint(bits, 274, 4), 0);
+        assert_eq!(uint(bits, 278, 5), 0);
+        assert_eq!(uint(bits, 283, 5), 24);
+        assert_eq!(uint(bits, 288, 6), 60);
+        assert_eq!(uint(bits, 294, 8), 0);
+    }
+
+    #[test]
+    fn decodes_a_real_position_report() {
+        let lines = vec!["!AIVDM,1,1,,A,15RTgt0PAso;90TKcjM8h6g208CQ,0*4A".to_string()];
+        let bits = &decode_lines(&lines)[0];
+        let r = decode(bits).unwrap();
+        assert_eq!((r.kind, r.mmsi), (1, 371_798_000));
+        assert_eq!((r.sog, r.cog, r.heading), (Some(12.3), Some(224.0), Some(215.0)));
+        let (lat, lon) = r.pos.unwrap();
+        assert!((lat - 48.381_63).abs() < 1e-5 && (lon + 123.395_38).abs() < 1e-5);
+        // and the bits go back to the same sentence
+        assert_eq!(with_bits(&lines, bits), lines);
+    }
+
+    #[test]
+    fn armoring_round_trips_with_fill_bits() {
+        let bits: Vec<u8> = (0..40).map(|i| ((i * 7) % 3 == 0) as u8).collect();
+        let (payload, fill) = armor(&bits);
+        assert_eq!((payload.len(), fill), (7, 2));
+        assert_eq!(unarmor(&payload, fill).unwrap(), bits);
+        // Characters outside the six bit alphabet
+        assert_eq!(unarmor("15RT X", 0), None);
+        assert_eq!(unarmor("15RT`w", 0).map(|b| b.len()), Some(36));
+    }
+
+    #[test]
+    fn signed_fields_and_text() {
+        let mut bits = vec![0u8; 64];
+        set_int(&mut bits, 3, 8, -128);
+        assert_eq!(int(&bits, 3, 8), -128);
+        assert_eq!(uint(&bits, 3, 8), 128);
+        set_int(&mut bits, 20, 28, -74_037_228);
+        assert_eq!(int(&bits, 20, 28), -74_037_228);
+        // Writing past the end of the bits does nothing
+        set_uint(&mut bits, 60, 8, 255);
+        assert_eq!(uint(&bits, 60, 4), 15);
+
+        let mut bits = vec![0u8; 42];
+        set_text(&mut bits, 0, 42, "ab-1");
+        assert_eq!(text(&bits, 0, 42), "AB-1");
+        set_text(&mut bits, 0, 42, "SEA WOLF");
+        assert_eq!(text(&bits, 0, 42), "SEA WOL");
+    }
+
+    #[test]
+    fn position_reports_round_trip() {
+        for kind in [1, 18] {
+            let lines = encode(&Report {
+                kind,
+                mmsi: 235_000_002,
+                pos: Some((50.5, -1.25)),
+                sog: Some(6.4),
+                cog: Some(359.96),
+                heading: Some(-10.0),
+                ..Report::default()
+            });
+            assert_eq!(lines.len(), 1);
+            assert!(lines.iter().all(|l| nmea::checksum_ok(l) == Some(true)));
+            let r = decode(&decode_lines(&lines)[0]).unwrap();
+            assert_eq!((r.kind, r.mmsi), (kind, 235_000_002));
+            assert_eq!(r.pos, Some((50.5, -1.25)));
+            assert_eq!((r.sog, r.cog, r.heading), (Some(6.4), Some(0.0), Some(350.0)));
+        }
+        // Nothing known but who we are
+        let lines = encode(&Report { kind: 18, mmsi: 235_000_003, ..Report::default() });
+        let r = decode(&decode_lines(&lines)[0]).unwrap();
+        assert_eq!((r.pos, r.sog, r.cog, r.heading), (None, None, None, None));
+    }
+
+    #[test]
+    fn class_b_static_data_round_trips() {
+        let lines = encode(&Report {
+            kind: 24,
+            mmsi: 235_000_004,
+            name: "SEA WOLF".to_string(),
+            call_sign: "2ABC3".to_string(),
+            ship_type: Some(SAILING),
+            ..Report::default()
+        });
+        let reports: Vec<Report> = decode_lines(&lines).iter().filter_map(|bits| decode(bits)).collect();
+        assert_eq!(reports.len(), 2);
+        assert!(reports.iter().all(|r| r.kind == 24 && r.mmsi == 235_000_004));
+        assert_eq!((reports[0].name.as_str(), reports[0].ship_type), ("SEA WOLF", None));
+        assert_eq!((reports[1].call_sign.as_str(), reports[1].ship_type), ("2ABC3", Some(SAILING)));
+    }
+}
@@ -0,0 +1,107 @@
+use super::{decode, message_bits, Assembler};
+use crate::geo::{self, Motion};
+use crate::nmea;
+use chrono::NaiveDateTime;
+use std::collections::BTreeMap;
+
+// Everything we have heard about the other boats, by MMSI. Position reports
+// and static data come in separate messages (and for class B, in two halves),
+// so each target is built up from whatever comes along. Our own ship (VDO) is
+// left out. Targets are never forgotten, so the scanner can list everyone at
+// the end; the screen only shows the ones heard from lately.
+#[derive(Default)]
+pub struct Targets {
+    assembler: Assembler,
+    pub list: BTreeMap<u32, Target>,
+}
+
+pub struct Target {
+    pub mmsi: u32,
+    pub class_b: bool,
+    pub name: String,
+    pub call_sign: String,
+    pub ship_type: Option<u8>,
+    pub pos: Option<(f64, f64)>,
+    pub cog: Option<f64>,
+    pub sog: Option<f64>,
+    pub heading: Option<f64>,
+    pub reports: u64,
+    pub first_seen: Option<NaiveDateTime>,
+    pub last_seen: Option<NaiveDateTime>,
+    // The closest it came to us in metres, and when
+    pub closest: Option<(f64, Option<NaiveDateTime>)>,
+}
+
+impl Targets {
+    // Hand it every AIS line along with the file time and our own position
+    pub fn add(&mut self, line: &str, now: Option<NaiveDateTime>, own: Option<(f64, f64)>) {
+        if !nmea::tag(line).is_some_and(|t| t.ends_with("VDM")) {
+            return;
+        }
+        let report = match self.assembler.add(line).and_then(|group| message_bits(&group)).and_then(|b| decode(&b)) {
+            Some(report) => report,
+            None => return,
+        };
+        let t = self.list.entry(report.mmsi).or_insert_with(|| Target {
+            mmsi: report.mmsi,
+            class_b: false,
+            name: String::new(),
+            call_sign: String::new(),
+            ship_type: None,
+            pos: None,
+            cog: None,
+            sog: None,
+            heading: None,
+            reports: 0,
+            first_seen: None,
+            last_seen: None,
+            closest: None,
+        });
+        t.reports += 1;
+        t.class_b = matches!(report.kind, 18 | 19 | 24);
+        if now.is_some() {
+            t.first_seen = t.first_seen.or(now);
+            t.last_seen = now;
+        }
+        if !report.name.is_empty() {
+            t.name = report.name;
+        }
+        if !report.call_sign.is_empty() {
+            t.call_sign = report.call_sign;
+        }
+        t.ship_type = report.ship_type.or(t.ship_type);
+        if report.pos.is_some() {
+            t.pos = report.pos;
+            t.cog = report.cog;
+            t.sog = report.sog;
+            t.heading = report.heading;
+        }
+        if let (Some((lat, lon)), Some((own_lat, own_lon))) = (report.pos, own) {
+            let range = geo::distance_m(own_lat, own_lon, lat, lon);
+            if t.closest.is_none_or(|(closest, _)| range < closest) {
+                t.closest = Some((range, now));
+            }
+        }
+    }
+}
+
+impl Target {
+    pub fn display_name(&self) -> &str {
+        if self.name.is_empty() {
+            "-"
+        } else {
+            &self.name
+        }
+    }
+
+    // Where it is and where it's going, if we know both
+    pub fn motion(&self) -> Option<Motion> {
+        let (lat, lon) = self.pos?;
+        Some(Motion {
+            lat,
+            lon,
+            cog: self.cog.unwrap_or(0.0),
+            sog: self.sog.unwrap_or(0.0),
+        })
+    }
+}
@@ -0,0 +1,395 @@
+use crate::geo::{self, LatLon};
+use crate::{ais, nmea};
+use std::collections::hash_map::RandomState;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, Seek, Write};
+
+// Takes out of a log whatever would tell somebody where we keep the boat or
+// which boat it is, so a race can be shared with the class association or a
+// coach. Positions can be moved by a secret distance and bearing (and turned
+// by a secret angle), positions close to places we want to keep to ourselves
+// are left out altogether, and MMSIs and names are taken out of the AIS and
+// proprietary sentences. The names to look for in the proprietary sentences
+// are the ones the AIS messages in the file give, plus any from --name, so the
+// file is read twice. Everything that changes gets a new checksum so the
+// result plays back just like the original.
+
+#[derive(clap::Args)]
+pub struct AnonymizeOptions {
+    /// Move every position by a distance and bearing worked out from this secret (the same secret always gives the same move)
+    #[arg(long, value_name = "PASSPHRASE")]
+    pub secret: Option<String>,
+
+    /// Turn the track around where it starts by an angle worked out from the secret as well
+    #[arg(long, requires = "secret")]
+    pub rotate: bool,
+
+    /// Leave out every position within --radius of this place (may be repeated)
+    #[arg(long, value_name = "LAT,LON", allow_hyphen_values = true)]
+    pub hide: Vec<LatLon>,
+
+    /// How close to a --hide place a position has to be to be left out
+    #[arg(long, default_value_t = 500.0, value_name = "METRES")]
+    pub radius: f64,
+
+    /// Also blank out any field that is this name, anywhere in the file (may be repeated; names from the AIS in the file are always blanked in proprietary sentences)
+    #[arg(long, value_name = "NAME")]
+    pub name: Vec<String>,
+}
+
+pub struct AnonymizeSummary {
+    pub lines_written: u64,
+    pub positions_hidden: u64,
+    pub ais_messages: u64,
+    pub fragments_dropped: usize,
+}
+
+struct Anonymizer<'a> {
+    options: &'a AnonymizeOptions,
+    // What goes in front of an MMSI before it is hashed: the secret, or
+    // without one something random, so the made up MMSIs can't be worked
+    // back to the real ones by trying them all
+    salt: String,
+    // Names and call signs from the AIS messages in the file
+    ais_names: Vec<String>,
+    // Distance (metres), bearing and rotation worked out from the secret
+    mv: Option<(f64, f64, f64)>,
+    // The first position in the file and where it is moved to
+    start: Option<(LatLon, LatLon)>,
+    hidden: u64,
+}
+
+pub fn anonymize(mut file: File, out: &mut dyn Write, options: &AnonymizeOptions) -> io::Result<AnonymizeSummary> {
+    let ais_names = ais_names(&file)?;
+    file.rewind()?;
+    let mut anon = Anonymizer {
+        options,
+        salt: match &options.secret {
+            Some(secret) => secret.clone(),
+            None => format!("{:016x}", RandomState::new().build_hasher().finish()),
+        },
+        ais_names,
+        mv: options.secret.as_deref().map(|secret| {
+            let h = fnv(secret.as_bytes());
+            let distance_km = 20.0 + (h % 100) as f64;
+            let bearing = ((h >> 8) % 360) as f64;
+            let rotate = if options.rotate { ((h >> 20) % 360) as f64 } else { 0.0 };
+            (distance_km * 1000.0, bearing, rotate)
+        }),
+        start: None,
+        hidden: 0,
+    };
+    let mut assembler = ais::Assembler::new();
+    let mut summary = AnonymizeSummary {
+        lines_written: 0,
+        positions_hidden: 0,
+        ais_messages: 0,
+        fragments_dropped: 0,
+    };
+    let mut reader = io::BufReader::new(file);
+    let mut raw = vec![];
+    loop {
+        raw.clear();
+        if reader.read_until(b'\n', &mut raw)? == 0 {
+            break;
+        }
+        let text = String::from_utf8_lossy(&raw);
+        let line = text.trim_end();
+        let lines = match nmea::tag(line) {
+            // Wait for all of the fragments of an AIS message before changing any of them
+            Some(tag) if line.starts_with('!') && (tag.ends_with("VDM") || tag.ends_with("VDO")) => {
+                match assembler.add(line) {
+                    Some(group) => {
+                        summary.ais_messages += 1;
+                        anon.ais(&group)
+                    }
+                    None => continue,
+                }
+            }
+            Some(_) => vec![anon.sentence(line)],
+            None => vec![line.to_string()],
+        };
+        for line in lines {
+            write!(out, "{}\r\n", line)?;
+            summary.lines_written += 1;
+        }
+    }
+    // Half a message can't be changed, so it can't go out either
+    summary.fragments_dropped = assembler.incomplete();
+    summary.positions_hidden = anon.hidden;
+    Ok(summary)
+}
+
+// Every name and call sign in the AIS messages in the file, ours (VDO) included
+fn ais_names(file: &File) -> io::Result<Vec<String>> {
+    let mut assembler = ais::Assembler::new();
+    let mut names = vec![];
+    let mut reader = io::BufReader::new(file);
+    let mut raw = vec![];
+    loop {
+        raw.clear();
+        if reader.read_until(b'\n', &mut raw)? == 0 {
+            break;
+        }
+        let text = String::from_utf8_lossy(&raw);
+        let line = text.trim_end();
+        if !line.starts_with('!') {
+            continue;
+        }
+        if let Some(report) = assembler.add(line).and_then(|group| ais::message_bits(&group)).and_then(|b| ais::decode(&b)) {
+            for name in [report.name, report.call_sign] {
+                if !name.is_empty() && !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+    }
+    Ok(names)
+}
+
+impl Anonymizer<'_> {
+    fn sentence(&mut self, line: &str) -> String {
+        let mut line = line.to_string();
+        let proprietary = nmea::tag(&line).is_some_and(|t| t.starts_with('P'));
+        if proprietary || !self.options.name.is_empty() {
+            line = self.blank_fields(&line, proprietary);
+        }
+        let rotate = self.mv.map_or(0.0, |(_, _, r)| r);
+        let mut map = |lat: f64, lon: f64| self.position(lat, lon);
+        nmea::positions::rewrite(&line, &mut map, rotate)
+    }
+
+    // Names given with --name anywhere, and names from the AIS and anything
+    // that looks like an MMSI (nine digits) in proprietary sentences
+    fn blank_fields(&self, line: &str, proprietary: bool) -> String {
+        let (mut fields, with_sum) = match nmea::split_fields(line) {
+            Some(split) => split,
+            None => return line.to_string(),
+        };
+        let mut changed = false;
+        for field in fields.iter_mut().skip(1) {
+            let same = |n: &String| n.eq_ignore_ascii_case(field.trim());
+            let is_name = self.options.name.iter().any(same) || (proprietary && self.ais_names.iter().any(same));
+            if is_name {
+                field.clear();
+                changed = true;
+            } else if proprietary && field.len() == 9 && field.bytes().all(|b| b.is_ascii_digit()) {
+                *field = self.mmsi(field.parse().unwrap_or(0)).to_string();
+                changed = true;
+            }
+        }
+        if changed {
+            nmea::rebuild(line, &fields, with_sum)
+        } else {
+            line.to_string()
+        }
+    }
+
+    fn ais(&mut self, group: &[String]) -> Vec<String> {
+        let mut bits = match ais::message_bits(group) {
+            Some(bits) if bits.len() >= 38 => bits,
+            // Can't read it, so can't make it safe either
+            _ => return vec![],
+        };
+        let mmsi = ais::uint(&bits, 8, 30);
+        ais::set_uint(&mut bits, 8, 30, self.mmsi(mmsi));
+        match ais::uint(&bits, 0, 6) {
+            // Static and voyage data: IMO number, call sign, name and destination
+            5 => {
+                ais::set_uint(&mut bits, 40, 30, 0);
+                ais::set_text(&mut bits, 70, 42, "");
+                ais::set_text(&mut bits, 112, 120, "");
+                ais::set_text(&mut bits, 302, 120, "");
+            }
+            19 => ais::set_text(&mut bits, 143, 120, ""),
+            21 => ais::set_text(&mut bits, 43, 120, ""),
+            // Part A has the name and part B the call sign
+            24 => match ais::uint(&bits, 38, 2) {
+                0 => ais::set_text(&mut bits, 40, 120, ""),
+                _ => ais::set_text(&mut bits, 90, 42, ""),
+            },
+            _ => {}
+        }
+        let rotate = self.mv.map_or(0.0, |(_, _, r)| r);
+        ais::move_motion(&mut bits, |lat, lon| self.position(lat, lon), rotate);
+        ais::with_bits(group, &bits)
+    }
+
+    // Where a position should be now, or None if it is too close to a hidden place
+    fn position(&mut self, lat: f64, lon: f64) -> Option<(f64, f64)> {
+        let o = self.options;
+        if o.hide.iter().any(|h| geo::distance_m(h.lat, h.lon, lat, lon) <= o.radius) {
+            self.hidden += 1;
+            return None;
+        }
+        let (distance, bearing, rotate) = match self.mv {
+            Some(mv) => mv,
+            None => return Some((lat, lon)),
+        };
+        let (from, to) = *self.start.get_or_insert_with(|| {
+            let (to_lat, to_lon) = geo::destination(lat, lon, bearing, distance);
+            (LatLon { lat, lon }, LatLon { lat: to_lat, lon: to_lon })
+        });
+        let d = geo::distance_m(from.lat, from.lon, lat, lon);
+        let b = geo::bearing(from.lat, from.lon, lat, lon);
+        Some(geo::destination(to.lat, to.lon, b + rotate, d))
+    }
+
+    // The same MMSI always becomes the same made up one (in the 9xxxxxxxx range,
+    // which isn't given out to ships) so targets can still be told apart
+    fn mmsi(&self, mmsi: u64) -> u64 {
+        900_000_000 + fnv(format!("{}{}", self.salt, mmsi).as_bytes()) % 100_000_000
+    }
+}
+
+// FNV-1a, because it's tiny and gives the same answer on every machine and
+// every version of Rust (the standard library hasher doesn't promise that)
+fn fnv(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0xcbf29ce484222325, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(secret: Option<&str>) -> AnonymizeOptions {
+        AnonymizeOptions {
+            secret: secret.map(|s| s.to_string()),
+            rotate: false,
+            hide: vec![],
+            radius: 500.0,
+            name: vec![],
+        }
+    }
+
+    // anonymize reads the file twice, so the lines have to go in a real one
+    fn anonymize_lines(name: &str, lines: &[String], options: &AnonymizeOptions) -> (Vec<String>, AnonymizeSummary) {
+        let path = std::env::temp_dir().join(format!("nmea_player_anon_{}_{}.nmea", std::process::id(), name));
+        std::fs::write(&path, lines.iter().map(|l| format!("{}\r\n", l)).collect::<String>()).unwrap();
+        let mut out = vec![];
+        let summary = anonymize(File::open(&path).unwrap(), &mut out, options);
+        std::fs::remove_file(&path).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        (out.split_terminator("\r\n").map(|l| l.to_string()).collect(), summary.unwrap())
+    }
+
+    fn reports(lines: &[String]) -> Vec<ais::Report> {
+        let mut assembler = ais::Assembler::new();
+        lines
+            .iter()
+            .filter_map(|line| assembler.add(line))
+            .filter_map(|group| ais::message_bits(&group))
+            .filter_map(|bits| ais::decode(&bits))
+            .collect()
+    }
+
+    fn gga_position(line: &str) -> Option<(f64, f64)> {
+        let f: Vec<&str> = line.split(['*', ',']).collect();
+        nmea::parse_lat_lon(f[2], f[3], f[4], f[5])
+    }
+
+    fn norman() -> Vec<String> {
+        let mut lines = ais::encode(&ais::Report {
+            kind: 1,
+            mmsi: 235_000_001,
+            pos: Some((49.5, -124.0)),
+            ..ais::Report::default()
+        });
+        lines.extend(ais::encode(&ais::Report {
+            kind: 5,
+            mmsi: 235_000_001,
+            name: "NORMAN".to_string(),
+            call_sign: "MABC1".to_string(),
+            ..ais::Report::default()
+        }));
+        lines
+    }
+
+    #[test]
+    fn mmsis_and_names_are_taken_out() {
+        let mut lines = norman();
+        lines.push(nmea::with_checksum("PXYZ,Norman,235000001,MABC1,12"));
+        lines.push(nmea::with_checksum("GPWPL,4930.00,N,12400.00,W,NORMAN"));
+        let (out, summary) = anonymize_lines("mmsi", &lines, &options(Some("sesame")));
+        assert_eq!(summary.ais_messages, 2);
+        let r = reports(&out);
+        assert_eq!(r.len(), 2);
+        let mmsi = r[0].mmsi;
+        assert!(mmsi != 235_000_001 && mmsi / 100_000_000 == 9);
+        assert_eq!(r[1].mmsi, mmsi);
+        assert_eq!((r[1].name.as_str(), r[1].call_sign.as_str()), ("", ""));
+        // The names from the AIS only go from proprietary sentences, unless given with --name
+        let last = out.len() - 2;
+        assert_eq!(out[last], nmea::with_checksum(&format!("PXYZ,,{},,12", mmsi)));
+        assert!(out[last + 1].contains(",NORMAN*"));
+
+        // The same secret makes the same MMSI every time
+        let (again, _) = anonymize_lines("mmsi_again", &lines, &options(Some("sesame")));
+        assert_eq!(reports(&again)[0].mmsi, mmsi);
+        let (other, _) = anonymize_lines("mmsi_other", &lines, &options(Some("open")));
+        assert_ne!(reports(&other)[0].mmsi, mmsi);
+    }
+
+    #[test]
+    fn names_given_are_taken_out_everywhere() {
+        let lines = vec![nmea::with_checksum("GPWPL,4930.00,N,12400.00,W,HOME")];
+        let mut o = options(None);
+        o.name.push("home".to_string());
+        let (out, _) = anonymize_lines("name", &lines, &o);
+        assert_eq!(out, vec![nmea::with_checksum("GPWPL,4930.00,N,12400.00,W,")]);
+    }
+
+    #[test]
+    fn positions_near_a_hidden_place_are_left_out() {
+        let lines = vec![
+            nmea::with_checksum("GPGGA,120000,4930.00,N,12400.00,W,1,8,0.9,,M,,M"),
+            nmea::with_checksum("GPGGA,120001,4935.00,N,12400.00,W,1,8,0.9,,M,,M"),
+        ];
+        let mut o = options(None);
+        o.hide.push(LatLon { lat: 49.501, lon: -124.0 });
+        let (out, summary) = anonymize_lines("hide", &lines, &o);
+        assert_eq!(out[0], nmea::with_checksum("GPGGA,120000,,,,,0,8,0.9,,M,,M"));
+        assert_eq!(out[1], lines[1]);
+        assert_eq!(summary.positions_hidden, 1);
+
+        // AIS targets there are gone from the map too
+        let (out, summary) = anonymize_lines("hide_ais", &norman(), &o);
+        assert_eq!(reports(&out)[0].pos, None);
+        assert_eq!(summary.positions_hidden, 1);
+    }
+
+    #[test]
+    fn the_secret_moves_everything_the_same_way() {
+        let mut lines = vec![
+            nmea::with_checksum("GPGGA,120000,4930.0000,N,12400.0000,W,1,8,0.9,,M,,M"),
+            nmea::with_checksum("GPGGA,120001,4930.5000,N,12400.0000,W,1,8,0.9,,M,,M"),
+        ];
+        lines.extend(norman());
+        let (out, _) = anonymize_lines("move", &lines, &options(Some("sesame")));
+        let a = gga_position(&out[0]).unwrap();
+        let b = gga_position(&out[1]).unwrap();
+        assert!(geo::distance_m(49.5, -124.0, a.0, a.1) > 20_000.0);
+        // Half a minute of latitude apart, and still due north
+        assert!((geo::distance_m(a.0, a.1, b.0, b.1) - 926.0).abs() < 2.0);
+        assert!(geo::bearing(a.0, a.1, b.0, b.1) < 0.1);
+        // The AIS target was on the first fix, and still is
+        let (lat, lon) = reports(&out)[0].pos.unwrap();
+        assert!(geo::distance_m(a.0, a.1, lat, lon) < 1.0);
+    }
+
+    #[test]
+    fn half_a_message_is_dropped() {
+        let statics = ais::encode(&ais::Report {
+            kind: 5,
+            mmsi: 235_000_001,
+            name: "NORMAN".to_string(),
+            ..ais::Report::default()
+        });
+        let (out, summary) = anonymize_lines("half", &statics[..1], &options(None));
+        assert!(out.is_empty());
+        assert_eq!((summary.ais_messages, summary.fragments_dropped), (0, 1));
+    }
+}
@@ -0,0 +1,318 @@
+use crate::anonymize::AnonymizeOptions;
+use crate::converter::TrackFormat;
+use crate::scanner::{Format, ScanOptions};
+use crate::simulator::SimulateOptions;
+use crate::trim::TimeSpec;
+use crate::udp_broadcaster::rewrite::RewriteOptions;
+use clap::{Args, Parser, Subcommand};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(author, version)] // Read from `Cargo.toml`
+#[command(
+    about = "A Rust program to read a text file containing NMEA sentences and resend them across the network."
+)]
+#[command(
+    long_about = "This program will read a file specified by the user and perform various operations
+using the contents of the file as input. The most common way to use this program is
+to read in a NMEA0183 file and resend the NMEA sentences out onto the network using
+UDP broadcast on port 10110. This will appear to be a Comar system to Navionics and
+other navigation systems that listend for UDP broadcasts on the network.
+\n
+The program can also scan the given file and produce a report showing summary information
+about the NMEA sentences contained therein. For example, it will report on time stamps
+found in sentences like $GPZDA, which will, in turn allow the user to ask the program to
+start broadcasting over the network starting at a certain time in the file. This is very
+useful when analyzing sailboat races, for example, where there could be a lot of unwanted
+NMEA traffic before and after the race itself.
+\n
+Instead of a file the program can also take live NMEA from a UDP port or a serial device,
+show it on the same screen and pass it on to the outputs, optionally keeping a copy in a
+log file (see the 'record' command).
+\n
+Default settings for any of the options can be kept in a TOML config file, with named
+profiles for the command lines you use all the time (see --config and --profile).
+\n
+The original command line (--scan, --file, --udp, --if ...) without a command still works."
+)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    legacy: LegacyArgs,
+
+    /// Read settings from this TOML file [default: $XDG_CONFIG_HOME/nmea_player/config.toml]
+    #[arg(long, global = true, value_name = "CONFIG_FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Use the settings in [profile.NAME] of the config file as well
+    #[arg(long, global = true, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Print the settings that would be used, in config file format, and exit
+    #[arg(long, global = true)]
+    pub print_config: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Play a NMEA file (or a GPX, KML or CSV track) onto the network, keeping in synch with the times in the file
+    Play(Box<PlayArgs>),
+    /// Scan a NMEA file and report on what is in it
+    Scan(ScanArgs),
+    /// Show live NMEA from a UDP port or serial device and pass it on to the outputs
+    Record(RecordArgs),
+    /// Convert a NMEA file into a track for mapping software (GPX, KML, GeoJSON or CSV), or a track into NMEA
+    #[command(alias = "export")]
+    Convert(ConvertArgs),
+    /// Cut the lines between two times out of a NMEA file, exactly as they are, into a new file
+    Trim(TrimArgs),
+    /// Interleave two or more NMEA files of the same sail into one, by their times
+    Merge(MergeArgs),
+    /// Take our home berth, MMSI and boat name out of a NMEA file before sharing it
+    #[command(alias = "anonymise")]
+    Anonymize(AnonymizeArgs),
+    /// Make up NMEA for a boat sailing between waypoints and play it, in real time or faster
+    Simulate(Box<SimulateArgs>),
+    /// List the network interfaces that can be used with --if
+    ListInterfaces,
+}
+
+#[derive(Args)]
+pub struct PlayArgs {
+    #[arg(short, long="file", value_name = "NMEA_FILE")]
+    pub file_name: PathBuf,
+
+    /// Start playing from this file time, skipping everything before it [default: the start of the file]
+    #[arg(short, long, value_name = "hh:mm:ss[.ss] | yyyy-mm-ddThh:mm:ss")]
+    pub time: Option<TimeSpec>,
+
+    /// GGA/RMC/VTG sentences a second to make up when playing a GPX, KML or CSV track
+    #[arg(long, default_value_t = 1.0, value_name = "HZ")]
+    pub rate: f64,
+
+    #[command(flatten)]
+    pub rewrite: RewriteOptions,
+
+    #[command(flatten)]
+    pub outputs: OutputArgs,
+}
+
+#[derive(Args)]
+pub struct SimulateArgs {
+    #[command(flatten)]
+    pub options: SimulateOptions,
+
+    #[command(flatten)]
+    pub rewrite: RewriteOptions,
+
+    #[command(flatten)]
+    pub outputs: OutputArgs,
+}
+
+#[derive(Args)]
+pub struct ScanArgs {
+    #[arg(short, long="file", value_name = "NMEA_FILE")]
+    pub file_name: PathBuf,
+
+    /// How to print the report
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+
+    #[command(flatten)]
+    pub options: ScanOptions,
+}
+
+#[derive(Args)]
+pub struct ConvertArgs {
+    /// A NMEA file, or a GPX, KML or CSV track when converting to NMEA
+    #[arg(short, long="file", value_name = "FILE")]
+    pub file_name: PathBuf,
+
+    /// What to convert to [default: worked out from the output file name]
+    #[arg(long, value_enum)]
+    pub to: Option<TrackFormat>,
+
+    /// Where to write the result [default: standard output]
+    #[arg(short, long, value_name = "OUTPUT_FILE")]
+    pub output: Option<PathBuf>,
+
+    /// Make a point every so many seconds of file time instead of one per fix
+    #[arg(long, value_name = "SECONDS")]
+    pub resample: Option<f64>,
+
+    /// GGA/RMC/VTG sentences a second to make up when converting a track to NMEA
+    #[arg(long, default_value_t = 1.0, value_name = "HZ")]
+    pub rate: f64,
+}
+
+#[derive(Args)]
+pub struct TrimArgs {
+    #[arg(short, long="file", value_name = "NMEA_FILE")]
+    pub file_name: PathBuf,
+
+    /// Start at this file time (a time of day is taken to be on the first day in the file)
+    #[arg(long, value_name = "hh:mm:ss | yyyy-mm-ddThh:mm:ss")]
+    pub from: Option<TimeSpec>,
+
+    /// Stop after this file time
+    #[arg(long, value_name = "hh:mm:ss | yyyy-mm-ddThh:mm:ss")]
+    pub until: Option<TimeSpec>,
+
+    /// Start with the last ZDA, RMC and GGA from before the cut, so there is a time and position straight away
+    #[arg(long)]
+    pub prime: bool,
+
+    /// The file to write the trimmed lines to
+    #[arg(short, long, value_name = "OUTPUT_FILE")]
+    pub output: PathBuf,
+}
+
+#[derive(Args)]
+pub struct MergeArgs {
+    /// The files to merge (give --file once for each, at least two)
+    #[arg(short, long="file", value_name = "NMEA_FILE", required = true, num_args = 1)]
+    pub file_names: Vec<PathBuf>,
+
+    /// Seconds to add to the times in each file, in the same order as the files (one for each file)
+    #[arg(long, value_name = "SECONDS", allow_negative_numbers = true, conflicts_with = "align")]
+    pub offset: Vec<f64>,
+
+    /// Work out the offsets from the times of the first GGA or RMC fix in each file
+    #[arg(long)]
+    pub align: bool,
+
+    /// The file to write the merged lines to
+    #[arg(short, long, value_name = "OUTPUT_FILE")]
+    pub output: PathBuf,
+}
+
+#[derive(Args)]
+pub struct AnonymizeArgs {
+    #[arg(short, long="file", value_name = "NMEA_FILE")]
+    pub file_name: PathBuf,
+
+    /// The file to write the anonymized lines to
+    #[arg(short, long, value_name = "OUTPUT_FILE")]
+    pub output: PathBuf,
+
+    #[command(flatten)]
+    pub options: AnonymizeOptions,
+}
+
+#[derive(Args)]
+#[group(id = "input", required = true, args = ["listen_port", "serial"])]
+pub struct RecordArgs {
+    /// Read live NMEA received on this UDP port
+    #[arg(short, long="listen", value_name = "UDP_PORT")]
+    pub listen_port: Option<u16>,
+
+    /// Read live NMEA from a serial device
+    #[arg(long, value_name = "/dev/ttyUSB0 ... etc", conflicts_with = "listen_port")]
+    pub serial: Option<PathBuf>,
+
+    /// The speed of the serial device
+    #[arg(long, default_value_t = 4800, value_name = "BAUD")]
+    pub baud: u32,
+
+    #[command(flatten)]
+    pub outputs: OutputArgs,
+}
+
+// Where the sentences go, shared by every command that sends NMEA somewhere
+#[derive(Args)]
+pub struct OutputArgs {
+    #[arg(short, long="udp", default_value_t = 10110, value_name = "UDP_PORT")]
+    pub udp_port: u16,
+
+    #[arg(short, long="if", default_value = "eth0", value_name = "en0, eth0 ... etc")]
+    pub if_name: String,
+
+    /// Also send every sentence to this address (may be given more than once)
+    #[arg(long, value_name = "HOST:PORT")]
+    pub forward: Vec<SocketAddr>,
+
+    /// Append a copy of every sentence sent to this log file
+    #[arg(long, value_name = "LOG_FILE")]
+    pub tee: Option<PathBuf>,
+}
+
+// The flags from before there were commands. They are hidden from the help but
+// still accepted when no command is given so that old scripts keep working.
+#[derive(Args)]
+struct LegacyArgs {
+    #[arg(short, long, hide = true)]
+    scan: bool,
+
+    #[arg(short, long, hide = true)]
+    time: Option<TimeSpec>,
+
+    #[arg(short, long="udp", default_value_t = 10110, hide = true)]
+    udp_port: u16,
+
+    #[arg(short, long="if", default_value = "eth0", hide = true)]
+    if_name: String,
+
+    #[arg(short, long="file", value_name = "NMEA_FILE", hide = true, required_unless_present_any = ["listen_port", "serial"])]
+    file_name: Option<PathBuf>,
+
+    #[arg(short, long="listen", value_name = "UDP_PORT", hide = true, conflicts_with_all = ["file_name", "serial"])]
+    listen_port: Option<u16>,
+
+    #[arg(long, hide = true, conflicts_with = "file_name")]
+    serial: Option<PathBuf>,
+
+    #[arg(long, default_value_t = 4800, hide = true)]
+    baud: u32,
+
+    #[arg(long, hide = true)]
+    forward: Vec<SocketAddr>,
+
+    #[arg(long, hide = true)]
+    tee: Option<PathBuf>,
+}
+
+impl Cli {
+    // Work out which command to run, turning the old style flags into the
+    // command they stand for when no command was given.
+    pub fn into_command(self) -> Command {
+        if let Some(command) = self.command {
+            return command;
+        }
+        let legacy = self.legacy;
+        let outputs = OutputArgs {
+            udp_port: legacy.udp_port,
+            if_name: legacy.if_name,
+            forward: legacy.forward,
+            tee: legacy.tee,
+        };
+        if legacy.listen_port.is_some() || legacy.serial.is_some() {
+            return Command::Record(RecordArgs {
+                listen_port: legacy.listen_port,
+                serial: legacy.serial,
+                baud: legacy.baud,
+                outputs,
+            });
+        }
+        // clap has already made sure that we have a file by here
+        let file_name = legacy.file_name.unwrap_or_default();
+        if legacy.scan {
+            Command::Scan(ScanArgs {
+                file_name,
+                format: Format::Text,
+                options: ScanOptions::default(),
+            })
+        } else {
+            Command::Play(Box::new(PlayArgs {
+                file_name,
+                time: legacy.time,
+                rate: 1.0,
+                rewrite: RewriteOptions::default(),
+                outputs,
+            }))
+        }
+    }
+}
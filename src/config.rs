@@ -0,0 +1,213 @@
+use crate::cli::Cli;
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, CommandFactory, FromArgMatches};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+use std::process::exit;
+
+// Settings can come from a TOML file as well as from the command line. The keys
+// are simply the long option names, so a file like
+//
+//     if = "en0"
+//     forward = ["192.168.1.20:10110"]
+//
+//     [profile.race-review]
+//     file = "/logs/2021-02-22.nmea"
+//     time = "18:05:00"
+//
+// sets --if and --forward for every command and adds --file and --time when
+// run with "--profile race-review". Keys a command doesn't have are skipped so
+// one file can hold the settings for all of them. Anything given on the command
+// line wins over the file.
+//
+// Rather than merging values after the fact we turn the file into command line
+// arguments and let clap do all of the parsing and checking as usual.
+
+pub fn parse() -> Cli {
+    let raw: Vec<OsString> = env::args_os().collect();
+    let (args, path) = match with_config(&raw) {
+        Ok(found) => found,
+        Err(e) => Cli::command().error(clap::error::ErrorKind::InvalidValue, e).exit(),
+    };
+    let matches = Cli::command().get_matches_from(&args);
+    let cli = match Cli::from_arg_matches(&matches) {
+        Ok(cli) => cli,
+        Err(e) => e.exit(),
+    };
+    if cli.print_config {
+        print_config(&matches, path.as_ref(), cli.profile.as_deref());
+        exit(0);
+    }
+    cli
+}
+
+// The config file we should read, either the one given with --config or the
+// default one if it exists.
+fn config_path(matches: &ArgMatches) -> Option<PathBuf> {
+    if let Some(path) = matches.get_one::<PathBuf>("config") {
+        return Some(path.clone());
+    }
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".config"),
+            None => return None,
+        },
+    };
+    let path = dir.join("nmea_player").join("config.toml");
+    if path.exists() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+// Insert the settings from the config file into the command line, right after
+// the command name, for every option the user didn't give themselves. Also
+// hands back which config file was used, if any.
+fn with_config(raw: &[OsString]) -> Result<(Vec<OsString>, Option<PathBuf>), String> {
+    // A forgiving first pass just to find --config, --profile and the command.
+    // If even that fails (or it was --help) the real parse will say so.
+    let first = match Cli::command().ignore_errors(true).try_get_matches_from(raw) {
+        Ok(first) => first,
+        Err(_) => return Ok((raw.to_vec(), None)),
+    };
+    let path = match config_path(&first) {
+        Some(path) => path,
+        None => match first.get_one::<String>("profile") {
+            Some(name) => return Err(format!("profile '{}' needs a config file", name)),
+            None => return Ok((raw.to_vec(), None)),
+        },
+    };
+    let text = fs::read_to_string(&path).map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+    let table: toml::Table = text.parse().map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    // Top level keys first, then the profile on top of them
+    let mut settings = toml::Table::new();
+    for (key, value) in &table {
+        if key != "profile" {
+            settings.insert(key.replace('_', "-"), value.clone());
+        }
+    }
+    if let Some(name) = first.get_one::<String>("profile") {
+        let profile = table
+            .get("profile")
+            .and_then(|p| p.get(name))
+            .and_then(|p| p.as_table())
+            .ok_or_else(|| format!("no [profile.{}] in {}", name, path.display()))?;
+        for (key, value) in profile {
+            settings.insert(key.replace('_', "-"), value.clone());
+        }
+    }
+
+    let root = Cli::command();
+    // The command can be given by any of its aliases as well as its name
+    let (command, matches, insert_at) = match first.subcommand() {
+        Some((name, sub_matches)) => {
+            let command = root.find_subcommand(name).cloned().unwrap_or(root.clone());
+            let names: Vec<&str> = std::iter::once(command.get_name()).chain(command.get_all_aliases()).collect();
+            let at = raw
+                .iter()
+                .skip(1)
+                .position(|a| a.to_str().is_some_and(|a| names.contains(&a)))
+                .map_or(raw.len(), |at| at + 2);
+            (command, sub_matches, at)
+        }
+        None => (root.clone(), &first, 1.min(raw.len())),
+    };
+    let mut extra: Vec<OsString> = vec![];
+    for (key, value) in &settings {
+        let arg = match command.get_arguments().find(|a| a.get_long() == Some(key.as_str())) {
+            Some(arg) => arg,
+            None => {
+                if !known_anywhere(&root, key) {
+                    eprintln!("warning: unknown setting '{}' in {}", key, path.display());
+                }
+                continue;
+            }
+        };
+        // Anything given on the command line wins, however it was written
+        if matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        let values = match value {
+            toml::Value::Array(list) => list.iter().map(toml_to_arg).collect::<Result<Vec<_>, _>>()?,
+            other => vec![toml_to_arg(other)?],
+        };
+        for v in values {
+            if matches!(arg.get_action(), ArgAction::SetTrue) {
+                if v == "true" {
+                    extra.push(format!("--{}", key).into());
+                }
+            } else {
+                extra.push(format!("--{}={}", key, v).into());
+            }
+        }
+    }
+    let mut args = raw.to_vec();
+    args.splice(insert_at..insert_at, extra);
+    Ok((args, Some(path)))
+}
+
+fn known_anywhere(command: &clap::Command, key: &str) -> bool {
+    command.get_arguments().any(|a| a.get_long() == Some(key))
+        || command.get_subcommands().any(|c| known_anywhere(c, key))
+}
+
+fn toml_to_arg(value: &toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(s) => Ok(s.clone()),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        toml::Value::Datetime(d) => Ok(d.to_string()),
+        _ => Err(format!("can't use {} as a setting", value)),
+    }
+}
+
+// Dump the settings the chosen command will actually run with, in the same
+// format as the config file so the output can be pasted into a profile.
+fn print_config(matches: &ArgMatches, path: Option<&PathBuf>, profile: Option<&str>) {
+    let root = Cli::command();
+    let (name, command, matches) = match matches.subcommand() {
+        Some((name, sub)) => (name.to_string(), root.find_subcommand(name).cloned().unwrap_or(root.clone()), sub),
+        None => ("(no command)".to_string(), root.clone(), matches),
+    };
+    println!("# Effective settings for {}", name);
+    if let Some(path) = path {
+        println!("# Config file: {}", path.display());
+    }
+    if let Some(profile) = profile {
+        println!("# Profile: {}", profile);
+    }
+    for arg in command.get_arguments() {
+        let key = match arg.get_long() {
+            Some(key) if !["help", "version", "config", "profile", "print-config"].contains(&key) => key,
+            _ => continue,
+        };
+        let id = arg.get_id().as_str();
+        if matches!(arg.get_action(), ArgAction::SetTrue) {
+            println!("{} = {}", key, matches.get_flag(id));
+            continue;
+        }
+        let values: Vec<String> = match matches.get_raw(id) {
+            Some(raw) => raw.map(|v| toml_value(&v.to_string_lossy())).collect(),
+            None => continue,
+        };
+        if matches!(arg.get_action(), ArgAction::Append) {
+            println!("{} = [{}]", key, values.join(", "));
+        } else if let Some(value) = values.first() {
+            println!("{} = {}", key, value);
+        }
+    }
+}
+
+fn toml_value(s: &str) -> String {
+    if s.parse::<i64>().is_ok() || s.parse::<f64>().is_ok() {
+        s.to_string()
+    } else {
+        toml::Value::String(s.to_string()).to_string()
+    }
+}
@@ -0,0 +1,305 @@
+use crate::nmea;
+use crate::udp_broadcaster::instruments::{Instruments, Readings};
+use chrono::{Duration, NaiveDateTime};
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+mod csv;
+mod geojson;
+mod gpx;
+mod import;
+mod kml;
+mod sentences;
+
+pub use import::parse_time;
+pub use sentences::synthesize;
+
+// Turns a NMEA file into a track that can be looked at on a map or loaded into
+// a spreadsheet. The file is read with the same Instruments the player uses for
+// the screen, and every position fix (or every so many seconds of file time,
+// when resampling) becomes a track point carrying whatever else we knew at the
+// time (speed, course, depth, wind ...). It also works the other way round:
+// a GPX, KML or CSV track can be read in and turned into NMEA.
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum TrackFormat {
+    Gpx,
+    Kml,
+    Geojson,
+    Csv,
+    Nmea,
+}
+
+pub struct TrackPoint {
+    pub time: Option<NaiveDateTime>,
+    pub lat: f64,
+    pub lon: f64,
+    pub data: Readings,
+}
+
+// Read a track from any file we understand: GPX, KML or CSV by the file
+// extension, or a NMEA log otherwise.
+pub fn load_track(path: &Path, resample: Option<f64>) -> io::Result<Vec<TrackPoint>> {
+    if is_track_file(path) {
+        let format = format_from_path(path).unwrap();
+        import::read(&std::fs::read_to_string(path)?, format)
+    } else {
+        read_track(File::open(path)?, resample)
+    }
+}
+
+// A route to follow, as planned on a plotter or in a spreadsheet
+pub struct Waypoint {
+    pub name: String,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+// Read a route from a GPX file (its <rtept>s, or failing that its <wpt>s) or a
+// CSV with lat and lon columns and maybe a name column
+pub fn load_route(path: &Path) -> io::Result<Vec<Waypoint>> {
+    let text = std::fs::read_to_string(path)?;
+    match format_from_path(path) {
+        Some(format @ (TrackFormat::Gpx | TrackFormat::Csv)) => import::read_route(&text, format),
+        _ => Err(io::Error::other("a route has to be a GPX or CSV file")),
+    }
+}
+
+pub fn is_track_file(path: &Path) -> bool {
+    !matches!(format_from_path(path), None | Some(TrackFormat::Nmea))
+}
+
+// With a resample interval we make a point every so many seconds of file time
+// instead of one for every fix, filling in each value from the most recent
+// sentence that carried it.
+pub fn read_track(file: File, resample: Option<f64>) -> io::Result<Vec<TrackPoint>> {
+    match resample {
+        Some(secs) => read_resampled(file, Duration::milliseconds((secs * 1000.0) as i64)),
+        None => read_fixes(file),
+    }
+}
+
+fn read_fixes(file: File) -> io::Result<Vec<TrackPoint>> {
+    let reader = io::BufReader::new(file);
+    let mut inst = Instruments::new();
+    let mut clock = nmea::FileClock::new();
+    let mut points = vec![];
+    // Use whichever of GGA or RMC turns up first for the positions, so that
+    // files with both don't end up with every point twice.
+    let mut position_sentence: Option<String> = None;
+    for line in reader.lines() {
+        // Skip over anything that isn't text rather than giving up on the whole file
+        let line = match line {
+            Ok(line) => line,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => continue,
+            Err(e) => return Err(e),
+        };
+        let line = line.trim_end();
+        let now = clock.update(line);
+        inst.update(line);
+        let sentence = match nmea::sentence_type(line) {
+            Some(s) if s == "GGA" || s == "RMC" => s,
+            _ => continue,
+        };
+        if position_sentence.get_or_insert_with(|| sentence.to_string()) != sentence {
+            continue;
+        }
+        // GGA without a fix (or an RMC marked void) leaves the last position
+        // in place, so only take fresh ones.
+        let (lat, lon) = match inst.pos.take() {
+            Some(pos) => pos,
+            None => continue,
+        };
+        points.push(TrackPoint {
+            time: now,
+            lat,
+            lon,
+            data: inst.data.clone(),
+        });
+    }
+    Ok(points)
+}
+
+fn read_resampled(file: File, step: Duration) -> io::Result<Vec<TrackPoint>> {
+    if step <= Duration::zero() {
+        return Err(io::Error::other("the resample interval has to be more than zero"));
+    }
+    let reader = io::BufReader::new(file);
+    let mut inst = Instruments::new();
+    let mut clock = nmea::FileClock::new();
+    let mut points = vec![];
+    let mut last_pos: Option<(f64, f64)> = None;
+    let mut next_time: Option<NaiveDateTime> = None;
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => continue,
+            Err(e) => return Err(e),
+        };
+        let line = line.trim_end();
+        // Before taking in this line, write out all of the points that fall
+        // due up to its time with what we knew just before it.
+        if let Some(now) = clock.update(line) {
+            let mut due = *next_time.get_or_insert(round_up(now, step));
+            while due <= now {
+                if let Some((lat, lon)) = last_pos {
+                    points.push(TrackPoint {
+                        time: Some(due),
+                        lat,
+                        lon,
+                        data: inst.data.clone(),
+                    });
+                }
+                due += step;
+            }
+            next_time = Some(due);
+        }
+        inst.update(line);
+        if let Some(pos) = inst.pos.take() {
+            last_pos = Some(pos);
+        }
+    }
+    Ok(points)
+}
+
+// The first multiple of step (counted from midnight) at or after t
+fn round_up(t: NaiveDateTime, step: Duration) -> NaiveDateTime {
+    let midnight = t.date().and_hms_opt(0, 0, 0).unwrap();
+    let step_ms = step.num_milliseconds();
+    let since = (t - midnight).num_milliseconds();
+    midnight + Duration::milliseconds((since + step_ms - 1) / step_ms * step_ms)
+}
+
+// Guess the output format from the file name when --to wasn't given
+pub fn format_from_path(path: &Path) -> Option<TrackFormat> {
+    match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+        "gpx" => Some(TrackFormat::Gpx),
+        "kml" => Some(TrackFormat::Kml),
+        "geojson" | "json" => Some(TrackFormat::Geojson),
+        "csv" => Some(TrackFormat::Csv),
+        "nmea" | "nma" | "txt" | "log" => Some(TrackFormat::Nmea),
+        _ => None,
+    }
+}
+
+// The rate is how many GGA/RMC/VTG a second to make up when writing NMEA
+pub fn write_track(
+    out: &mut dyn Write,
+    points: &[TrackPoint],
+    format: TrackFormat,
+    name: &str,
+    rate_hz: f64,
+) -> io::Result<()> {
+    match format {
+        TrackFormat::Gpx => gpx::write(out, points, name),
+        TrackFormat::Kml => kml::write(out, points, name),
+        TrackFormat::Geojson => geojson::write(out, points, name),
+        TrackFormat::Csv => csv::write(out, points),
+        TrackFormat::Nmea => sentences::write(out, points, rate_hz),
+    }
+}
+
+fn iso_time(t: NaiveDateTime) -> String {
+    t.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    // read_track wants a file, so put the lines in one first
+    fn track_of(name: &str, lines: &[&str], resample: Option<f64>) -> Vec<TrackPoint> {
+        let path = std::env::temp_dir().join(format!("nmea_player_track_{}_{}.nmea", std::process::id(), name));
+        std::fs::write(&path, lines.iter().map(|l| format!("{}\r\n", l)).collect::<String>()).unwrap();
+        let points = read_track(File::open(&path).unwrap(), resample);
+        std::fs::remove_file(&path).unwrap();
+        points.unwrap()
+    }
+
+    fn at(h: u32, m: u32, s: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2021, 2, 22).unwrap().and_hms_opt(h, m, s).unwrap()
+    }
+
+    fn times(points: &[TrackPoint]) -> Vec<Option<NaiveDateTime>> {
+        points.iter().map(|p| p.time).collect()
+    }
+
+    const LOG: [&str; 7] = [
+        "$GPZDA,120000,22,02,2021,00,00",
+        "$GPGGA,120000,4930.00,N,12400.00,W,1,8,0.9,,M,,M",
+        "$GPRMC,120000,A,4930.00,N,12400.00,W,5.0,90.0,220221,,",
+        "$SDDPT,5.2,0.0",
+        "$GPGGA,120001,4930.00,N,12359.99,W,1,8,0.9,,M,,M",
+        "$GPGGA,120002,,,,,0,0,,,M,,M",
+        "$GPGGA,120003,4930.00,N,12359.98,W,1,8,0.9,,M,,M",
+    ];
+
+    #[test]
+    fn one_point_per_fix() {
+        let points = track_of("fixes", &LOG, None);
+        // The RMC doesn't make another point, and nor does the GGA without a fix
+        assert_eq!(times(&points), vec![Some(at(12, 0, 0)), Some(at(12, 0, 1)), Some(at(12, 0, 3))]);
+        assert!((points[0].lat - 49.5).abs() < 1e-9);
+        assert!((points[0].lon + 124.0).abs() < 1e-9);
+        assert_eq!(points[0].data.depth_m, None);
+        assert_eq!(points[1].data.depth_m, Some(5.2));
+        assert_eq!(points[1].data.sog_kts, Some(5.0));
+    }
+
+    #[test]
+    fn resampled_points_fall_on_the_step() {
+        let points = track_of("resampled", &LOG, Some(2.0));
+        // Nothing at 12:00:00 because there was no position before that line
+        assert_eq!(times(&points), vec![Some(at(12, 0, 2))]);
+        assert!((points[0].lon + 123.0 + 59.99 / 60.0).abs() < 1e-9);
+        assert!(read_track(File::open("Cargo.toml").unwrap(), Some(0.0)).is_err());
+    }
+
+    #[test]
+    fn rounding_up_to_the_step() {
+        let step = Duration::seconds(10);
+        assert_eq!(round_up(at(12, 0, 0), step), at(12, 0, 0));
+        assert_eq!(round_up(at(12, 0, 1), step), at(12, 0, 10));
+        assert_eq!(round_up(at(23, 59, 55), step), at(23, 59, 55) + Duration::seconds(5));
+    }
+
+    #[test]
+    fn formats_from_file_names() {
+        assert!(format_from_path(Path::new("a/track.GPX")) == Some(TrackFormat::Gpx));
+        assert!(format_from_path(Path::new("track.json")) == Some(TrackFormat::Geojson));
+        assert!(format_from_path(Path::new("track.log")) == Some(TrackFormat::Nmea));
+        assert!(format_from_path(Path::new("track")).is_none());
+        assert!(is_track_file(Path::new("track.kml")));
+        assert!(!is_track_file(Path::new("track.nmea")));
+        assert!(!is_track_file(Path::new("track.zip")));
+    }
+
+    #[test]
+    fn gpx_and_geojson_output() {
+        let points = track_of("output", &LOG, None);
+        let mut gpx = vec![];
+        write_track(&mut gpx, &points, TrackFormat::Gpx, "Tom & Jerry", 1.0).unwrap();
+        let gpx = String::from_utf8(gpx).unwrap();
+        assert!(gpx.contains("<name>Tom &amp; Jerry</name>"));
+        assert!(gpx.contains(r#"<trkpt lat="49.5000000" lon="-124.0000000">"#));
+        assert!(gpx.contains("<time>2021-02-22T12:00:01Z</time>"));
+        // 5 knots in metres per second
+        assert!(gpx.contains("<gpxtpx:speed>2.572</gpxtpx:speed>"));
+        assert_eq!(gpx.matches("<trkpt").count(), 3);
+
+        let mut geojson = vec![];
+        write_track(&mut geojson, &points, TrackFormat::Geojson, "track", 1.0).unwrap();
+        let geojson = json::parse(&String::from_utf8(geojson).unwrap()).unwrap();
+        let features = &geojson["features"];
+        assert_eq!(features.len(), 4);
+        assert_eq!(features[0]["geometry"]["coordinates"][0][0].as_f64(), Some(-124.0));
+        assert_eq!(features[0]["properties"]["end"].as_str(), Some("2021-02-22T12:00:03Z"));
+        assert_eq!(features[2]["properties"]["depth_m"].as_f64(), Some(5.2));
+    }
+}
@@ -0,0 +1,73 @@
+use super::TrackPoint;
+use std::fmt::Display;
+use std::io::{self, Write};
+
+// One row per point with every value we know about, for spreadsheets and
+// plotting. A value stays empty until the first sentence that carries it, and
+// after that each row has the most recent reading.
+const COLUMNS: &[&str] = &[
+    "time",
+    "lat",
+    "lon",
+    "cog_t",
+    "sog_kts",
+    "stw_kts",
+    "heading_t",
+    "heading_m",
+    "depth_m",
+    "awa",
+    "aws_kts",
+    "twa",
+    "tws_kts",
+    "twd_t",
+    "water_temp_c",
+    "log_nm",
+    "trip_nm",
+    "rudder",
+    "heel",
+    "pitch",
+    "pressure_hpa",
+    "fix_quality",
+    "sats",
+    "hdop",
+];
+
+pub fn write(out: &mut dyn Write, points: &[TrackPoint]) -> io::Result<()> {
+    writeln!(out, "{}", COLUMNS.join(","))?;
+    for p in points {
+        let d = &p.data;
+        let row = [
+            // Keep any fraction of a second for resampling at less than a second
+            p.time.map(|t| t.format("%Y-%m-%dT%H:%M:%S%.fZ").to_string()).unwrap_or_default(),
+            format!("{:.6}", p.lat),
+            format!("{:.6}", p.lon),
+            opt(d.cog_t),
+            opt(d.sog_kts),
+            opt(d.stw_kts),
+            opt(d.heading_t),
+            opt(d.heading_m),
+            opt(d.depth_m),
+            opt(d.awa),
+            opt(d.aws_kts),
+            opt(d.twa),
+            opt(d.tws_kts),
+            opt(d.twd_t),
+            opt(d.water_temp_c),
+            opt(d.log_nm),
+            opt(d.trip_nm),
+            opt(d.rudder),
+            opt(d.heel),
+            opt(d.pitch),
+            opt(d.pressure_hpa),
+            opt(d.fix_quality),
+            opt(d.sats),
+            opt(d.hdop),
+        ];
+        writeln!(out, "{}", row.join(","))?;
+    }
+    Ok(())
+}
+
+fn opt<T: Display>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
@@ -0,0 +1,52 @@
+use super::{iso_time, TrackPoint};
+use std::io::{self, Write};
+
+// GeoJSON FeatureCollection: the whole track as one LineString, plus a Point
+// for every fix with the instrument values as its properties.
+pub fn write(out: &mut dyn Write, points: &[TrackPoint], name: &str) -> io::Result<()> {
+    let mut features = json::JsonValue::new_array();
+    let mut line = json::JsonValue::new_array();
+    for p in points {
+        let _ = line.push(json::array![p.lon, p.lat]);
+    }
+    let _ = features.push(json::object! {
+        type: "Feature",
+        geometry: json::object! { type: "LineString", coordinates: line },
+        properties: json::object! {
+            name: name,
+            start: points.first().and_then(|p| p.time).map(iso_time),
+            end: points.last().and_then(|p| p.time).map(iso_time),
+        },
+    });
+    for p in points {
+        let _ = features.push(json::object! {
+            type: "Feature",
+            geometry: json::object! { type: "Point", coordinates: json::array![p.lon, p.lat] },
+            properties: json::object! {
+                time: p.time.map(iso_time),
+                fix_quality: p.data.fix_quality,
+                sats: p.data.sats,
+                hdop: p.data.hdop,
+                sog_kts: p.data.sog_kts,
+                cog_t: p.data.cog_t,
+                stw_kts: p.data.stw_kts,
+                depth_m: p.data.depth_m,
+                awa: p.data.awa,
+                aws_kts: p.data.aws_kts,
+                heading_t: p.data.heading_t,
+                twa: p.data.twa,
+                tws_kts: p.data.tws_kts,
+                twd_t: p.data.twd_t,
+                water_temp_c: p.data.water_temp_c,
+                log_nm: p.data.log_nm,
+                trip_nm: p.data.trip_nm,
+                rudder: p.data.rudder,
+                heel: p.data.heel,
+                pitch: p.data.pitch,
+                pressure_hpa: p.data.pressure_hpa,
+            },
+        });
+    }
+    let collection = json::object! { type: "FeatureCollection", features: features };
+    writeln!(out, "{}", collection.dump())
+}
@@ -0,0 +1,69 @@
+use super::{iso_time, xml_escape, TrackPoint};
+use std::io::{self, Write};
+
+// GPX 1.1. Speed, course and depth go in Garmin's TrackPointExtension, which
+// most mapping software understands, and the wind goes in our own namespace.
+pub fn write(out: &mut dyn Write, points: &[TrackPoint], name: &str) -> io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<gpx version="1.1" creator="nmea_player" xmlns="http://www.topografix.com/GPX/1/1" xmlns:gpxtpx="http://www.garmin.com/xmlschemas/TrackPointExtension/v2" xmlns:nmea="https://github.com/tonymattheys/rust_curses_nmea_player">"#
+    )?;
+    writeln!(out, "  <trk>")?;
+    writeln!(out, "    <name>{}</name>", xml_escape(name))?;
+    writeln!(out, "    <trkseg>")?;
+    for p in points {
+        writeln!(out, r#"      <trkpt lat="{:.7}" lon="{:.7}">"#, p.lat, p.lon)?;
+        if let Some(t) = p.time {
+            writeln!(out, "        <time>{}</time>", iso_time(t))?;
+        }
+        if let Some(fix) = p.data.fix_quality.and_then(gpx_fix) {
+            writeln!(out, "        <fix>{}</fix>", fix)?;
+        }
+        if let Some(sats) = p.data.sats {
+            writeln!(out, "        <sat>{}</sat>", sats)?;
+        }
+        if let Some(hdop) = p.data.hdop {
+            writeln!(out, "        <hdop>{}</hdop>", hdop)?;
+        }
+        let has_tpx = p.data.depth_m.is_some() || p.data.sog_kts.is_some() || p.data.cog_t.is_some();
+        if has_tpx || p.data.awa.is_some() {
+            writeln!(out, "        <extensions>")?;
+            if has_tpx {
+                writeln!(out, "          <gpxtpx:TrackPointExtension>")?;
+                if let Some(depth) = p.data.depth_m {
+                    writeln!(out, "            <gpxtpx:depth>{:.2}</gpxtpx:depth>", depth)?;
+                }
+                // GPX wants metres per second
+                if let Some(sog) = p.data.sog_kts {
+                    writeln!(out, "            <gpxtpx:speed>{:.3}</gpxtpx:speed>", sog * 1852.0 / 3600.0)?;
+                }
+                if let Some(cog) = p.data.cog_t {
+                    writeln!(out, "            <gpxtpx:course>{:.1}</gpxtpx:course>", cog)?;
+                }
+                writeln!(out, "          </gpxtpx:TrackPointExtension>")?;
+            }
+            if let (Some(awa), Some(aws)) = (p.data.awa, p.data.aws_kts) {
+                writeln!(out, "          <nmea:awa>{:.1}</nmea:awa>", awa)?;
+                writeln!(out, "          <nmea:aws>{:.1}</nmea:aws>", aws)?;
+            }
+            writeln!(out, "        </extensions>")?;
+        }
+        writeln!(out, "      </trkpt>")?;
+    }
+    writeln!(out, "    </trkseg>")?;
+    writeln!(out, "  </trk>")?;
+    writeln!(out, "</gpx>")?;
+    Ok(())
+}
+
+// GGA fix quality as one of the GPX fix types
+fn gpx_fix(quality: u32) -> Option<&'static str> {
+    match quality {
+        0 => Some("none"),
+        1 => Some("3d"),
+        2 => Some("dgps"),
+        3 => Some("pps"),
+        _ => None,
+    }
+}
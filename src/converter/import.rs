@@ -0,0 +1,293 @@
+use super::{TrackFormat, TrackPoint, Waypoint};
+use crate::udp_broadcaster::instruments::Readings;
+use chrono::{DateTime, NaiveDateTime};
+use std::io;
+use std::str::FromStr;
+
+// Reads a track that came from somewhere else (a phone app, Google Earth, a
+// spreadsheet) back into track points. All we need out of it is the time and
+// position of every point. This is nowhere near a full XML parser, but GPX and
+// KML files are regular enough that picking out the elements we want works.
+pub fn read(text: &str, format: TrackFormat) -> io::Result<Vec<TrackPoint>> {
+    let points = match format {
+        TrackFormat::Gpx => gpx(text),
+        TrackFormat::Kml => kml(text),
+        TrackFormat::Csv => csv(text)?,
+        _ => return Err(io::Error::other("only GPX, KML and CSV tracks can be read")),
+    };
+    Ok(points)
+}
+
+// <trkpt lat="49.630848" lon="-124.023973"><time>2021-02-22T02:06:59Z</time></trkpt>
+fn gpx(text: &str) -> Vec<TrackPoint> {
+    let mut points = vec![];
+    for chunk in text.split("<trkpt").skip(1) {
+        let head = chunk.split('>').next().unwrap_or("");
+        let body = chunk.split("</trkpt>").next().unwrap_or("");
+        let lat = attribute(head, "lat").and_then(|v| f64::from_str(v).ok());
+        let lon = attribute(head, "lon").and_then(|v| f64::from_str(v).ok());
+        if let (Some(lat), Some(lon)) = (lat, lon) {
+            let time = elements(body, "time").first().and_then(|t| parse_time(t));
+            points.push(point(time, lat, lon));
+        }
+    }
+    points
+}
+
+// A gx:Track has all of the <when>s and then all of the <gx:coord>s ("lon lat alt").
+// Failing that, take Placemarks that each have a <when> and a Point.
+fn kml(text: &str) -> Vec<TrackPoint> {
+    let whens = elements(text, "when");
+    let mut coords: Vec<(f64, f64)> = elements(text, "gx:coord")
+        .iter()
+        .filter_map(|c| lon_lat(c.split_whitespace()))
+        .collect();
+    if coords.is_empty() {
+        coords = elements(text, "coordinates")
+            .iter()
+            .filter_map(|c| lon_lat(c.split_whitespace().next().unwrap_or("").split(',')))
+            .collect();
+    }
+    whens
+        .iter()
+        .zip(coords)
+        .map(|(when, (lon, lat))| point(parse_time(when), lat, lon))
+        .collect()
+}
+
+// Any CSV with a header row naming the time, latitude and longitude columns,
+// which includes the ones we write ourselves.
+fn csv(text: &str) -> io::Result<Vec<TrackPoint>> {
+    let mut lines = text.lines();
+    let header: Vec<String> = lines
+        .next()
+        .unwrap_or("")
+        .split(',')
+        .map(|h| h.trim().trim_matches('"').to_ascii_lowercase())
+        .collect();
+    let column = |names: &[&str]| header.iter().position(|h| names.contains(&h.as_str()));
+    let (time, lat, lon) = match (
+        column(&["time", "timestamp", "datetime", "utc"]),
+        column(&["lat", "latitude"]),
+        column(&["lon", "lng", "long", "longitude"]),
+    ) {
+        (Some(time), Some(lat), Some(lon)) => (time, lat, lon),
+        _ => return Err(io::Error::other("the CSV needs time, lat and lon columns")),
+    };
+    let mut points = vec![];
+    for line in lines {
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim().trim_matches('"')).collect();
+        let value = |i: usize| fields.get(i).and_then(|f| f64::from_str(f).ok());
+        if let (Some(lat), Some(lon)) = (value(lat), value(lon)) {
+            points.push(point(fields.get(time).and_then(|t| parse_time(t)), lat, lon));
+        }
+    }
+    Ok(points)
+}
+
+// Waypoints without a name are numbered, which is what a plotter would call them
+pub fn read_route(text: &str, format: TrackFormat) -> io::Result<Vec<Waypoint>> {
+    let mut points = match format {
+        TrackFormat::Gpx => route_gpx(text),
+        TrackFormat::Csv => route_csv(text)?,
+        _ => return Err(io::Error::other("only GPX and CSV routes can be read")),
+    };
+    for (i, p) in points.iter_mut().enumerate() {
+        // Commas and stars would break the sentences the name goes into
+        p.name = p.name.replace([',', '*', '$', '!'], " ").split_whitespace().collect::<Vec<_>>().join(" ");
+        if p.name.is_empty() {
+            p.name = format!("{:03}", i + 1);
+        }
+    }
+    Ok(points)
+}
+
+// <rtept lat="49.630848" lon="-124.023973"><name>GIBSONS</name></rtept>
+fn route_gpx(text: &str) -> Vec<Waypoint> {
+    let tag = if text.contains("<rtept") { "rtept" } else { "wpt" };
+    let mut points = vec![];
+    for chunk in text.split(&format!("<{}", tag)).skip(1) {
+        let head = chunk.split('>').next().unwrap_or("");
+        let body = chunk.split(&format!("</{}>", tag)).next().unwrap_or("");
+        let lat = attribute(head, "lat").and_then(|v| f64::from_str(v).ok());
+        let lon = attribute(head, "lon").and_then(|v| f64::from_str(v).ok());
+        if let (Some(lat), Some(lon)) = (lat, lon) {
+            let name = elements(body, "name").first().map_or(String::new(), |n| n.to_string());
+            points.push(Waypoint { name, lat, lon });
+        }
+    }
+    points
+}
+
+fn route_csv(text: &str) -> io::Result<Vec<Waypoint>> {
+    let mut lines = text.lines();
+    let header: Vec<String> = lines
+        .next()
+        .unwrap_or("")
+        .split(',')
+        .map(|h| h.trim().trim_matches('"').to_ascii_lowercase())
+        .collect();
+    let column = |names: &[&str]| header.iter().position(|h| names.contains(&h.as_str()));
+    let (lat, lon) = match (column(&["lat", "latitude"]), column(&["lon", "lng", "long", "longitude"])) {
+        (Some(lat), Some(lon)) => (lat, lon),
+        _ => return Err(io::Error::other("the CSV needs lat and lon columns")),
+    };
+    let name = column(&["name", "waypoint", "id"]);
+    let mut points = vec![];
+    for line in lines {
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim().trim_matches('"')).collect();
+        let value = |i: usize| fields.get(i).and_then(|f| f64::from_str(f).ok());
+        if let (Some(lat), Some(lon)) = (value(lat), value(lon)) {
+            let name = name.and_then(|i| fields.get(i)).map_or(String::new(), |n| n.to_string());
+            points.push(Waypoint { name, lat, lon });
+        }
+    }
+    Ok(points)
+}
+
+fn point(time: Option<NaiveDateTime>, lat: f64, lon: f64) -> TrackPoint {
+    TrackPoint {
+        time,
+        lat,
+        lon,
+        data: Readings::default(),
+    }
+}
+
+fn lon_lat<'a>(mut parts: impl Iterator<Item = &'a str>) -> Option<(f64, f64)> {
+    let lon = f64::from_str(parts.next()?).ok()?;
+    let lat = f64::from_str(parts.next()?).ok()?;
+    Some((lon, lat))
+}
+
+// The value of name="..." (or name='...') in the attributes of a tag
+fn attribute<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!("{}=", name);
+    let mut from = 0;
+    while let Some(i) = head[from..].find(&pattern) {
+        let at = from + i;
+        from = at + pattern.len();
+        // Make sure we found lat= and not something like xlat=
+        if at > 0 && !head[..at].ends_with(char::is_whitespace) {
+            continue;
+        }
+        let rest = &head[from..];
+        return match rest.chars().next()? {
+            quote @ ('"' | '\'') => rest[quote.len_utf8()..].split(quote).next(),
+            _ => None,
+        };
+    }
+    None
+}
+
+// The text of every <name>...</name> element, in order
+fn elements<'a>(text: &'a str, name: &str) -> Vec<&'a str> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    text.split(&open)
+        .skip(1)
+        .filter_map(|chunk| chunk.split(&close).next())
+        .map(|s| s.trim())
+        .collect()
+}
+
+// ISO 8601 / RFC 3339 times as written by pretty much everything, with or
+// without the time zone (which we take to be UTC when it's missing).
+pub fn parse_time(s: &str) -> Option<NaiveDateTime> {
+    let s = s.trim();
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Some(t.naive_utc());
+    }
+    let s = s.trim_end_matches('Z');
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f"))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attributes_in_either_quotes() {
+        let head = r#"trkpt lat="50.76" lon='-1.29' xlon="9""#;
+        assert_eq!(attribute(head, "lat"), Some("50.76"));
+        assert_eq!(attribute(head, "lon"), Some("-1.29"));
+        assert_eq!(attribute(head, "ele"), None);
+    }
+
+    #[test]
+    fn attributes_without_proper_quotes() {
+        assert_eq!(attribute("trkpt lat=“50.76” lon=\"1\"", "lat"), None);
+        assert_eq!(attribute("trkpt lat=é lon=\"1\"", "lat"), None);
+        assert_eq!(attribute("trkpt lat=50.76", "lat"), None);
+        assert_eq!(attribute("trkpt lat=", "lat"), None);
+    }
+
+    fn at(h: u32, m: u32, s: u32) -> Option<NaiveDateTime> {
+        chrono::NaiveDate::from_ymd_opt(2021, 2, 22).unwrap().and_hms_opt(h, m, s)
+    }
+
+    fn positions(points: &[TrackPoint]) -> Vec<(Option<NaiveDateTime>, f64, f64)> {
+        points.iter().map(|p| (p.time, p.lat, p.lon)).collect()
+    }
+
+    #[test]
+    fn times_with_and_without_a_zone() {
+        assert_eq!(parse_time("2021-02-22T12:00:01Z"), at(12, 0, 1));
+        assert_eq!(parse_time("2021-02-22T05:00:01-07:00"), at(12, 0, 1));
+        assert_eq!(parse_time(" 2021-02-22 12:00:01 "), at(12, 0, 1));
+        assert_eq!(parse_time("2021-02-22T12:00:01.5").map(|t| t.and_utc().timestamp_subsec_millis()), Some(500));
+        assert_eq!(parse_time("yesterday"), None);
+    }
+
+    #[test]
+    fn gpx_track_points() {
+        let text = r#"<gpx><trk><trkseg>
+            <trkpt lat="49.5" lon="-124.0"><ele>3</ele><time>2021-02-22T12:00:00Z</time></trkpt>
+            <trkpt lon="-124.1" lat="49.6"></trkpt>
+            <trkpt lat="north" lon="-124.2"><time>2021-02-22T12:00:02Z</time></trkpt>
+        </trkseg></trk></gpx>"#;
+        let points = read(text, TrackFormat::Gpx).unwrap();
+        assert_eq!(positions(&points), vec![(at(12, 0, 0), 49.5, -124.0), (None, 49.6, -124.1)]);
+    }
+
+    #[test]
+    fn kml_tracks_and_placemarks() {
+        let track = "<gx:Track><when>2021-02-22T12:00:00Z</when><when>2021-02-22T12:00:01Z</when>\
+            <gx:coord>-124.0 49.5 0</gx:coord><gx:coord>-124.1 49.6 0</gx:coord></gx:Track>";
+        let points = read(track, TrackFormat::Kml).unwrap();
+        assert_eq!(positions(&points), vec![(at(12, 0, 0), 49.5, -124.0), (at(12, 0, 1), 49.6, -124.1)]);
+
+        let placemarks = "<Placemark><TimeStamp><when>2021-02-22T12:00:00Z</when></TimeStamp>\
+            <Point><coordinates>-124.0,49.5,0</coordinates></Point></Placemark>";
+        let points = read(placemarks, TrackFormat::Kml).unwrap();
+        assert_eq!(positions(&points), vec![(at(12, 0, 0), 49.5, -124.0)]);
+    }
+
+    #[test]
+    fn csv_columns_by_name() {
+        let text = "Latitude,\"Longitude\",Speed,Timestamp\n49.5,-124.0,5,2021-02-22 12:00:00\n,,,\n49.6,-124.1,5,\n";
+        let points = read(text, TrackFormat::Csv).unwrap();
+        assert_eq!(positions(&points), vec![(at(12, 0, 0), 49.5, -124.0), (None, 49.6, -124.1)]);
+        assert!(read("when,lat,lon\n", TrackFormat::Csv).is_err());
+        assert!(read("", TrackFormat::Geojson).is_err());
+    }
+
+    #[test]
+    fn route_names_are_cleaned_up_or_numbered() {
+        let text = r#"<gpx><wpt lat="49.5" lon="-124.0"><name>START</name></wpt>
+            <rte><rtept lat="49.6" lon="-124.1"><name>Gibsons, *North*</name></rtept>
+            <rtept lat="49.7" lon="-124.2"></rtept></rte></gpx>"#;
+        let route = read_route(text, TrackFormat::Gpx).unwrap();
+        // The rtepts win over the wpt
+        let names: Vec<&str> = route.iter().map(|w| w.name.as_str()).collect();
+        assert_eq!(names, vec!["Gibsons North", "002"]);
+        assert_eq!((route[1].lat, route[1].lon), (49.7, -124.2));
+
+        let route = read_route("lon,lat\n-124.0,49.5\n", TrackFormat::Csv).unwrap();
+        assert_eq!(route[0].name, "001");
+        assert!(read_route("name,lat\nA,49.5\n", TrackFormat::Csv).is_err());
+        assert!(read_route("", TrackFormat::Kml).is_err());
+    }
+}
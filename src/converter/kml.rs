@@ -0,0 +1,72 @@
+use super::{iso_time, xml_escape, TrackPoint};
+use std::io::{self, Write};
+
+// KML with a gx:Track, so Google Earth can play the track back against its
+// time slider. The instrument values ride along as ExtendedData arrays, one
+// value per point, which Google Earth shows in the elevation profile.
+pub fn write(out: &mut dyn Write, points: &[TrackPoint], name: &str) -> io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2">"#
+    )?;
+    writeln!(out, "<Document>")?;
+    writeln!(out, "  <name>{}</name>", xml_escape(name))?;
+    writeln!(out, r#"  <Schema id="nmea">"#)?;
+    for (field, label) in FIELDS {
+        writeln!(
+            out,
+            r#"    <gx:SimpleArrayField name="{}" type="float"><displayName>{}</displayName></gx:SimpleArrayField>"#,
+            field, label
+        )?;
+    }
+    writeln!(out, "  </Schema>")?;
+    writeln!(out, "  <Placemark>")?;
+    writeln!(out, "    <name>{}</name>", xml_escape(name))?;
+    writeln!(out, "    <gx:Track>")?;
+    for p in points {
+        match p.time {
+            Some(t) => writeln!(out, "      <when>{}</when>", iso_time(t))?,
+            None => writeln!(out, "      <when/>")?,
+        }
+    }
+    for p in points {
+        writeln!(out, "      <gx:coord>{:.7} {:.7} 0</gx:coord>", p.lon, p.lat)?;
+    }
+    writeln!(out, "      <ExtendedData>")?;
+    writeln!(out, r##"        <SchemaData schemaUrl="#nmea">"##)?;
+    for (field, _) in FIELDS {
+        writeln!(out, r#"          <gx:SimpleArrayData name="{}">"#, field)?;
+        for p in points {
+            let value = match field {
+                "sog" => p.data.sog_kts,
+                "cog" => p.data.cog_t,
+                "hdop" => p.data.hdop,
+                "depth" => p.data.depth_m,
+                "awa" => p.data.awa,
+                _ => p.data.aws_kts,
+            };
+            match value {
+                Some(v) => writeln!(out, "            <gx:value>{}</gx:value>", v)?,
+                None => writeln!(out, "            <gx:value/>")?,
+            }
+        }
+        writeln!(out, "          </gx:SimpleArrayData>")?;
+    }
+    writeln!(out, "        </SchemaData>")?;
+    writeln!(out, "      </ExtendedData>")?;
+    writeln!(out, "    </gx:Track>")?;
+    writeln!(out, "  </Placemark>")?;
+    writeln!(out, "</Document>")?;
+    writeln!(out, "</kml>")?;
+    Ok(())
+}
+
+const FIELDS: [(&str, &str); 6] = [
+    ("sog", "SOG (kts)"),
+    ("cog", "COG (°T)"),
+    ("hdop", "HDOP"),
+    ("depth", "Depth (m)"),
+    ("awa", "Apparent wind angle (°)"),
+    ("aws", "Apparent wind speed (kts)"),
+];
@@ -0,0 +1,66 @@
+use super::TrackPoint;
+use crate::geo;
+use crate::nmea::build;
+use chrono::{Duration, NaiveDateTime, Timelike};
+use std::io::{self, Write};
+
+// Makes up a NMEA stream from a track, for when all we have of a boat is a GPX
+// from somebody's phone. ZDA goes out once a second and GGA, RMC and VTG at
+// the chosen rate, with the position worked out along the straight line
+// between the track points either side and the course and speed from that
+// same leg of the track.
+pub fn synthesize(points: &[TrackPoint], rate_hz: f64) -> io::Result<Vec<String>> {
+    if rate_hz <= 0.0 {
+        return Err(io::Error::other("the rate has to be more than zero"));
+    }
+    let mut track: Vec<(NaiveDateTime, f64, f64)> = points
+        .iter()
+        .filter_map(|p| p.time.map(|t| (t, p.lat, p.lon)))
+        .collect();
+    track.sort_by_key(|(t, _, _)| *t);
+    track.dedup_by_key(|(t, _, _)| *t);
+    if track.len() < 2 {
+        return Err(io::Error::other("the track needs at least two points with times on them"));
+    }
+    let (first, last) = (track[0].0, track[track.len() - 1].0);
+    let fix_step = Duration::milliseconds(((1000.0 / rate_hz).round() as i64).max(10));
+    let second = Duration::seconds(1);
+    // Start on the first whole second of the track
+    let start = if first.nanosecond() == 0 {
+        first
+    } else {
+        first.with_nanosecond(0).unwrap() + second
+    };
+    let mut lines = vec![];
+    let (mut next_zda, mut next_fix) = (start, start);
+    let mut leg = 0;
+    while next_zda <= last || next_fix <= last {
+        if next_zda <= next_fix {
+            lines.push(build::zda("GP", next_zda));
+            next_zda += second;
+            continue;
+        }
+        let t = next_fix;
+        next_fix += fix_step;
+        while leg + 2 < track.len() && track[leg + 1].0 <= t {
+            leg += 1;
+        }
+        let ((t1, lat1, lon1), (t2, lat2, lon2)) = (track[leg], track[leg + 1]);
+        let secs = (t2 - t1).num_milliseconds() as f64 / 1000.0;
+        let f = ((t - t1).num_milliseconds() as f64 / 1000.0 / secs).clamp(0.0, 1.0);
+        let (lat, lon) = (lat1 + (lat2 - lat1) * f, lon1 + (lon2 - lon1) * f);
+        let sog = geo::distance_m(lat1, lon1, lat2, lon2) / secs * 3600.0 / 1852.0;
+        let cog = geo::bearing(lat1, lon1, lat2, lon2);
+        lines.push(build::gga("GP", t, lat, lon));
+        lines.push(build::rmc("GP", t, lat, lon, sog, cog));
+        lines.push(build::vtg("GP", sog, cog));
+    }
+    Ok(lines)
+}
+
+pub fn write(out: &mut dyn Write, points: &[TrackPoint], rate_hz: f64) -> io::Result<()> {
+    for line in synthesize(points, rate_hz)? {
+        write!(out, "{}\r\n", line)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,106 @@
+// A little bit of navigation maths on a spherical earth, which is plenty good
+// enough over the distances a boat covers between two fixes.
+
+use std::str::FromStr;
+
+const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+// Great circle distance in metres
+pub fn distance_m(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (p1, p2) = (lat1.to_radians(), lat2.to_radians());
+    let dp = p2 - p1;
+    let dl = (lon2 - lon1).to_radians();
+    let a = (dp / 2.0).sin().powi(2) + p1.cos() * p2.cos() * (dl / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+// Initial true bearing (0 - 360) to get from the first point to the second
+pub fn bearing(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (p1, p2) = (lat1.to_radians(), lat2.to_radians());
+    let dl = (lon2 - lon1).to_radians();
+    let y = dl.sin() * p2.cos();
+    let x = p1.cos() * p2.sin() - p1.sin() * p2.cos() * dl.cos();
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
+
+// Where you end up going this far on this bearing
+pub fn destination(lat: f64, lon: f64, bearing: f64, distance_m: f64) -> (f64, f64) {
+    let d = distance_m / EARTH_RADIUS_M;
+    let b = bearing.to_radians();
+    let p1 = lat.to_radians();
+    let l1 = lon.to_radians();
+    let p2 = (p1.sin() * d.cos() + p1.cos() * d.sin() * b.cos()).asin();
+    let l2 = l1 + (b.sin() * d.sin() * p1.cos()).atan2(d.cos() - p1.sin() * p2.sin());
+    (p2.to_degrees(), (l2.to_degrees() + 540.0).rem_euclid(360.0) - 180.0)
+}
+
+// Back onto the globe after adding to a position: going over a pole comes back
+// down the other side, half way round the world, and longitude wraps at 180
+pub fn normalize(lat: f64, lon: f64) -> (f64, f64) {
+    let mut lat = (lat + 90.0).rem_euclid(360.0) - 90.0;
+    let mut lon = lon;
+    if lat > 90.0 {
+        lat = 180.0 - lat;
+        lon += 180.0;
+    }
+    (lat, (lon + 180.0).rem_euclid(360.0) - 180.0)
+}
+
+// A boat going somewhere: where it is, and its course (degrees true) and
+// speed (knots) over the ground
+#[derive(Clone, Copy)]
+pub struct Motion {
+    pub lat: f64,
+    pub lon: f64,
+    pub cog: f64,
+    pub sog: f64,
+}
+
+// Closest point of approach in nautical miles and the time to it in minutes,
+// assuming both boats hold their course and speed. Worked out on a flat sea
+// around our own position, which is fine at the ranges where CPA matters. The
+// time is negative once the closest point has been and gone.
+pub fn cpa(own: Motion, other: Motion) -> (f64, f64) {
+    let range = distance_m(own.lat, own.lon, other.lat, other.lon) / 1852.0;
+    let b = bearing(own.lat, own.lon, other.lat, other.lon).to_radians();
+    let (x, y) = (range * b.sin(), range * b.cos());
+    let velocity = |m: Motion| (m.sog * m.cog.to_radians().sin(), m.sog * m.cog.to_radians().cos());
+    let ((ox, oy), (tx, ty)) = (velocity(own), velocity(other));
+    let (dx, dy) = (tx - ox, ty - oy);
+    let closing = dx * dx + dy * dy;
+    if closing < 1e-9 {
+        // Same course and speed, so the range never changes
+        return (range, 0.0);
+    }
+    let hours = -(x * dx + y * dy) / closing;
+    ((x + dx * hours).hypot(y + dy * hours), hours * 60.0)
+}
+
+// A "lat,lon" pair in decimal degrees as given on the command line
+#[derive(Clone, Copy)]
+pub struct LatLon {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl FromStr for LatLon {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LatLon, String> {
+        let (lat, lon) = s.split_once(',').ok_or_else(|| format!("'{}' should be two numbers like 49.5,-123.25", s))?;
+        let number = |x: &str| f64::from_str(x.trim()).map_err(|_| format!("'{}' isn't a number", x));
+        Ok(LatLon {
+            lat: number(lat)?,
+            lon: number(lon)?,
+        })
+    }
+}
+
+// A LatLon that has to be a real place, for clap's value_parser
+pub fn position(s: &str) -> Result<LatLon, String> {
+    let p: LatLon = s.parse()?;
+    if !(-90.0..=90.0).contains(&p.lat) || !(-180.0..=180.0).contains(&p.lon) {
+        return Err(format!("'{}' isn't on the globe, latitude goes from -90 to 90 and longitude from -180 to 180", s));
+    }
+    Ok(p)
+}
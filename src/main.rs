@@ -1,79 +1,197 @@
-use clap::Parser;
 use pnet::datalink::{self};
 use std::fs::File;
-use std::io::{self};
-use std::path::PathBuf;
+use std::io::{self, BufRead, Write};
 use std::process::exit;
 
+mod ais;
+mod anonymize;
+mod cli;
+mod config;
+mod converter;
+mod geo;
+mod merge;
+mod nmea;
 mod udp_broadcaster;
 mod scanner;
+mod simulator;
+mod trim;
 
-
-#[derive(Parser)]
-#[command(author, version)] // Read from `Cargo.toml`
-#[command(
-    about = "A Rust program to read a text file containing NMEA sentences and resend them across the network."
-)]
-#[command(
-    long_about = "This program will read a file specified by the user and perform various operations
-using the contents of the file as input. The most common way to use this program is
-to read in a NMEA0183 file and resend the NMEA sentences out onto the network using
-UDP broadcast on port 10110. This will appear to be a Comar system to Navionics and
-other navigation systems that listend for UDP broadcasts on the network.
-\n
-The program can also scan the given file and produce a report showing summary information
-about the NMEA sentences contained therein. For example, it will report on time stamps
-found in sentences like $GPZDA, which will, in turn allow the user to ask the program to
-start broadcasting over the network starting at a certain time in the file. This is very 
-useful when analyzing sailboat races, for example, where there could be a lot of unwanted 
-NMEA traffic before and after the race itself."
-)]
-struct Cli {
-    #[arg(short, long, default_value_t = false, value_name = "true | false (default is false)")]
-    scan: bool,
-
-    #[arg(short, long, default_value = "00:00:00", value_name = "hh:mm:ss[.ss]")]
-    time: String,
-
-    #[arg(short, long="udp", default_value_t = 10110, value_name = "UDP_PORT")]
-    udp_port: u16,
-
-    #[arg(short, long="if", default_value = "eth0", value_name = "en0, eth0 ... etc")]
-    if_name: String,
-
-    #[arg(short, long="file", value_name = "NMEA_FILE")]
-    file_name: PathBuf,
-}
+use cli::{Command, OutputArgs};
+use udp_broadcaster::outputs::Outputs;
 
 fn main() -> io::Result<()> {
-    // Parse command-line arguments to get the network interface name and file name
-    let cli = Cli::parse();
+    // Parse command-line arguments (and the config file) to work out what we have been asked to do
+    let cli = config::parse();
 
-    // Open the file
-    let file = File::open(cli.file_name)?;
-	// If he asked for the file to be scanned, then just pass the file handle to the scanner
-    if cli.scan {
-    	if scanner::scan_and_report(file) {
-    		exit(0)
-    	} else {
-    		exit(2)
-    	}
+    match cli.into_command() {
+        // If he asked for the file to be scanned, then just pass the file handle to the scanner.
+        // Exit code is 0 for a good scan, 3 if there were no time stamps in the file and 2
+        // if the file couldn't be read at all.
+        Command::Scan(args) => {
+            let file = File::open(args.file_name)?;
+            match scanner::scan(file, &args.options) {
+                Ok(report) => {
+                    print!("{}", report.render(args.format));
+                    if report.has_times() {
+                        exit(0)
+                    } else {
+                        exit(3)
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error reading file: {}", e);
+                    exit(2)
+                }
+            }
+        }
+        Command::Play(args) => {
+            let mut outputs = open_outputs(&args.outputs)?;
+            // A track from somewhere else gets turned into NMEA first and played just the same
+            if converter::is_track_file(&args.file_name) {
+                let points = converter::load_track(&args.file_name, None)?;
+                let lines = converter::synthesize(&points, args.rate)?;
+                return udp_broadcaster::send_lines(lines.into_iter().map(Ok), &mut outputs, args.time, 1.0, &args.rewrite);
+            }
+            let file = File::open(&args.file_name)?;
+            // Read the file line by line and send each line over UDP to the specified interface
+            udp_broadcaster::send_lines(io::BufReader::new(file).lines(), &mut outputs, args.time, 1.0, &args.rewrite)
+        }
+        Command::Simulate(args) => {
+            let sim = simulator::Simulation::new(&args.options)?;
+            let mut outputs = open_outputs(&args.outputs)?;
+            udp_broadcaster::send_lines(sim.map(Ok), &mut outputs, None, args.options.speedup, &args.rewrite)
+        }
+        // Live data goes straight through to the outputs
+        Command::Record(args) => {
+            let mut outputs = open_outputs(&args.outputs)?;
+            if let Some(listen_port) = args.listen_port {
+                // Listening on the port we broadcast to would just feed our own output back to us
+                if listen_port == args.outputs.udp_port {
+                    return Err(io::Error::other("--listen and --udp must use different ports"));
+                }
+                let lines = udp_broadcaster::listener::udp_lines(listen_port)?;
+                udp_broadcaster::relay_lines(lines, &mut outputs)
+            } else {
+                let device = args.serial.unwrap_or_default();
+                let lines = udp_broadcaster::listener::serial_lines(&device, args.baud)?;
+                udp_broadcaster::relay_lines(lines, &mut outputs)
+            }
+        }
+        Command::Convert(args) => {
+            let format = match args.to.or_else(|| args.output.as_deref().and_then(converter::format_from_path)) {
+                Some(format) => format,
+                None => return Err(io::Error::other("Can't tell what to convert to, use --to")),
+            };
+            let points = converter::load_track(&args.file_name, args.resample)?;
+            let name = args.file_name.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            let mut out: Box<dyn Write> = match &args.output {
+                Some(path) => Box::new(io::BufWriter::new(File::create(path)?)),
+                None => Box::new(io::stdout().lock()),
+            };
+            converter::write_track(&mut out, &points, format, &name, args.rate)?;
+            out.flush()?;
+            eprintln!("{} track points written", points.len());
+            Ok(())
+        }
+        // Cut the race out of a day's log
+        Command::Trim(args) => {
+            let file = File::open(&args.file_name)?;
+            let mut out = io::BufWriter::new(File::create(&args.output)?);
+            let summary = trim::trim(file, &mut out, args.from, args.until, args.prime)?;
+            out.flush()?;
+            eprintln!(
+                "{} of {} lines written to {}{}",
+                summary.lines_written,
+                summary.lines_read,
+                args.output.display(),
+                if args.prime { format!(" after {} priming sentences", summary.primed) } else { String::new() }
+            );
+            Ok(())
+        }
+        // Put the logs from more than one logger back together
+        Command::Merge(args) => {
+            if args.file_names.len() < 2 {
+                return Err(io::Error::other("give --file at least twice to have something to merge"));
+            }
+            if !args.offset.is_empty() && args.offset.len() != args.file_names.len() {
+                return Err(io::Error::other(format!(
+                    "give one --offset for each --file ({} offsets for {} files)",
+                    args.offset.len(),
+                    args.file_names.len()
+                )));
+            }
+            let mut files = vec![];
+            for name in &args.file_names {
+                files.push(merge::read_lines(File::open(name)?)?);
+            }
+            let offsets: Vec<f64> = if args.align {
+                let found = merge::align(&files);
+                for (name, offset) in args.file_names.iter().zip(&found).skip(1) {
+                    match offset {
+                        Some(secs) => eprintln!("Moving {} by {:+.2} s to line up with {}", name.display(), secs, args.file_names[0].display()),
+                        None => eprintln!("{} has no GGA or RMC fix to line up with {}, leaving it as it is", name.display(), args.file_names[0].display()),
+                    }
+                }
+                found.into_iter().map(|o| o.unwrap_or(0.0)).collect()
+            } else {
+                args.offset
+            };
+            let mut out = io::BufWriter::new(File::create(&args.output)?);
+            let summary = merge::merge(files, &offsets, &mut out)?;
+            out.flush()?;
+            eprintln!(
+                "{} lines written to {} ({} duplicates left out)",
+                summary.lines_written,
+                args.output.display(),
+                summary.duplicates
+            );
+            Ok(())
+        }
+        Command::Anonymize(args) => {
+            let file = File::open(&args.file_name)?;
+            let mut out = io::BufWriter::new(File::create(&args.output)?);
+            let summary = anonymize::anonymize(file, &mut out, &args.options)?;
+            out.flush()?;
+            eprintln!(
+                "{} lines written to {}, {} positions left out, {} AIS messages changed",
+                summary.lines_written,
+                args.output.display(),
+                summary.positions_hidden,
+                summary.ais_messages
+            );
+            if summary.fragments_dropped > 0 {
+                eprintln!("{} AIS fragments without the rest of their message were left out", summary.fragments_dropped);
+            }
+            Ok(())
+        }
+        Command::ListInterfaces => {
+            for iface in datalink::interfaces() {
+                let ips: Vec<String> = iface.ips.iter().map(|ip| ip.to_string()).collect();
+                println!(
+                    "{:<12} {:<5} {}",
+                    iface.name,
+                    if iface.is_up() { "up" } else { "down" },
+                    ips.join(", ")
+                );
+            }
+            Ok(())
+        }
     }
+}
 
-    let start_time = cli.time;
-    let if_name = cli.if_name;
-
-    // Get the network interface with the name that was specified as the first parameter
+// Get the network interface with the name that was specified and set up the
+// UDP broadcast on it, plus any extra destinations and the tee log file.
+fn open_outputs(args: &OutputArgs) -> io::Result<Outputs> {
     let interface = datalink::interfaces()
         .into_iter()
-        .find(|iface| iface.name == if_name)
-        .ok_or_else(|| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                "Interface '".to_owned() + &if_name + "' not found",
-            )
-        })?;
-    // Read the file line by line and send each line over UDP to the specified interface
-    udp_broadcaster::send_lines(file, interface, cli.udp_port, start_time)?;
-    Ok(())
+        .find(|iface| iface.name == args.if_name)
+        .ok_or_else(|| io::Error::other("Interface '".to_owned() + &args.if_name + "' not found"))?;
+    let mut outputs = Outputs::broadcast(&interface, args.udp_port)?;
+    for destination in &args.forward {
+        outputs.forward(*destination);
+    }
+    if let Some(tee) = &args.tee {
+        outputs.tee(tee)?;
+    }
+    Ok(outputs)
 }
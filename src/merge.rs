@@ -0,0 +1,201 @@
+use crate::nmea;
+use chrono::{Duration, NaiveDateTime, NaiveTime};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+
+// Puts two or more logs of the same sail back together into one file, for
+// example the GPS from the nav station and the instruments from a tablet
+// logger. Lines are interleaved by their file times, keeping the lines of each
+// file in the order they were logged, and a sentence that turns up in more than
+// one file (the GPS is often wired into both) only goes out once.
+//
+// When the loggers' clocks don't agree, each file can be moved by an offset in
+// seconds. The time stamps in that file's ZDA, RMC, GGA and GLL sentences are
+// moved along with it, so the merged file has one clock that send_lines can
+// keep up with.
+
+struct Line {
+    time: Option<NaiveDateTime>,
+    text: String,
+}
+
+pub struct MergeSummary {
+    pub lines_written: u64,
+    pub duplicates: u64,
+}
+
+// A sentence is only a duplicate if another file had it this close in time
+const DUPLICATE_WINDOW_MS: i64 = 1000;
+
+pub fn read_lines(file: File) -> io::Result<Vec<(Option<NaiveDateTime>, String)>> {
+    let mut clock = nmea::FileClock::new();
+    let mut lines = vec![];
+    let mut reader = io::BufReader::new(file);
+    let mut raw = vec![];
+    loop {
+        raw.clear();
+        if reader.read_until(b'\n', &mut raw)? == 0 {
+            break;
+        }
+        let line = String::from_utf8_lossy(&raw).trim_end().to_string();
+        if line.is_empty() {
+            continue;
+        }
+        lines.push((clock.update(&line), line));
+    }
+    Ok(lines)
+}
+
+// Work out how far each file's clock is from the first one's from the first
+// GGA or RMC fix in each: those are taken to be the same moment, whatever time
+// the loggers put on them. The fix times are parsed out of the sentences, so it
+// doesn't matter how each logger writes them. Without a date in either file
+// only the time of day is compared, taking the nearer way round midnight.
+pub fn align(files: &[Vec<(Option<NaiveDateTime>, String)>]) -> Vec<Option<f64>> {
+    let first = first_fix(&files[0]);
+    let mut offsets = vec![Some(0.0)];
+    for other in &files[1..] {
+        let offset = match (first, first_fix(other)) {
+            (Some((Some(a), _)), Some((Some(b), _))) => Some((a - b).num_milliseconds()),
+            (Some((_, a)), Some((_, b))) => {
+                let ms = (a - b).num_milliseconds();
+                Some((ms + 43_200_000).rem_euclid(86_400_000) - 43_200_000)
+            }
+            _ => None,
+        };
+        offsets.push(offset.map(|ms| ms as f64 / 1000.0));
+    }
+    offsets
+}
+
+// The time of the first GGA or RMC fix in the file: the full date and time if
+// the file has a date by then, and the time of day from the sentence itself
+fn first_fix(lines: &[(Option<NaiveDateTime>, String)]) -> Option<(Option<NaiveDateTime>, NaiveTime)> {
+    lines.iter().find_map(|(time, line)| {
+        let fields: Vec<&str> = line.split(',').collect();
+        match (nmea::sentence_type(line), fields.get(1)) {
+            (Some("GGA" | "RMC"), Some(field)) => nmea::parse_hms(field).map(|t| (*time, t)),
+            _ => None,
+        }
+    })
+}
+
+pub fn merge(
+    files: Vec<Vec<(Option<NaiveDateTime>, String)>>,
+    offsets: &[f64],
+    out: &mut dyn Write,
+) -> io::Result<MergeSummary> {
+    let mut queues: Vec<std::vec::IntoIter<Line>> = vec![];
+    for (i, lines) in files.into_iter().enumerate() {
+        let offset = Duration::milliseconds((offsets.get(i).copied().unwrap_or(0.0) * 1000.0) as i64);
+        // Lines before the first time stamp in a file go with that first time
+        let first = lines.iter().find_map(|(t, _)| *t);
+        let shifted: Vec<Line> = lines
+            .into_iter()
+            .map(|(time, text)| Line {
+                time: time.or(first).map(|t| t + offset),
+                text: if offset.is_zero() { text } else { nmea::shift_times(&text, offset) },
+            })
+            .collect();
+        queues.push(shifted.into_iter());
+    }
+    let mut heads: Vec<Option<Line>> = queues.iter_mut().map(|q| q.next()).collect();
+    let mut last_sent: HashMap<String, (usize, Option<NaiveDateTime>)> = HashMap::new();
+    let mut summary = MergeSummary {
+        lines_written: 0,
+        duplicates: 0,
+    };
+    // Always take the earliest line at the front of any file. Lines without a
+    // time at all (a file with no time stamps) go first.
+    while let Some(i) = (0..heads.len())
+        .filter(|i| heads[*i].is_some())
+        .min_by_key(|i| heads[*i].as_ref().unwrap().time)
+    {
+        let line = heads[i].take().unwrap();
+        heads[i] = queues[i].next();
+        let duplicate = match last_sent.get(&line.text) {
+            Some((from, Some(then))) if *from != i => match line.time {
+                Some(now) => (now - *then).num_milliseconds().abs() <= DUPLICATE_WINDOW_MS,
+                None => false,
+            },
+            _ => false,
+        };
+        if duplicate {
+            summary.duplicates += 1;
+            continue;
+        }
+        write!(out, "{}\r\n", line.text)?;
+        summary.lines_written += 1;
+        last_sent.insert(line.text, (i, line.time));
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The lines of a file with their file times, as read_lines gives them
+    fn timed(lines: &[&str]) -> Vec<(Option<NaiveDateTime>, String)> {
+        let mut clock = nmea::FileClock::new();
+        lines.iter().map(|l| (clock.update(l), l.to_string())).collect()
+    }
+
+    fn merged(files: &[&[&str]], offsets: &[f64]) -> (Vec<String>, MergeSummary) {
+        let mut out = vec![];
+        let summary = merge(files.iter().map(|f| timed(f)).collect(), offsets, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        (out.split_terminator("\r\n").map(|l| l.to_string()).collect(), summary)
+    }
+
+    #[test]
+    fn aligning_without_dates_goes_the_near_way_round_midnight() {
+        let before = timed(&["$GPGGA,235950,,,,,1"]);
+        let after = timed(&["$SDDPT,5.2,0.0", "$GPRMC,000010,A"]);
+        let none = timed(&["$SDDPT,5.2,0.0"]);
+        assert_eq!(align(&[after.clone(), before.clone(), none]), vec![Some(0.0), Some(20.0), None]);
+        assert_eq!(align(&[before, after]), vec![Some(0.0), Some(-20.0)]);
+    }
+
+    #[test]
+    fn aligning_with_dates_takes_the_whole_difference() {
+        let today = timed(&["$GPZDA,120000,22,02,2021,00,00", "$GPGGA,120000,,,,,1"]);
+        let yesterday = timed(&["$GPZDA,120005,21,02,2021,00,00", "$GPGGA,120005,,,,,1"]);
+        assert_eq!(align(&[today, yesterday]), vec![Some(0.0), Some(86_395.0)]);
+    }
+
+    #[test]
+    fn sentences_in_both_files_go_out_once() {
+        let a = ["$GPZDA,120000,22,02,2021,00,00", "$GPGGA,120000,,,,,1", "$GPGGA,120001,,,,,1", "$IIMWV,1"];
+        let b = ["$GPZDA,120000,22,02,2021,00,00", "$SDDPT,5.2,0.0", "$GPGGA,120001,,,,,1", "$SDDPT,5.2,0.0"];
+        let (out, summary) = merged(&[&a, &b], &[0.0, 0.0]);
+        // The second depth is from the same file, so it isn't a duplicate
+        assert_eq!(out, vec![a[0], a[1], b[1], a[2], a[3], b[3]]);
+        assert_eq!((summary.lines_written, summary.duplicates), (6, 2));
+
+        // Nor is the same wind more than a second later
+        let c = ["$GPZDA,120003,22,02,2021,00,00", "$IIMWV,1"];
+        let (out, summary) = merged(&[&a, &c], &[0.0, 0.0]);
+        assert_eq!(out.iter().filter(|l| *l == "$IIMWV,1").count(), 2);
+        assert_eq!(summary.duplicates, 0);
+    }
+
+    #[test]
+    fn offsets_move_the_lines_and_their_times() {
+        let a = ["$GPZDA,120000,22,02,2021,00,00", "$SDDPT,5.2,0.0", "$GPGGA,120003,,,,,1", "$SDDPT,5.3,0.0"];
+        let b = ["$GPZDA,115958,22,02,2021,00,00", "$GPGGA,115958,,,,,1", "$GPGGA,120000,,,,,1"];
+        let (out, _) = merged(&[&a, &b], &[0.0, 2.0]);
+        assert_eq!(
+            out,
+            vec![
+                "$GPZDA,120000,22,02,2021,00,00",
+                "$SDDPT,5.2,0.0",
+                "$GPGGA,120000,,,,,1",
+                "$GPGGA,120002,,,,,1",
+                "$GPGGA,120003,,,,,1",
+                "$SDDPT,5.3,0.0",
+            ]
+        );
+    }
+}
@@ -0,0 +1,470 @@
+// Small helpers for picking apart NMEA 0183 sentences that are shared by the
+// player, the live relay and the scanner.
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use std::str::FromStr;
+
+pub mod build;
+pub mod positions;
+
+// Returns the three letter sentence type of a "$ttsss,..." sentence, so
+// "$GPGGA,020659.21,..." gives "GGA". Anything else gives None.
+pub fn sentence_type(line: &str) -> Option<&str> {
+    let tag = line.split(',').next()?;
+    if tag.starts_with('$') && tag.len() >= 6 {
+        tag.get(3..6)
+    } else {
+        None
+    }
+}
+
+// The address field of a sentence without its '$' or '!', e.g. "GPGGA", "PGRME"
+// or "AIVDM". Returns None for anything that doesn't look like NMEA at all.
+pub fn tag(line: &str) -> Option<&str> {
+    let first = line.split(',').next()?;
+    let first = first.split('*').next()?;
+    if !(first.starts_with('$') || first.starts_with('!')) {
+        return None;
+    }
+    let tag = &first[1..];
+    if tag.len() >= 4 && tag.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Some(tag)
+    } else {
+        None
+    }
+}
+
+// Does a sentence's address match a pattern like "GPGSV", "*VWR" or "P*"? A
+// '*' matches any number of characters and a '?' any one. Three letters on
+// their own are a sentence type from any talker, so "GSV" is the same as "??GSV".
+pub fn tag_matches(pattern: &str, tag: &str) -> bool {
+    let pattern = pattern.to_ascii_uppercase();
+    let tag = tag.to_ascii_uppercase();
+    if pattern.len() == 3 && !pattern.contains(['*', '?']) {
+        return tag.len() == 5 && !tag.starts_with('P') && tag[2..] == pattern;
+    }
+    glob(pattern.as_bytes(), tag.as_bytes())
+}
+
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => glob(&pattern[1..], text) || (!text.is_empty() && glob(pattern, &text[1..])),
+        (Some(b'?'), Some(_)) => glob(&pattern[1..], &text[1..]),
+        (Some(p), Some(t)) if p == t => glob(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
+
+// Work out the XOR checksum of everything between the '$' (or '!') and the '*'
+pub fn checksum(body: &str) -> u8 {
+    body.bytes().fold(0, |acc, b| acc ^ b)
+}
+
+// Check the "*hh" on the end of a sentence. Returns None when there isn't one,
+// which is allowed for NMEA 0183 (but pretty rare these days).
+pub fn checksum_ok(line: &str) -> Option<bool> {
+    let line = line.trim_end();
+    let (body, sum) = line.get(1..)?.rsplit_once('*')?;
+    let sum = u8::from_str_radix(sum, 16).ok()?;
+    Some(checksum(body) == sum)
+}
+
+// $GPZDA,234626.99,22,02,2021,08,00*6A
+pub fn parse_zda(fields: &[&str]) -> Option<NaiveDateTime> {
+    if fields.len() < 5 {
+        return None;
+    }
+    let y: i32 = FromStr::from_str(fields[4]).ok()?;
+    let m: u32 = FromStr::from_str(fields[3]).ok()?;
+    let d: u32 = FromStr::from_str(fields[2]).ok()?;
+    let t = parse_hms(fields[1])?;
+    Some(NaiveDate::from_ymd_opt(y, m, d)?.and_time(t))
+}
+
+// "hhmmss.ss" as found in ZDA, GGA, RMC, GLL ... The fraction is dropped.
+pub fn parse_hms(field: &str) -> Option<NaiveTime> {
+    let mut hr: u32 = FromStr::from_str(field.get(0..2)?).ok()?;
+    let mut mn: u32 = FromStr::from_str(field.get(2..4)?).ok()?;
+    let mut se: u32 = FromStr::from_str(field.get(4..6)?).ok()?;
+    // Some GPS units will give you "60" for minutes or seconds but chrono hates that
+    // Quick and dirty fix for that...
+    if se >= 60 {
+        se = 0;
+        mn += 1
+    }
+    if mn >= 60 {
+        mn = 0;
+        hr += 1
+    }
+    NaiveTime::from_hms_opt(hr, mn, se)
+}
+
+// "4937.8509","N" -> 49.630848 and "12401.4384","W" -> -124.02397
+pub fn parse_lat_lon(lat: &str, n_s: &str, lon: &str, e_w: &str) -> Option<(f64, f64)> {
+    let x: f64 = FromStr::from_str(lat).ok()?;
+    let mut lat_d = (x / 100.0).floor() + (x / 100.0).fract() * 100.0 / 60.0;
+    if n_s.contains('S') {
+        lat_d = -lat_d
+    }
+    let x: f64 = FromStr::from_str(lon).ok()?;
+    let mut lon_d = (x / 100.0).floor() + (x / 100.0).fract() * 100.0 / 60.0;
+    if e_w.contains('W') {
+        lon_d = -lon_d
+    }
+    Some((lat_d, lon_d))
+}
+
+// Keeps track of the time in a file as we read through it. The date comes
+// from ZDA or RMC and the time of day from any sentence that carries one, so
+// every line can be given a time stamp even when ZDA only turns up now and then.
+#[derive(Default)]
+pub struct FileClock {
+    date: Option<NaiveDate>,
+    pub now: Option<NaiveDateTime>,
+}
+
+impl FileClock {
+    pub fn new() -> FileClock {
+        FileClock::default()
+    }
+
+    // For files that might not have a date at all: until a ZDA or RMC says
+    // otherwise the times of day are taken to be on some day or other. Good
+    // enough for anything that only needs to know how far apart lines are.
+    pub fn undated() -> FileClock {
+        FileClock {
+            date: NaiveDate::from_ymd_opt(2000, 1, 1),
+            now: None,
+        }
+    }
+
+    // Look at one line and move the clock along if it carries a time.
+    // Returns the time of the line, which is the last known time otherwise.
+    pub fn update(&mut self, line: &str) -> Option<NaiveDateTime> {
+        let fields: Vec<&str> = line.split(',').collect();
+        let time_field = match sentence_type(line) {
+            Some("ZDA") => {
+                if let Some(dt) = parse_zda(&fields) {
+                    self.date = Some(dt.date());
+                    self.now = Some(dt);
+                }
+                return self.now;
+            }
+            // $GPRMC,020659.21,A,4937.8509,N,12401.4384,W,0.1,359.5,220221,,,D*7A
+            Some("RMC") if fields.len() > 9 => {
+                if let Some(date) = parse_ddmmyy(fields[9]) {
+                    self.date = Some(date);
+                }
+                fields[1]
+            }
+            Some("GGA") if fields.len() > 1 => fields[1],
+            // $GPGLL,4937.8509,N,12401.4384,W,020659.21,A*2C
+            Some("GLL") if fields.len() > 5 => fields[5],
+            _ => return self.now,
+        };
+        if let (Some(date), Some(t)) = (self.date, parse_hms(time_field)) {
+            let mut dt = date.and_time(t);
+            // Sentences can go past midnight before the next ZDA or RMC tells us the new date
+            if let Some(now) = self.now {
+                if now - dt > chrono::Duration::hours(12) {
+                    dt += chrono::Duration::days(1);
+                    self.date = Some(dt.date());
+                }
+            }
+            self.now = Some(dt);
+        }
+        self.now
+    }
+}
+
+// "220221" -> 2021-02-22 as found in RMC
+pub fn parse_ddmmyy(field: &str) -> Option<NaiveDate> {
+    let d: u32 = FromStr::from_str(field.get(0..2)?).ok()?;
+    let m: u32 = FromStr::from_str(field.get(2..4)?).ok()?;
+    let y: i32 = FromStr::from_str(field.get(4..6)?).ok()?;
+    NaiveDate::from_ymd_opt(2000 + y, m, d)
+}
+
+// Put the '$', the checksum and the "*hh" around the body of a sentence, so
+// "GPZDA,020659.00,22,02,2021,00,00" becomes "$GPZDA,020659.00,22,02,2021,00,00*6D"
+pub fn with_checksum(body: &str) -> String {
+    format!("${}*{:02X}", body, checksum(body))
+}
+
+// 49.630848 -> "4937.8509,N"
+pub fn format_lat(lat: f64) -> String {
+    format!("{},{}", degrees_minutes(lat, 2, 4), if lat < 0.0 { 'S' } else { 'N' })
+}
+
+// -124.02397 -> "12401.4384,W"
+pub fn format_lon(lon: f64) -> String {
+    format!("{},{}", degrees_minutes(lon, 3, 4), if lon < 0.0 { 'W' } else { 'E' })
+}
+
+// "dddmm.mmmm" without the hemisphere, with so many digits of degrees and
+// decimals of minutes
+pub fn degrees_minutes(x: f64, deg_digits: usize, decimals: usize) -> String {
+    // Round to the decimals of minutes we print first so we never get "60.0000"
+    let scale = 10f64.powi(decimals as i32);
+    let min = (x.abs() * 60.0 * scale).round() / scale;
+    let deg = (min / 60.0).floor();
+    format!(
+        "{:0dw$}{:0mw$.decimals$}",
+        deg as u32,
+        min - deg * 60.0,
+        dw = deg_digits,
+        mw = if decimals > 0 { decimals + 3 } else { 2 },
+        decimals = decimals
+    )
+}
+
+// The time of day as "hhmmss.ss"
+pub fn format_hms(t: NaiveDateTime) -> String {
+    format!("{}.{:02}", t.format("%H%M%S"), t.nanosecond() / 10_000_000)
+}
+
+// The date as "ddmmyy" for RMC
+pub fn format_ddmmyy(t: NaiveDateTime) -> String {
+    t.format("%d%m%y").to_string()
+}
+
+// Move the time stamps in a ZDA, RMC, GGA or GLL along by offset, keeping the
+// same number of decimals on the seconds, and put a new checksum on the end.
+// The date moves too where there is one, and a time of day on its own wraps
+// around midnight. Any other line comes back as it was.
+pub fn shift_times(line: &str, offset: chrono::Duration) -> String {
+    let (mut fields, with_sum) = match split_fields(line) {
+        Some(split) => split,
+        None => return line.to_string(),
+    };
+    let shift = |date: NaiveDate, time: &str| {
+        parse_hms_fraction(time).map(|t| round_to(date.and_time(t) + offset, decimals(time)))
+    };
+    // Any day will do for a time of day on its own
+    let any_day = NaiveDate::from_ymd_opt(2000, 1, 1).unwrap();
+    match sentence_type(line) {
+        // $GPZDA,234626.99,22,02,2021,08,00*6A
+        Some("ZDA") if fields.len() >= 5 => {
+            let date = NaiveDate::from_ymd_opt(
+                fields[4].parse().unwrap_or(0),
+                fields[3].parse().unwrap_or(0),
+                fields[2].parse().unwrap_or(0),
+            );
+            match date.and_then(|d| shift(d, &fields[1])) {
+                Some(t) => {
+                    fields[1] = format_hms_like(t, &fields[1]);
+                    fields[2] = t.format("%d").to_string();
+                    fields[3] = t.format("%m").to_string();
+                    fields[4] = t.format("%Y").to_string();
+                }
+                None => return line.to_string(),
+            }
+        }
+        // $GPRMC,020659.21,A,4937.8509,N,12401.4384,W,0.1,359.5,220221,,,D*7A
+        Some("RMC") if fields.len() >= 10 => {
+            let date = parse_ddmmyy(&fields[9]);
+            match shift(date.unwrap_or(any_day), &fields[1]) {
+                Some(t) => {
+                    fields[1] = format_hms_like(t, &fields[1]);
+                    if date.is_some() {
+                        fields[9] = format_ddmmyy(t);
+                    }
+                }
+                None => return line.to_string(),
+            }
+        }
+        // $GPGGA,020659.21,... and $GPGLL,4937.8509,N,12401.4384,W,020659.21,A*2C
+        Some(kind @ ("GGA" | "GLL")) => {
+            let i = if kind == "GGA" { 1 } else { 5 };
+            match fields.get(i).and_then(|f| shift(any_day, f)) {
+                Some(t) => fields[i] = format_hms_like(t, &fields[i]),
+                None => return line.to_string(),
+            }
+        }
+        _ => return line.to_string(),
+    }
+    rebuild(line, &fields, with_sum)
+}
+
+// Put a sentence back together after changing some of its fields, keeping the
+// '$' or '!' it started with and with a new checksum if it had one before.
+pub fn rebuild(line: &str, fields: &[String], with_sum: bool) -> String {
+    let body = fields.join(",");
+    let start = &line[..1];
+    if with_sum {
+        format!("{}{}*{:02X}", start, body, checksum(&body))
+    } else {
+        format!("{}{}", start, body)
+    }
+}
+
+// The fields of a sentence without the '$' at the front or the "*hh" on the
+// end, and whether there was a checksum
+pub fn split_fields(line: &str) -> Option<(Vec<String>, bool)> {
+    let rest = line.get(1..)?;
+    let (body, with_sum) = match rest.rsplit_once('*') {
+        Some((body, _)) => (body, true),
+        None => (rest, false),
+    };
+    Some((body.split(',').map(|f| f.to_string()).collect(), with_sum))
+}
+
+// Like parse_hms but keeping the fraction of a second
+pub fn parse_hms_fraction(field: &str) -> Option<NaiveTime> {
+    let t = parse_hms(field)?;
+    let fraction: f64 = match field.get(6..) {
+        Some(f) if f.starts_with('.') && f.len() > 1 => format!("0{}", f).parse().ok()?,
+        _ => 0.0,
+    };
+    Some(t + chrono::Duration::nanoseconds((fraction * 1e9).round() as i64))
+}
+
+fn decimals(field: &str) -> u32 {
+    field.split_once('.').map_or(0, |(_, f)| f.len().min(9) as u32)
+}
+
+// Round to so many decimals of a second, so that printing them can't carry
+fn round_to(t: NaiveDateTime, decimals: u32) -> NaiveDateTime {
+    let unit = 10i64.pow(9 - decimals);
+    let ns = t.nanosecond() as i64;
+    t - chrono::Duration::nanoseconds(ns) + chrono::Duration::nanoseconds((ns + unit / 2) / unit * unit)
+}
+
+// "hhmmss" with as many decimals on the seconds as the original had
+fn format_hms_like(t: NaiveDateTime, original: &str) -> String {
+    let decimals = decimals(original);
+    let hms = t.format("%H%M%S").to_string();
+    if decimals == 0 {
+        return hms;
+    }
+    let digits = t.nanosecond() / 10u32.pow(9 - decimals);
+    format!("{}.{:0width$}", hms, digits, width = decimals as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn at(y: i32, m: u32, d: u32, hms: (u32, u32, u32)) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap().and_hms_opt(hms.0, hms.1, hms.2).unwrap()
+    }
+
+    #[test]
+    fn clock_takes_the_date_from_zda_or_rmc() {
+        let mut clock = FileClock::new();
+        // No date yet, so no time either
+        assert_eq!(clock.update("$GPGGA,020659,4937.8509,N,12401.4384,W,2,9,0.83,,M,,M*44"), None);
+        assert_eq!(clock.update("$GPZDA,020700,22,02,2021,00,00*6B"), Some(at(2021, 2, 22, (2, 7, 0))));
+        assert_eq!(
+            clock.update("$GPGGA,020701,4937.8509,N,12401.4384,W,2,9,0.83,,M,,M*44"),
+            Some(at(2021, 2, 22, (2, 7, 1)))
+        );
+        // Lines without a time stay at the last one
+        assert_eq!(clock.update("$HCHDG,101.1,,,,*41"), Some(at(2021, 2, 22, (2, 7, 1))));
+        assert_eq!(clock.update("$GPGLL,4937.8509,N,12401.4384,W,020702,A*2C"), Some(at(2021, 2, 22, (2, 7, 2))));
+
+        let mut clock = FileClock::new();
+        assert_eq!(
+            clock.update("$GPRMC,020659.21,A,4937.8509,N,12401.4384,W,0.1,359.5,220221,,,D*7A"),
+            Some(at(2021, 2, 22, (2, 6, 59)))
+        );
+    }
+
+    #[test]
+    fn clock_goes_past_midnight() {
+        let mut clock = FileClock::new();
+        clock.update("$GPZDA,235959,22,02,2021,00,00*6B");
+        assert_eq!(
+            clock.update("$GPGGA,000001,4937.8509,N,12401.4384,W,2,9,0.83,,M,,M*44"),
+            Some(at(2021, 2, 23, (0, 0, 1)))
+        );
+        // A time a little before the last one is not the next day
+        assert_eq!(
+            clock.update("$GPGGA,000000,4937.8509,N,12401.4384,W,2,9,0.83,,M,,M*44"),
+            Some(at(2021, 2, 23, (0, 0, 0)))
+        );
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(with_checksum("GPZDA,020659.00,22,02,2021,00,00"), "$GPZDA,020659.00,22,02,2021,00,00*6D");
+        assert_eq!(checksum_ok("$GPZDA,020659.00,22,02,2021,00,00*6D\r\n"), Some(true));
+        assert_eq!(checksum_ok("$GPZDA,020659.00,22,02,2021,00,00*6B"), Some(false));
+        assert_eq!(checksum_ok("$GPZDA,020659.00,22,02,2021,00,00"), None);
+    }
+
+    #[test]
+    fn positions_in_degrees_and_minutes() {
+        let (lat, lon) = parse_lat_lon("4937.8509", "N", "12401.4384", "W").unwrap();
+        assert!((lat - 49.630848).abs() < 1e-6);
+        assert!((lon + 124.02397).abs() < 1e-5);
+        assert_eq!(format_lat(49.630848), "4937.8509,N");
+        assert_eq!(format_lat(-0.5), "0030.0000,S");
+        assert_eq!(format_lon(-124.023973), "12401.4384,W");
+        assert_eq!(format_lon(5.0), "00500.0000,E");
+        // Rounding up to the next degree doesn't leave "60" minutes
+        assert_eq!(degrees_minutes(10.999_999_9, 2, 4), "1100.0000");
+        assert_eq!(degrees_minutes(10.5, 3, 0), "01030");
+        assert_eq!(degrees_minutes(10.5, 2, 1), "1030.0");
+    }
+
+    #[test]
+    fn times_of_day() {
+        assert_eq!(parse_hms("020659.21"), NaiveTime::from_hms_opt(2, 6, 59));
+        // A "60" for the seconds or minutes is carried
+        assert_eq!(parse_hms("105960"), NaiveTime::from_hms_opt(11, 0, 0));
+        assert_eq!(parse_hms("0206"), None);
+        assert_eq!(parse_hms_fraction("020659.25"), NaiveTime::from_hms_milli_opt(2, 6, 59, 250));
+    }
+
+    #[test]
+    fn shifting_times_moves_the_date_too() {
+        let line = with_checksum("GPZDA,235959.50,28,02,2021,00,00");
+        assert_eq!(shift_times(&line, Duration::seconds(1)), with_checksum("GPZDA,000000.50,01,03,2021,00,00"));
+        let line = with_checksum("GPRMC,000010,A,4937.8509,N,12401.4384,W,0.1,359.5,010321,,,D");
+        assert_eq!(
+            shift_times(&line, Duration::seconds(-20)),
+            with_checksum("GPRMC,235950,A,4937.8509,N,12401.4384,W,0.1,359.5,280221,,,D")
+        );
+    }
+
+    #[test]
+    fn shifting_times_of_day() {
+        // No date in a GGA, so it just wraps round
+        let line = with_checksum("GPGGA,235959.21,4937.8509,N,12401.4384,W,2,9,0.83,,M,,M");
+        assert_eq!(
+            shift_times(&line, Duration::milliseconds(1500)),
+            with_checksum("GPGGA,000000.71,4937.8509,N,12401.4384,W,2,9,0.83,,M,,M")
+        );
+        // No checksum stays that way
+        assert_eq!(
+            shift_times("$GPGLL,4937.8509,N,12401.4384,W,020659,A", Duration::hours(-3)),
+            "$GPGLL,4937.8509,N,12401.4384,W,230659,A"
+        );
+        // Anything else comes back as it was
+        let line = with_checksum("HCHDG,101.1,,,,");
+        assert_eq!(shift_times(&line, Duration::seconds(5)), line);
+        assert_eq!(shift_times("not nmea", Duration::seconds(5)), "not nmea");
+    }
+
+    #[test]
+    fn tags_and_patterns() {
+        assert_eq!(tag("$GPGGA,020659.21,4937.8509,N"), Some("GPGGA"));
+        assert_eq!(tag("!AIVDM,1,1,,B,15Mg,0*4E"), Some("AIVDM"));
+        assert_eq!(tag("GPGGA,1"), None);
+        // Three letters are a sentence type from any talker, but not proprietary
+        assert!(tag_matches("GSV", "GPGSV"));
+        assert!(tag_matches("gsv", "GLGSV"));
+        assert!(!tag_matches("GSV", "PGSVX"));
+        assert!(!tag_matches("GSV", "GPGSA"));
+        assert!(tag_matches("*VWR", "IIVWR"));
+        assert!(tag_matches("P*", "PGRME"));
+        assert!(!tag_matches("P*", "GPRMC"));
+        assert!(tag_matches("GP???", "GPRMC"));
+        assert!(!tag_matches("GP??", "GPRMC"));
+        assert!(tag_matches("*", "AIVDM"));
+        assert!(tag_matches("G*A", "GPGGA"));
+    }
+}
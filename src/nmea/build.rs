@@ -0,0 +1,153 @@
+use super::{format_ddmmyy, format_hms, format_lat, format_lon, with_checksum};
+use chrono::NaiveDateTime;
+
+// Sentences we make up ourselves (from a track, a simulation, or to fill in
+// what's missing from a file), with whatever talker the caller wants.
+
+pub fn zda(talker: &str, t: NaiveDateTime) -> String {
+    with_checksum(&format!("{}ZDA,{},{},00,00", talker, format_hms(t), t.format("%d,%m,%Y")))
+}
+
+// A plain GPS fix with a believable number of satellites
+pub fn gga(talker: &str, t: NaiveDateTime, lat: f64, lon: f64) -> String {
+    with_checksum(&format!(
+        "{}GGA,{},{},{},1,08,1.0,0.0,M,0.0,M,,",
+        talker,
+        format_hms(t),
+        format_lat(lat),
+        format_lon(lon)
+    ))
+}
+
+pub fn rmc(talker: &str, t: NaiveDateTime, lat: f64, lon: f64, sog: f64, cog: f64) -> String {
+    with_checksum(&format!(
+        "{}RMC,{},A,{},{},{:.1},{:.1},{},,,A",
+        talker,
+        format_hms(t),
+        format_lat(lat),
+        format_lon(lon),
+        sog,
+        cog,
+        format_ddmmyy(t)
+    ))
+}
+
+pub fn vtg(talker: &str, sog: f64, cog: f64) -> String {
+    with_checksum(&format!("{}VTG,{:.1},T,,M,{:.1},N,{:.1},K,A", talker, cog, sog, sog * 1.852))
+}
+
+pub fn hdt(talker: &str, heading: f64) -> String {
+    with_checksum(&format!("{}HDT,{:.1},T", talker, heading))
+}
+
+// Wind angles are off the bow, 0 - 360 clockwise, and R (relative) or T (true)
+pub fn mwv(talker: &str, angle: f64, reference: &str, speed_kts: f64) -> String {
+    with_checksum(&format!("{}MWV,{:.1},{},{:.1},N,A", talker, angle.rem_euclid(360.0), reference, speed_kts))
+}
+
+pub fn mwd(talker: &str, direction_t: f64, speed_kts: f64) -> String {
+    with_checksum(&format!(
+        "{}MWD,{:.1},T,,M,{:.1},N,{:.1},M",
+        talker,
+        direction_t,
+        speed_kts,
+        speed_kts * 1852.0 / 3600.0
+    ))
+}
+
+// Magnetic heading with the variation, and no deviation
+pub fn hdg(talker: &str, heading_m: f64, variation: f64) -> String {
+    with_checksum(&format!(
+        "{}HDG,{:.1},,,{:.1},{}",
+        talker,
+        heading_m.rem_euclid(360.0),
+        variation.abs(),
+        if variation < 0.0 { 'W' } else { 'E' }
+    ))
+}
+
+pub fn dpt(talker: &str, depth_m: f64, offset_m: f64) -> String {
+    with_checksum(&format!("{}DPT,{:.1},{:.1},", talker, depth_m, offset_m))
+}
+
+// Where we are with respect to the leg we're sailing, for the autopilot
+// sentences. Bearings are true; the magnetic fields are left empty.
+pub struct Steering<'a> {
+    pub origin: &'a str,
+    pub dest: &'a str,
+    pub dest_lat: f64,
+    pub dest_lon: f64,
+    // Nautical miles, positive when we are right of the track
+    pub xte_nm: f64,
+    pub range_nm: f64,
+    pub bearing: f64,
+    // Bearing of the leg, origin to destination
+    pub bod: f64,
+    pub vmg_kts: f64,
+    pub arrived: bool,
+    pub passed: bool,
+}
+
+// Off the track to the right means steer left
+fn xte_fields(s: &Steering) -> String {
+    format!("{:.3},{}", s.xte_nm.abs().min(9.999), if s.xte_nm > 0.0 { 'L' } else { 'R' })
+}
+
+fn status(b: bool) -> char {
+    if b {
+        'A'
+    } else {
+        'V'
+    }
+}
+
+pub fn rmb(talker: &str, s: &Steering) -> String {
+    with_checksum(&format!(
+        "{}RMB,A,{},{},{},{},{},{:.1},{:.1},{:.1},{},A",
+        talker,
+        xte_fields(s),
+        s.origin,
+        s.dest,
+        format_lat(s.dest_lat),
+        format_lon(s.dest_lon),
+        s.range_nm.min(999.9),
+        s.bearing.rem_euclid(360.0),
+        s.vmg_kts,
+        status(s.arrived)
+    ))
+}
+
+pub fn apb(talker: &str, s: &Steering) -> String {
+    with_checksum(&format!(
+        "{}APB,A,A,{},N,{},{},{:.1},T,{},{:.1},T,{:.1},T,A",
+        talker,
+        xte_fields(s),
+        status(s.arrived),
+        status(s.passed),
+        s.bod.rem_euclid(360.0),
+        s.dest,
+        s.bearing.rem_euclid(360.0),
+        s.bearing.rem_euclid(360.0)
+    ))
+}
+
+pub fn xte(talker: &str, s: &Steering) -> String {
+    with_checksum(&format!("{}XTE,A,A,{},N,A", talker, xte_fields(s)))
+}
+
+pub fn bwc(talker: &str, t: NaiveDateTime, s: &Steering) -> String {
+    with_checksum(&format!(
+        "{}BWC,{},{},{},{:.1},T,,M,{:.2},N,{},A",
+        talker,
+        format_hms(t),
+        format_lat(s.dest_lat),
+        format_lon(s.dest_lon),
+        s.bearing.rem_euclid(360.0),
+        s.range_nm,
+        s.dest
+    ))
+}
+
+pub fn bod(talker: &str, s: &Steering) -> String {
+    with_checksum(&format!("{}BOD,{:.1},T,,M,{},{}", talker, s.bod.rem_euclid(360.0), s.dest, s.origin))
+}
@@ -0,0 +1,110 @@
+use super::{degrees_minutes, parse_lat_lon, rebuild, sentence_type, split_fields};
+
+// Where the positions and true bearings are in the sentences that carry them.
+// Each position is the index of the latitude, followed by N/S, the longitude
+// and E/W.
+const POSITIONS: &[(&str, &[usize])] = &[
+    // $GPGGA,020659.21,4937.8509,N,12401.4384,W,2,9,0.83,,M,,M*44
+    ("GGA", &[2]),
+    // $GPRMC,020659.21,A,4937.8509,N,12401.4384,W,0.1,359.5,220221,,,D*7A
+    ("RMC", &[3]),
+    // $GPGLL,4937.8509,N,12401.4384,W,020659.21,A*2C
+    ("GLL", &[1]),
+    ("GNS", &[2]),
+    // $GPRMB,A,0.66,L,003,004,4917.24,N,12309.57,W,001.3,052.5,000.5,V*20
+    ("RMB", &[6]),
+    // $GPWPL,4917.16,N,12310.64,W,003*65
+    ("WPL", &[1]),
+    // $GPBWC,220516,5130.02,N,00046.34,W,213.8,T,218.0,M,0004.6,N,EGLM*21
+    ("BWC", &[2]),
+];
+
+// Course, heading, wind direction and bearing fields. The magnetic ones turn
+// with the true ones, since variation doesn't change over the small distances
+// we move things.
+const BEARINGS: &[(&str, &[usize])] = &[
+    ("RMC", &[8]),
+    // $IIVTG,359.5,T,,M,0.1,N,0.1,K,D*15
+    ("VTG", &[1, 3]),
+    // $HEHDT,274.07,T*03
+    ("HDT", &[1]),
+    ("HDG", &[1]),
+    ("HDM", &[1]),
+    ("VHW", &[1, 3]),
+    // $WIMWD,245.1,T,226.4,M,12.3,N,6.3,M*5B
+    ("MWD", &[1, 3]),
+    ("RMB", &[11]),
+    ("BWC", &[6, 8]),
+    ("BOD", &[1, 3]),
+    // $GPAPB,A,A,0.10,R,N,V,V,011,M,DEST,011,M,011,M*3C
+    ("APB", &[8, 11, 13]),
+];
+
+// Run every position in a sentence through "map", which gives back where it
+// should be now (or None to leave the position out altogether), and turn every
+// bearing by "rotate" degrees. The sentence comes back with a new checksum if
+// anything changed, and just as it was if not. A fix whose position is left
+// out is marked as no fix so nothing tries to use it.
+pub fn rewrite(line: &str, map: &mut dyn FnMut(f64, f64) -> Option<(f64, f64)>, rotate: f64) -> String {
+    let kind = match sentence_type(line) {
+        Some(kind) => kind,
+        None => return line.to_string(),
+    };
+    let positions = POSITIONS.iter().find(|(k, _)| *k == kind).map_or(&[][..], |(_, p)| *p);
+    let bearings = BEARINGS.iter().find(|(k, _)| *k == kind).map_or(&[][..], |(_, b)| *b);
+    if positions.is_empty() && (bearings.is_empty() || rotate == 0.0) {
+        return line.to_string();
+    }
+    let (mut fields, with_sum) = match split_fields(line) {
+        Some(split) => split,
+        None => return line.to_string(),
+    };
+    let mut changed = false;
+    for &i in positions {
+        if fields.len() < i + 4 {
+            continue;
+        }
+        let (lat, lon) = match parse_lat_lon(&fields[i], &fields[i + 1], &fields[i + 2], &fields[i + 3]) {
+            Some(pos) => pos,
+            None => continue,
+        };
+        changed = true;
+        match map(lat, lon) {
+            Some((lat, lon)) => {
+                fields[i] = degrees_minutes(lat, 2, decimals(&fields[i]));
+                fields[i + 1] = if lat < 0.0 { "S" } else { "N" }.to_string();
+                fields[i + 2] = degrees_minutes(lon, 3, decimals(&fields[i + 2]));
+                fields[i + 3] = if lon < 0.0 { "W" } else { "E" }.to_string();
+            }
+            None => {
+                for field in &mut fields[i..i + 4] {
+                    field.clear();
+                }
+                match kind {
+                    "GGA" if fields.len() > 6 => fields[6] = "0".to_string(),
+                    "RMC" => fields[2] = "V".to_string(),
+                    "GLL" if fields.len() > 6 => fields[6] = "V".to_string(),
+                    _ => {}
+                }
+            }
+        }
+    }
+    if rotate != 0.0 {
+        for &i in bearings {
+            if let Some(b) = fields.get(i).and_then(|f| f.parse::<f64>().ok()) {
+                let d = decimals(&fields[i]);
+                fields[i] = format!("{:.*}", d, (b + rotate).rem_euclid(360.0));
+                changed = true;
+            }
+        }
+    }
+    if changed {
+        rebuild(line, &fields, with_sum)
+    } else {
+        line.to_string()
+    }
+}
+
+fn decimals(field: &str) -> usize {
+    field.split_once('.').map_or(0, |(_, f)| f.len())
+}
@@ -1,124 +1,997 @@
+use crate::ais::targets::Target;
+use crate::nmea;
+use crate::udp_broadcaster::instruments::Instruments;
 use crate::udp_broadcaster::where_am_i_now;
-use chrono::NaiveDate;
+use chrono::{NaiveDateTime, Timelike};
 use geoutils::Location;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::{self, BufRead};
-use std::str::FromStr;
-
-pub fn scan_and_report(file_h: File) -> bool {
-    let mut where_have_i_been: Vec<String> = [].to_vec();
-    let mut lat_d: f64 = 0.0;
-    let mut lon_d: f64 = 0.0;
-    let mut accum_distance: f64 = 0.0;
-    let mut file_start_time = NaiveDate::from_ymd_opt(1970, 1, 1)
-        .unwrap()
-        .and_hms_opt(0, 0, 0)
-        .unwrap();
-    let mut dt = NaiveDate::from_ymd_opt(1970, 1, 1)
-        .unwrap()
-        .and_hms_opt(0, 0, 0)
-        .unwrap();
-    // Read the file line by line and send each line over UDP
-    let reader = io::BufReader::new(file_h);
-    for line in reader.lines() {
-        let line = line.unwrap_or(" ".to_string());
-        let fields: Vec<&str> = line.split(',').collect();
-        if fields[0].starts_with("$") && fields[0].len() >= 6 && fields[0][3..6].eq("ZDA") {
-            let y: i32 = FromStr::from_str(fields[4]).unwrap_or(1970);
-            let m: u32 = FromStr::from_str(fields[3]).unwrap_or(1);
-            let d: u32 = FromStr::from_str(fields[2]).unwrap_or(1);
-            let mut hr: u32 = FromStr::from_str(&fields[1][0..2]).unwrap_or(0);
-            let mut mn: u32 = FromStr::from_str(&fields[1][2..4]).unwrap_or(0);
-            let mut se: u32 = FromStr::from_str(&fields[1][4..6]).unwrap_or(0);
-            // Some GPS units will give you "60" for minutes or seconds but chrono hates that
-            // Quick and dirty fix for that...
-            if se >= 60 {
-                se = 0;
-                mn += 1
+
+mod distance;
+mod fix_quality;
+mod readings;
+mod wind;
+
+use distance::{Distance, Method};
+use fix_quality::FixQuality;
+use readings::ReadingStats;
+use wind::TrueWind;
+
+// Everything the scanner found out about a file. It gets built up while we
+// read the file and is printed at the end in whichever format was asked for:
+// the original prose, JSON or CSV for loading into a spreadsheet or database.
+pub struct ScanReport {
+    pub start_time: Option<NaiveDateTime>,
+    pub end_time: Option<NaiveDateTime>,
+    pub distance: Distance,
+    pub places: Vec<PlaceVisit>,
+    pub lines: u64,
+    pub inventory: BTreeMap<String, SentenceStats>,
+    pub non_nmea: u64,
+    pub events: Vec<TimeEvent>,
+    pub fix: FixQuality,
+    pub exclude_poor_fixes: bool,
+    pub wind: TrueWind,
+    pub readings: Vec<ReadingStats>,
+    pub ais: BTreeMap<u32, Target>,
+    pub errors: Vec<ScanError>,
+}
+
+// How long the clock or a sentence can go quiet before we call it a dropout
+#[derive(clap::Args)]
+pub struct ScanOptions {
+    /// Report jumps in the file clock longer than this many seconds
+    #[arg(long, default_value_t = DEFAULT_GAP, value_name = "SECONDS")]
+    pub gap: f64,
+
+    /// Report any sentence type that goes quiet for longer than this many seconds
+    #[arg(long, default_value_t = DEFAULT_SILENCE, value_name = "SECONDS")]
+    pub silence: f64,
+
+    /// Summarise the GPS fix quality over periods of this many seconds
+    #[arg(long, default_value_t = DEFAULT_FIX_INTERVAL, value_name = "SECONDS")]
+    pub fix_interval: f64,
+
+    /// Summarise the true wind over periods of this many seconds
+    #[arg(long, default_value_t = DEFAULT_WIND_INTERVAL, value_name = "SECONDS")]
+    pub wind_interval: f64,
+
+    /// A fix with an HDOP above this is too poor to trust
+    #[arg(long, default_value_t = DEFAULT_MAX_HDOP, value_name = "HDOP")]
+    pub max_hdop: f64,
+
+    /// Leave positions from poor fixes out of the distance travelled
+    #[arg(long)]
+    pub exclude_poor_fixes: bool,
+
+    /// Throw out fixes that would need a speed over this many knots to get to
+    #[arg(long, default_value_t = DEFAULT_MAX_SPEED, value_name = "KNOTS")]
+    pub max_speed: f64,
+
+    /// Only count movement once it adds up to this many metres (ignores jitter at the dock)
+    #[arg(long, default_value_t = DEFAULT_JITTER, value_name = "METRES")]
+    pub jitter: f64,
+
+    /// How to work out the distance between two fixes
+    #[arg(long, value_enum, default_value_t = Method::Haversine)]
+    pub distance_method: Method,
+}
+
+const DEFAULT_GAP: f64 = 5.0;
+const DEFAULT_SILENCE: f64 = 30.0;
+const DEFAULT_FIX_INTERVAL: f64 = 60.0;
+const DEFAULT_WIND_INTERVAL: f64 = 60.0;
+const DEFAULT_MAX_HDOP: f64 = 5.0;
+const DEFAULT_MAX_SPEED: f64 = 50.0;
+const DEFAULT_JITTER: f64 = 10.0;
+
+impl Default for ScanOptions {
+    fn default() -> ScanOptions {
+        ScanOptions {
+            gap: DEFAULT_GAP,
+            silence: DEFAULT_SILENCE,
+            fix_interval: DEFAULT_FIX_INTERVAL,
+            wind_interval: DEFAULT_WIND_INTERVAL,
+            max_hdop: DEFAULT_MAX_HDOP,
+            exclude_poor_fixes: false,
+            max_speed: DEFAULT_MAX_SPEED,
+            jitter: DEFAULT_JITTER,
+            distance_method: Method::Haversine,
+        }
+    }
+}
+
+// Something odd about the time line of the file: a gap in the time stamps, a
+// sentence type that went quiet for a while, or time going backwards.
+pub struct TimeEvent {
+    pub line_no: u64,
+    pub time: Option<NaiveDateTime>,
+    pub kind: EventKind,
+    pub seconds: f64,
+    pub message: String,
+}
+
+#[derive(Clone, Copy)]
+pub enum EventKind {
+    Gap,
+    Backwards,
+    Silence,
+    Stopped,
+}
+
+// What we saw of one talker + sentence type, e.g. "GPGGA" or "WIVWR". Times are
+// the file clock at the time, so these are only as good as the time stamps in the file.
+pub struct SentenceStats {
+    pub kind: Kind,
+    pub count: u64,
+    pub first: Option<NaiveDateTime>,
+    pub last: Option<NaiveDateTime>,
+    pub max_per_second: u64,
+    this_second: Option<NaiveDateTime>,
+    in_this_second: u64,
+    last_line: u64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Kind {
+    Nmea,
+    Proprietary,
+    Ais,
+}
+
+// The first time we were near a new place, along with the distance covered up to then
+pub struct PlaceVisit {
+    pub utc: String,
+    pub lat: f64,
+    pub lon: f64,
+    pub place: String,
+    pub distance_m: f64,
+}
+
+pub struct ScanError {
+    pub line_no: u64,
+    pub message: String,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum Format {
+    Text,
+    Json,
+    Csv,
+}
+
+pub fn scan(file_h: File, options: &ScanOptions) -> io::Result<ScanReport> {
+    let mut report = ScanReport {
+        start_time: None,
+        end_time: None,
+        distance: Distance::new(options.distance_method, options.max_speed, options.jitter),
+        places: vec![],
+        lines: 0,
+        inventory: BTreeMap::new(),
+        non_nmea: 0,
+        events: vec![],
+        fix: FixQuality::new(options.fix_interval, options.max_hdop),
+        exclude_poor_fixes: options.exclude_poor_fixes,
+        wind: TrueWind::new(options.wind_interval),
+        readings: readings::all(),
+        ais: BTreeMap::new(),
+        errors: vec![],
+    };
+    let mut clock = nmea::FileClock::new();
+    // For the true wind, which needs the latest speed and heading as well
+    let mut inst = Instruments::new();
+    let mut last_time: Option<NaiveDateTime> = None;
+    // Read the file line by line and look at each sentence as we go
+    let mut reader = io::BufReader::new(file_h);
+    let mut buf = vec![];
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+        report.lines += 1;
+        let line_no = report.lines;
+        let line = match std::str::from_utf8(&buf) {
+            Ok(line) => line.trim_end(),
+            Err(_) => {
+                report.errors.push(ScanError { line_no, message: "line is not valid text".to_string() });
+                continue;
+            }
+        };
+        if line.is_empty() {
+            continue;
+        }
+        if nmea::checksum_ok(line) == Some(false) {
+            report.errors.push(ScanError { line_no, message: "bad checksum".to_string() });
+        }
+        let now = clock.update(line);
+        // Look for holes in the time line of the file, and for time going backwards
+        if let (Some(before), Some(now)) = (last_time, now) {
+            let step = (now - before).num_milliseconds() as f64 / 1000.0;
+            if step < 0.0 {
+                report.events.push(TimeEvent {
+                    line_no,
+                    time: Some(now),
+                    kind: EventKind::Backwards,
+                    seconds: -step,
+                    message: format!("time went back {:.1} s from {}", -step, before.format("%H:%M:%S")),
+                });
+            } else if step > options.gap {
+                report.events.push(TimeEvent {
+                    line_no,
+                    time: Some(now),
+                    kind: EventKind::Gap,
+                    seconds: step,
+                    message: format!("no time stamps for {:.1} s since {}", step, before.format("%H:%M:%S")),
+                });
+            }
+        }
+        last_time = now.or(last_time);
+        inst.update(line);
+        if let (true, Some(now)) = (inst.new_true_wind, now) {
+            let d = &inst.data;
+            if let (Some(twa), Some(tws)) = (d.twa, d.tws_kts) {
+                report.wind.add(now, twa, tws, d.twd_t, inst.true_wind_computed);
             }
-            if mn >= 60 {
-                mn = 0;
-                hr += 1
+        }
+        // Just what this line says, for the statistics
+        for stats in report.readings.iter_mut() {
+            stats.add(&inst.line);
+        }
+        match nmea::tag(line) {
+            Some(tag) => report.count(tag, line.starts_with('!'), now, line_no, options.silence),
+            None => {
+                report.non_nmea += 1;
+                continue;
             }
-            // The most recent date that we read from the file is always in 'dt'
-            dt = NaiveDate::from_ymd_opt(y, m, d)
-                .unwrap()
-                .and_hms_opt(hr, mn, se)
-                .unwrap();
-            // If we have not yet initialized the start times, then do it now.
-            if file_start_time
-                == NaiveDate::from_ymd_opt(1970, 1, 1)
-                    .unwrap()
-                    .and_hms_opt(0, 0, 0)
-                    .unwrap()
-            {
-                file_start_time = dt;
+        }
+        let fields: Vec<&str> = line.split(',').collect();
+        let sentence = match nmea::sentence_type(line) {
+            Some(sentence) => sentence,
+            None => continue,
+        };
+        if sentence == "ZDA" {
+            match nmea::parse_zda(&fields) {
+                Some(dt) => {
+                    // The most recent date that we read from the file is always in end_time
+                    report.end_time = Some(dt);
+                    // If we have not yet initialized the start times, then do it now.
+                    if report.start_time.is_none() {
+                        report.start_time = Some(dt);
+                    }
+                }
+                None => report.errors.push(ScanError { line_no, message: "unreadable ZDA time".to_string() }),
             }
         }
+        if sentence == "GSA" {
+            report.fix.gsa(&fields, now);
+        }
+        if sentence == "GSV" {
+            report.fix.gsv(&fields[0][1..3], &fields, now);
+        }
         // $GPGGA,020659.21,4937.8509,N,12401.4384,W,2,9,0.83,,M,,M*44
-        if fields[0].starts_with("$") && fields[0].len() >= 6 && fields[0][3..6].eq("GGA") {
-            // Make our first distance point the last location stored in lat_d, lon_d
-            let mut first_time: bool = false;
-            if lat_d == 0.0 && lon_d == 0.0 {
-                first_time = true;
-            };
-            let l1 = Location::new(lat_d, lon_d);
-            // Get latitude from GPS statement
-            let x: f64 = FromStr::from_str(&fields[2]).unwrap_or(0.0);
-            let lat_deg: f64 = (x / 100.0).floor();
-            let lat_min: f64 = (x / 100.0).fract() * 100.0;
-            let n_s: &str = fields[3];
-            lat_d = lat_deg + (lat_min / 60.0);
-            if n_s.contains("S") {
-                lat_d = lat_d * -1.0
-            };
-            // Get longitude from GPS statements
-            let x: f64 = FromStr::from_str(&fields[4]).unwrap_or(0.0);
-            let lon_deg: f64 = (x / 100.0).floor();
-            let lon_min: f64 = (x / 100.0).fract() * 100.0;
-            let e_w: &str = fields[5];
-            lon_d = lon_deg + (lon_min / 60.0);
-            if e_w.contains("W") {
-                lon_d = lon_d * -1.0
+        if sentence == "GGA" && fields.len() >= 6 {
+            if !report.fix.gga(&fields, now, line_no) && options.exclude_poor_fixes {
+                report.fix.excluded += 1;
+                continue;
+            }
+            let (lat_d, lon_d) = match nmea::parse_lat_lon(fields[2], fields[3], fields[4], fields[5]) {
+                Some(pos) => pos,
+                None => continue,
             };
-            // By here we will have some meaningful lat/lon values except
-            // the first time through when lat/long are zero
-            if !first_time {
-                let l2 = Location::new(lat_d, lon_d);
-                let d = accum_distance + l1.haversine_distance_to(&l2).meters();
-                accum_distance = d;
+            if !report.distance.add(Location::new(lat_d, lon_d), now) {
+                continue;
             }
-            if !where_have_i_been.contains(&&where_am_i_now::from_http(lat_d, lon_d)) {
-                where_have_i_been.push(where_am_i_now::from_http(lat_d, lon_d));
-                println!(
-                    "At {} UTC, ({:.4}, {:.4}) is near '{}'",
-                    fields[1],
-                    lat_d,
-                    lon_d,
-                    where_am_i_now::from_http(lat_d, lon_d)
+            let place = where_am_i_now::from_http(lat_d, lon_d);
+            if !report.places.iter().any(|p| p.place == place) {
+                report.places.push(PlaceVisit {
+                    utc: fields[1].to_string(),
+                    lat: lat_d,
+                    lon: lon_d,
+                    place,
+                    distance_m: report.distance.filtered_m,
+                });
+            }
+        }
+    }
+    report.fix.finish();
+    report.ais = std::mem::take(&mut inst.ais.list);
+    // Anything that went quiet and never came back before the end of the file
+    if let Some(end) = last_time {
+        for (tag, st) in &report.inventory {
+            if let Some(last) = st.last {
+                let quiet = (end - last).num_milliseconds() as f64 / 1000.0;
+                if quiet > options.silence {
+                    report.events.push(TimeEvent {
+                        line_no: st.last_line,
+                        time: Some(last),
+                        kind: EventKind::Stopped,
+                        seconds: quiet,
+                        message: format!("{} stopped {:.1} s before the end of the file", tag, quiet),
+                    });
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+impl ScanReport {
+    fn count(&mut self, tag: &str, ais: bool, now: Option<NaiveDateTime>, line_no: u64, silence: f64) {
+        let stats = self.inventory.entry(tag.to_string()).or_insert_with(|| SentenceStats {
+            kind: if ais {
+                Kind::Ais
+            } else if tag.starts_with('P') {
+                Kind::Proprietary
+            } else {
+                Kind::Nmea
+            },
+            count: 0,
+            first: None,
+            last: None,
+            max_per_second: 0,
+            this_second: None,
+            in_this_second: 0,
+            last_line: 0,
+        });
+        stats.count += 1;
+        if let (Some(last), Some(now)) = (stats.last, now) {
+            let quiet = (now - last).num_milliseconds() as f64 / 1000.0;
+            if quiet > silence {
+                self.events.push(TimeEvent {
+                    line_no,
+                    time: Some(now),
+                    kind: EventKind::Silence,
+                    seconds: quiet,
+                    message: format!("{} was silent for {:.1} s since line {}", tag, quiet, stats.last_line),
+                });
+            }
+        }
+        stats.last_line = line_no;
+        if let Some(now) = now {
+            stats.first.get_or_insert(now);
+            stats.last = Some(now);
+            let second = now.with_nanosecond(0);
+            if second == stats.this_second {
+                stats.in_this_second += 1;
+            } else {
+                stats.this_second = second;
+                stats.in_this_second = 1;
+            }
+            stats.max_per_second = stats.max_per_second.max(stats.in_this_second);
+        }
+    }
+
+    // Lines by kind: (NMEA, proprietary, AIS)
+    fn kind_counts(&self) -> (u64, u64, u64) {
+        let by_kind = |k: Kind| self.inventory.values().filter(|s| s.kind == k).map(|s| s.count).sum();
+        (by_kind(Kind::Nmea), by_kind(Kind::Proprietary), by_kind(Kind::Ais))
+    }
+    // True when we found at least one time stamp, which is what the player
+    // needs to keep in synch. The exit code of the scan tells scripts about it.
+    pub fn has_times(&self) -> bool {
+        self.start_time.is_some()
+    }
+
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Text => self.to_text(),
+            Format::Json => self.to_json(),
+            Format::Csv => self.to_csv(),
+        }
+    }
+
+    fn to_text(&self) -> String {
+        let mut out = String::new();
+        for p in &self.places {
+            out += &format!("At {} UTC, ({:.4}, {:.4}) is near '{}'\n", p.utc, p.lat, p.lon, p.place);
+            out += &format!(
+                "Distance travelled so far is {:.1} nautical miles, or {:.1} km\n",
+                nautical_miles(p.distance_m),
+                p.distance_m / 1000.0
+            );
+        }
+        out += &format!("Start time in file is {} UTC\n", time_str(self.start_time));
+        out += &format!("Last time read from file is {} UTC\n", time_str(self.end_time));
+        let d = &self.distance;
+        out += &format!(
+            "Accumulated distance travelled in this file is {:.1} nautical miles, or {:.1} km\n",
+            nautical_miles(d.filtered_m),
+            d.filtered_m / 1000.0
+        );
+        out += &format!(
+            "  ({} distance with {} outlier fixes over {} kts thrown out and {} m of jitter ignored;\n   every fix as it came gives {:.1} nautical miles, or {:.1} km)\n",
+            d.method.name(),
+            d.outliers,
+            d.max_speed_kts,
+            d.jitter_m,
+            nautical_miles(d.raw_m),
+            d.raw_m / 1000.0
+        );
+        out += "\n";
+        out += &format!(
+            "{:<10} {:<12} {:>8} {:>7} {:>7}  {:<19}  {:<19}\n",
+            "Sentence", "Kind", "Count", "Avg Hz", "Max Hz", "First", "Last"
+        );
+        for (tag, st) in &self.inventory {
+            out += &format!(
+                "{:<10} {:<12} {:>8} {:>7} {:>7}  {:<19}  {:<19}\n",
+                tag,
+                st.kind.name(),
+                st.count,
+                st.avg_hz().map_or("-".to_string(), |hz| format!("{:.2}", hz)),
+                st.max_per_second,
+                st.first.map_or("-".to_string(), |t| t.format("%Y-%m-%d %H:%M:%S").to_string()),
+                st.last.map_or("-".to_string(), |t| t.format("%Y-%m-%d %H:%M:%S").to_string()),
+            );
+        }
+        let (nmea, proprietary, ais) = self.kind_counts();
+        out += &format!(
+            "{} NMEA sentences, {} proprietary, {} AIS and {} lines that are not NMEA\n\n",
+            nmea, proprietary, ais, self.non_nmea
+        );
+        out += &self.fix_text();
+        out += &self.wind_text();
+        out += &self.readings_text();
+        out += &self.ais_text();
+        if !self.events.is_empty() {
+            out += "Time gaps and dropouts:\n";
+            for e in &self.events {
+                out += &format!(
+                    "  line {:<8} {:<19}  {:<10} {}\n",
+                    e.line_no,
+                    e.time.map_or("-".to_string(), |t| t.format("%Y-%m-%d %H:%M:%S").to_string()),
+                    e.kind.name(),
+                    e.message
                 );
-                println!(
-                    "Distance travelled so far is {:.1} nautical miles, or {:.1} km",
-                    accum_distance / 1000.0 * 0.5399568,
-                    accum_distance / 1000.0
+            }
+            out += "\n";
+        }
+        out += &format!("{} lines read, {} errors\n", self.lines, self.errors.len());
+        for e in &self.errors {
+            out += &format!("  line {}: {}\n", e.line_no, e.message);
+        }
+        out
+    }
+
+    fn fix_text(&self) -> String {
+        let fix = &self.fix;
+        if fix.series.is_empty() {
+            return String::new();
+        }
+        let opt = |v: Option<f64>, places: usize| v.map_or("-".to_string(), |v| format!("{:.*}", places, v));
+        let mut out = format!("GPS fix quality every {} s:\n", fix.interval);
+        out += &format!(
+            "{:<19}  {:<10} {:>9} {:>9} {:>9} {:>9}  {}\n",
+            "Time", "Fix", "Sats min", "Sats avg", "HDOP avg", "HDOP max", "PDOP avg   In view"
+        );
+        for b in &fix.series {
+            out += &format!(
+                "{:<19}  {:<10} {:>9} {:>9} {:>9} {:>9}  {:>8}   {}\n",
+                b.start.format("%Y-%m-%d %H:%M:%S"),
+                b.fix_type(),
+                b.sats_min().map_or("-".to_string(), |n| n.to_string()),
+                opt(b.sats_avg(), 1),
+                opt(b.hdop_avg(), 2),
+                opt(if b.hdop_max > 0.0 { Some(b.hdop_max) } else { None }, 2),
+                opt(b.pdop_avg(), 2),
+                b.in_view_str()
+            );
+        }
+        if !fix.constellations.is_empty() {
+            let list: Vec<String> = fix.constellations.iter().map(|(c, n)| format!("{} {}", c, n)).collect();
+            out += &format!("Most satellites in view at once: {}\n", list.join(", "));
+        }
+        if fix.poor.is_empty() {
+            out += &format!("No periods where the fix was too poor to trust (no fix, or HDOP over {})\n", fix.max_hdop);
+        } else {
+            out += &format!("Periods where the fix was too poor to trust (no fix, or HDOP over {}):\n", fix.max_hdop);
+            for p in &fix.poor {
+                out += &format!(
+                    "  line {:<8} {} to {}  ({:.0} s)  {}\n",
+                    p.line_no,
+                    p.start.format("%Y-%m-%d %H:%M:%S"),
+                    p.end.format("%H:%M:%S"),
+                    p.seconds(),
+                    p.reason
                 );
             }
         }
+        if self.exclude_poor_fixes {
+            out += &format!("{} positions from poor fixes were left out of the distance\n", fix.excluded);
+        }
+        out + "\n"
     }
-    println!(
-        "Start time in file is {} UTC",
-        file_start_time.format("%Y-%m-%d %H:%M:%S").to_string()
-    );
-    println!(
-        "Last time read from file is {} UTC",
-        dt.format("%Y-%m-%d %H:%M:%S").to_string()
-    );
 
-    println!(
-        "Accumulated distance travelled in this file is {:.1} nautical miles, or {:.1} km",
-        accum_distance/ 1000.0 * 0.5399568,
-        accum_distance / 1000.0
-    );
+    fn wind_text(&self) -> String {
+        let wind = &self.wind;
+        if wind.series.is_empty() {
+            return String::new();
+        }
+        let mut out = format!("True wind every {} s ({}):\n", wind.interval, wind.source());
+        out += &format!(
+            "{:<19}  {:>8} {:>8} {:>8} {:>8} {:>8}\n",
+            "Time", "Samples", "TWS avg", "TWS max", "TWA avg", "TWD avg"
+        );
+        for b in &wind.series {
+            out += &format!(
+                "{:<19}  {:>8} {:>8.1} {:>8.1} {:>8.0} {:>8}\n",
+                b.start.format("%Y-%m-%d %H:%M:%S"),
+                b.samples,
+                b.tws_avg(),
+                b.tws_max,
+                b.twa_avg(),
+                b.twd_avg().map_or("-".to_string(), |d| format!("{:.0}", d))
+            );
+        }
+        out + "(TWS in knots, TWA off the bow on either tack, TWD in degrees true)\n\n"
+    }
+
+    fn readings_text(&self) -> String {
+        let seen: Vec<&ReadingStats> = self.readings.iter().filter(|r| r.count > 0).collect();
+        if seen.is_empty() {
+            return String::new();
+        }
+        let mut out = "Instrument readings:\n".to_string();
+        out += &format!("{:<12} {:>8} {:>9} {:>9} {:>9}  {}\n", "Reading", "Count", "Min", "Avg", "Max", "Units");
+        for r in &seen {
+            out += &format!(
+                "{:<12} {:>8} {:>9.1} {:>9.1} {:>9.1}  {}\n",
+                r.name,
+                r.count,
+                r.min,
+                r.avg(),
+                r.max,
+                r.unit
+            );
+        }
+        out + "\n"
+    }
 
-    true
+    // Every vessel we heard, with the closest it came to our position from GGA
+    fn ais_text(&self) -> String {
+        if self.ais.is_empty() {
+            return String::new();
+        }
+        let mut out = format!("{} AIS vessels seen:\n", self.ais.len());
+        out += &format!(
+            "{:<9} {:<20} {:<5} {:>7}  {:>10}  {:<19}\n",
+            "MMSI", "Name", "Class", "Reports", "Closest nm", "At"
+        );
+        for t in self.ais.values() {
+            out += &format!(
+                "{:<9} {:<20} {:<5} {:>7}  {:>10}  {:<19}\n",
+                t.mmsi,
+                t.display_name(),
+                if t.class_b { "B" } else { "A" },
+                t.reports,
+                t.closest.map_or("-".to_string(), |(m, _)| format!("{:.2}", nautical_miles(m))),
+                t.closest.and_then(|(_, at)| at).map_or("-".to_string(), |t| t.format("%Y-%m-%d %H:%M:%S").to_string()),
+            );
+        }
+        out + "\n"
+    }
+
+    fn to_json(&self) -> String {
+        let mut places = json::JsonValue::new_array();
+        for p in &self.places {
+            let _ = places.push(json::object! {
+                utc: p.utc.clone(),
+                lat: p.lat,
+                lon: p.lon,
+                place: p.place.clone(),
+                distance_km: p.distance_m / 1000.0,
+            });
+        }
+        let mut sentences = json::JsonValue::new_object();
+        for (tag, st) in &self.inventory {
+            sentences[tag.as_str()] = json::object! {
+                kind: st.kind.name(),
+                count: st.count,
+                avg_hz: st.avg_hz(),
+                max_hz: st.max_per_second,
+                first: st.first.map(|t| t.format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+                last: st.last.map(|t| t.format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+            };
+        }
+        let (nmea, proprietary, ais) = self.kind_counts();
+        let mut events = json::JsonValue::new_array();
+        for e in &self.events {
+            let _ = events.push(json::object! {
+                line: e.line_no,
+                time: e.time.map(|t| t.format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+                kind: e.kind.name(),
+                seconds: e.seconds,
+                message: e.message.clone(),
+            });
+        }
+        let mut series = json::JsonValue::new_array();
+        for b in &self.fix.series {
+            let mut in_view = json::JsonValue::new_object();
+            for (c, n) in &b.in_view {
+                in_view[c.as_str()] = (*n).into();
+            }
+            let _ = series.push(json::object! {
+                time: b.start.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                fixes: b.fixes,
+                fix_type: b.fix_type(),
+                sats_min: b.sats_min(),
+                sats_avg: b.sats_avg(),
+                hdop_avg: b.hdop_avg(),
+                hdop_max: b.hdop_max,
+                pdop_avg: b.pdop_avg(),
+                in_view: in_view,
+            });
+        }
+        let mut poor = json::JsonValue::new_array();
+        for p in &self.fix.poor {
+            let _ = poor.push(json::object! {
+                line: p.line_no,
+                start: p.start.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                end: p.end.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                seconds: p.seconds(),
+                reason: p.reason.clone(),
+            });
+        }
+        let mut constellations = json::JsonValue::new_object();
+        for (c, n) in &self.fix.constellations {
+            constellations[c.as_str()] = (*n).into();
+        }
+        let mut wind = json::JsonValue::new_array();
+        for b in &self.wind.series {
+            let _ = wind.push(json::object! {
+                time: b.start.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                samples: b.samples,
+                tws_avg_kts: b.tws_avg(),
+                tws_max_kts: b.tws_max,
+                twa_avg: b.twa_avg(),
+                twd_avg_t: b.twd_avg(),
+            });
+        }
+        let mut readings = json::JsonValue::new_object();
+        for r in self.readings.iter().filter(|r| r.count > 0) {
+            readings[r.name] = json::object! {
+                unit: r.unit,
+                count: r.count,
+                min: r.min,
+                avg: r.avg(),
+                max: r.max,
+            };
+        }
+        let mut vessels = json::JsonValue::new_array();
+        for t in self.ais.values() {
+            let _ = vessels.push(json::object! {
+                mmsi: t.mmsi,
+                name: if t.name.is_empty() { None } else { Some(t.name.clone()) },
+                call_sign: if t.call_sign.is_empty() { None } else { Some(t.call_sign.clone()) },
+                class: if t.class_b { "B" } else { "A" },
+                ship_type: t.ship_type,
+                reports: t.reports,
+                first_seen: t.first_seen.map(|t| t.format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+                last_seen: t.last_seen.map(|t| t.format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+                closest_nm: t.closest.map(|(m, _)| nautical_miles(m)),
+                closest_at: t.closest.and_then(|(_, at)| at).map(|t| t.format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+            });
+        }
+        let mut errors = json::JsonValue::new_array();
+        for e in &self.errors {
+            let _ = errors.push(json::object! { line: e.line_no, message: e.message.clone() });
+        }
+        let report = json::object! {
+            start_time: self.start_time.map(|t| t.format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+            end_time: self.end_time.map(|t| t.format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+            distance_km: self.distance.filtered_m / 1000.0,
+            distance_nm: nautical_miles(self.distance.filtered_m),
+            raw_distance_km: self.distance.raw_m / 1000.0,
+            raw_distance_nm: nautical_miles(self.distance.raw_m),
+            distance_method: self.distance.method.name(),
+            outlier_fixes: self.distance.outliers,
+            lines: self.lines,
+            places: places,
+            sentences: sentences,
+            totals: json::object! {
+                nmea: nmea,
+                proprietary: proprietary,
+                ais: ais,
+                non_nmea: self.non_nmea,
+            },
+            events: events,
+            fix_quality: json::object! {
+                interval: self.fix.interval,
+                max_hdop: self.fix.max_hdop,
+                series: series,
+                poor_periods: poor,
+                constellations: constellations,
+                excluded_fixes: if self.exclude_poor_fixes { Some(self.fix.excluded) } else { None },
+            },
+            true_wind: json::object! {
+                interval: self.wind.interval,
+                computed: self.wind.computed,
+                measured: self.wind.measured,
+                series: wind,
+            },
+            readings: readings,
+            ais_vessels: vessels,
+            errors: errors,
+        };
+        report.pretty(2) + "\n"
+    }
+
+    // One CSV row per fact, with a "record" column saying what sort of fact it
+    // is. Columns that don't apply to a record are left empty.
+    fn to_csv(&self) -> String {
+        let mut out = CSV_COLUMNS.join(",") + "\n";
+        out += &csv_row("start", &[("time", csv_time(self.start_time))]);
+        out += &csv_row("end", &[("time", csv_time(self.end_time))]);
+        out += &csv_row(
+            "distance",
+            &[
+                ("distance_km", format!("{:.3}", self.distance.filtered_m / 1000.0)),
+                ("count", self.distance.outliers.to_string()),
+                ("kind", self.distance.method.name().to_string()),
+                ("text", "filtered".to_string()),
+            ],
+        );
+        out += &csv_row(
+            "distance",
+            &[
+                ("distance_km", format!("{:.3}", self.distance.raw_m / 1000.0)),
+                ("kind", self.distance.method.name().to_string()),
+                ("text", "raw".to_string()),
+            ],
+        );
+        out += &csv_row("lines", &[("count", self.lines.to_string())]);
+        out += &csv_row("non_nmea", &[("count", self.non_nmea.to_string())]);
+        for p in &self.places {
+            out += &csv_row(
+                "place",
+                &[
+                    ("time", p.utc.clone()),
+                    ("lat", format!("{:.6}", p.lat)),
+                    ("lon", format!("{:.6}", p.lon)),
+                    ("distance_km", format!("{:.3}", p.distance_m / 1000.0)),
+                    ("text", p.place.clone()),
+                ],
+            );
+        }
+        for (tag, st) in &self.inventory {
+            out += &csv_row(
+                "sentence",
+                &[
+                    ("time", csv_time(st.first)),
+                    ("end_time", csv_time(st.last)),
+                    ("count", st.count.to_string()),
+                    ("avg_hz", st.avg_hz().map_or(String::new(), |hz| format!("{:.3}", hz))),
+                    ("max_hz", st.max_per_second.to_string()),
+                    ("kind", st.kind.name().to_string()),
+                    ("text", tag.clone()),
+                ],
+            );
+        }
+        for b in &self.fix.series {
+            out += &csv_row(
+                "fix",
+                &[
+                    ("time", b.start.format("%Y-%m-%d %H:%M:%S").to_string()),
+                    ("count", b.fixes.to_string()),
+                    ("sats", b.sats_min().map_or(String::new(), |n| n.to_string())),
+                    ("hdop", b.hdop_avg().map_or(String::new(), |h| format!("{:.2}", h))),
+                    ("pdop", b.pdop_avg().map_or(String::new(), |p| format!("{:.2}", p))),
+                    ("kind", b.fix_type().to_string()),
+                    ("text", b.in_view_str()),
+                ],
+            );
+        }
+        for p in &self.fix.poor {
+            out += &csv_row(
+                "poor_fix",
+                &[
+                    ("time", p.start.format("%Y-%m-%d %H:%M:%S").to_string()),
+                    ("end_time", p.end.format("%Y-%m-%d %H:%M:%S").to_string()),
+                    ("line", p.line_no.to_string()),
+                    ("seconds", format!("{:.1}", p.seconds())),
+                    ("text", p.reason.clone()),
+                ],
+            );
+        }
+        for (c, n) in &self.fix.constellations {
+            out += &csv_row("constellation", &[("count", n.to_string()), ("text", c.clone())]);
+        }
+        for b in &self.wind.series {
+            out += &csv_row(
+                "true_wind",
+                &[
+                    ("time", b.start.format("%Y-%m-%d %H:%M:%S").to_string()),
+                    ("count", b.samples.to_string()),
+                    ("tws_kts", format!("{:.1}", b.tws_avg())),
+                    ("twa", format!("{:.0}", b.twa_avg())),
+                    ("twd", b.twd_avg().map_or(String::new(), |d| format!("{:.0}", d))),
+                    ("text", format!("max {:.1} kts", b.tws_max)),
+                ],
+            );
+        }
+        for r in self.readings.iter().filter(|r| r.count > 0) {
+            out += &csv_row(
+                "reading",
+                &[
+                    ("count", r.count.to_string()),
+                    ("min", format!("{:.2}", r.min)),
+                    ("avg", format!("{:.2}", r.avg())),
+                    ("max", format!("{:.2}", r.max)),
+                    ("kind", r.name.to_string()),
+                    ("text", r.unit.to_string()),
+                ],
+            );
+        }
+        for t in self.ais.values() {
+            out += &csv_row(
+                "ais_vessel",
+                &[
+                    ("time", csv_time(t.closest.and_then(|(_, at)| at))),
+                    ("count", t.reports.to_string()),
+                    ("distance_km", t.closest.map_or(String::new(), |(m, _)| format!("{:.3}", m / 1000.0))),
+                    ("mmsi", t.mmsi.to_string()),
+                    ("kind", if t.class_b { "B" } else { "A" }.to_string()),
+                    ("text", t.name.clone()),
+                ],
+            );
+        }
+        for e in &self.events {
+            out += &csv_row(
+                e.kind.name(),
+                &[
+                    ("time", csv_time(e.time)),
+                    ("line", e.line_no.to_string()),
+                    ("seconds", format!("{:.1}", e.seconds)),
+                    ("text", e.message.clone()),
+                ],
+            );
+        }
+        for e in &self.errors {
+            out += &csv_row("error", &[("line", e.line_no.to_string()), ("text", e.message.clone())]);
+        }
+        out
+    }
+}
+
+impl SentenceStats {
+    // Average rate over the time the sentence was seen, None if we never saw it
+    // over more than an instant.
+    pub fn avg_hz(&self) -> Option<f64> {
+        let span = (self.last? - self.first?).num_milliseconds() as f64 / 1000.0;
+        if span > 0.0 {
+            Some((self.count - 1) as f64 / span)
+        } else {
+            None
+        }
+    }
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::Gap => "gap",
+            EventKind::Backwards => "backwards",
+            EventKind::Silence => "silence",
+            EventKind::Stopped => "stopped",
+        }
+    }
+}
+
+impl Kind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Nmea => "NMEA",
+            Kind::Proprietary => "proprietary",
+            Kind::Ais => "AIS",
+        }
+    }
+}
+
+// For "fix" records sats is the least satellites used, hdop and pdop are averages,
+// for "true_wind" records tws_kts, twa and twd are averages, "reading" records
+// have the reading in kind and its units in text, and "ais_vessel" records have
+// the closest approach in distance_km and when it was in time
+const CSV_COLUMNS: [&str; 23] = [
+    "record", "time", "end_time", "line", "lat", "lon", "distance_km", "count", "avg_hz", "max_hz", "seconds", "sats",
+    "hdop", "pdop", "tws_kts", "twa", "twd", "min", "avg", "max", "mmsi", "kind", "text",
+];
+
+fn csv_row(record: &str, values: &[(&str, String)]) -> String {
+    let mut row = vec![record.to_string()];
+    for column in &CSV_COLUMNS[1..] {
+        row.push(values.iter().find(|(c, _)| c == column).map_or(String::new(), |(_, v)| csv_quote(v)));
+    }
+    row.join(",") + "\n"
+}
+
+fn nautical_miles(meters: f64) -> f64 {
+    meters / 1000.0 * 0.5399568
+}
+
+fn time_str(t: Option<NaiveDateTime>) -> String {
+    match t {
+        Some(t) => t.format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => "(none found)".to_string(),
+    }
+}
+
+fn csv_time(t: Option<NaiveDateTime>) -> String {
+    t.map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or_default()
+}
+
+// Quote a CSV field if it needs it
+fn csv_quote(s: &str) -> String {
+    if s.contains(',') || s.contains('"') || s.contains('\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use std::io::Write;
+
+    // The scanner reads a file, so put the lines in one first. The fixes have
+    // no position in them so nothing goes off to look up where we are.
+    fn scan_lines(name: &str, lines: &[String]) -> ScanReport {
+        let path = std::env::temp_dir().join(format!("nmea_player_scan_{}_{}.nmea", std::process::id(), name));
+        let mut file = File::create(&path).unwrap();
+        for line in lines {
+            write!(file, "{}\r\n", line).unwrap();
+        }
+        let report = scan(File::open(&path).unwrap(), &ScanOptions::default());
+        std::fs::remove_file(&path).unwrap();
+        report.unwrap()
+    }
+
+    fn gga(hms: &str) -> String {
+        format!("$GPGGA,{},,,,,1,8,0.9,,M,,M", hms)
+    }
+
+    fn at(h: u32, m: u32, s: u32) -> Option<NaiveDateTime> {
+        NaiveDate::from_ymd_opt(2021, 2, 22).unwrap().and_hms_opt(h, m, s)
+    }
+
+    fn events(report: &ScanReport, kind: &str) -> Vec<(u64, Option<NaiveDateTime>, f64)> {
+        report.events.iter().filter(|e| e.kind.name() == kind).map(|e| (e.line_no, e.time, e.seconds)).collect()
+    }
+
+    #[test]
+    fn gap_in_the_time_stamps() {
+        let lines = ["$GPZDA,120000,22,02,2021,00,00".to_string(), gga("120000"), gga("120001"), gga("120010"), gga("120011")];
+        let report = scan_lines("gap", &lines);
+        assert_eq!(events(&report, "gap"), vec![(4, at(12, 0, 10), 9.0)]);
+        assert!(events(&report, "backwards").is_empty());
+    }
+
+    #[test]
+    fn time_going_backwards() {
+        let lines = ["$GPZDA,120000,22,02,2021,00,00".to_string(), gga("120004"), gga("120005"), gga("120002"), gga("120003")];
+        let report = scan_lines("backwards", &lines);
+        assert_eq!(events(&report, "backwards"), vec![(4, at(12, 0, 2), 3.0)]);
+        assert!(events(&report, "gap").is_empty());
+    }
+
+    #[test]
+    fn one_sentence_going_quiet() {
+        // GGA every second, but the depth drops out for 35 s
+        let mut lines = vec!["$GPZDA,120000,22,02,2021,00,00".to_string()];
+        for s in 0..40 {
+            lines.push(gga(&format!("1200{:02}", s)));
+            if s == 0 || s == 35 {
+                lines.push("$SDDPT,5.2,0.0".to_string());
+            }
+        }
+        let report = scan_lines("silence", &lines);
+        assert_eq!(events(&report, "silence"), vec![(39, at(12, 0, 35), 35.0)]);
+        assert!(report.events.iter().filter(|e| e.kind.name() == "silence").all(|e| e.message.starts_with("SDDPT")));
+        // The ZDA only came once, so it stopped, at its line and time
+        assert_eq!(events(&report, "stopped"), vec![(1, at(12, 0, 0), 39.0)]);
+        assert!(events(&report, "gap").is_empty());
+    }
 }
@@ -0,0 +1,108 @@
+use chrono::NaiveDateTime;
+use geoutils::Location;
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Method {
+    // Great circle on a sphere. Quick and plenty good for boats.
+    Haversine,
+    // Vincenty's formula on the WGS-84 ellipsoid. A bit slower but accurate to the millimetre.
+    Vincenty,
+}
+
+impl Method {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Method::Haversine => "haversine",
+            Method::Vincenty => "Vincenty",
+        }
+    }
+}
+
+// Adds up the distance travelled from one GPS fix to the next, twice over. The
+// raw total takes every fix as it comes. The filtered total throws out fixes
+// that would need an impossible speed to get to (a single glitch 40 nm away
+// would otherwise add 80 nm), and ignores the jitter of a boat that isn't
+// going anywhere by only counting movement once it adds up to more than a few
+// metres from the last point counted.
+pub struct Distance {
+    pub method: Method,
+    pub max_speed_kts: f64,
+    pub jitter_m: f64,
+    pub raw_m: f64,
+    pub filtered_m: f64,
+    pub outliers: u64,
+    last_raw: Option<Location>,
+    last_good: Option<(Location, Option<NaiveDateTime>)>,
+    anchor: Option<Location>,
+    rejected_in_a_row: u64,
+}
+
+// If this many fixes in a row look like outliers it's more likely that the fix
+// we are comparing them with was the bad one, so we start again from here.
+const MAX_REJECTED_IN_A_ROW: u64 = 5;
+
+impl Distance {
+    pub fn new(method: Method, max_speed_kts: f64, jitter_m: f64) -> Distance {
+        Distance {
+            method,
+            max_speed_kts,
+            jitter_m,
+            raw_m: 0.0,
+            filtered_m: 0.0,
+            outliers: 0,
+            last_raw: None,
+            last_good: None,
+            anchor: None,
+            rejected_in_a_row: 0,
+        }
+    }
+
+    pub fn between(&self, a: &Location, b: &Location) -> f64 {
+        match self.method {
+            Method::Haversine => a.haversine_distance_to(b).meters(),
+            // Vincenty doesn't always converge for points on opposite sides of the earth
+            Method::Vincenty => a
+                .distance_to(b)
+                .map_or_else(|_| a.haversine_distance_to(b).meters(), |d| d.meters()),
+        }
+    }
+
+    // Take the next fix. Returns false if it was thrown out as an outlier.
+    pub fn add(&mut self, here: Location, now: Option<NaiveDateTime>) -> bool {
+        if let Some(there) = self.last_raw {
+            self.raw_m += self.between(&there, &here);
+        }
+        self.last_raw = Some(here);
+
+        if let Some((good, then)) = self.last_good {
+            let d = self.between(&good, &here);
+            // Time stamps only go down to the second, so give every step at least a second
+            let secs = match (then, now) {
+                (Some(then), Some(now)) => ((now - then).num_milliseconds() as f64 / 1000.0).max(1.0),
+                _ => 1.0,
+            };
+            let knots = d / secs * 3600.0 / 1852.0;
+            if knots > self.max_speed_kts && self.rejected_in_a_row < MAX_REJECTED_IN_A_ROW {
+                self.outliers += 1;
+                self.rejected_in_a_row += 1;
+                return false;
+            }
+        }
+        let starting_again = self.rejected_in_a_row >= MAX_REJECTED_IN_A_ROW;
+        self.rejected_in_a_row = 0;
+        self.last_good = Some((here, now));
+
+        match self.anchor {
+            Some(_) if starting_again => self.anchor = Some(here),
+            Some(anchor) => {
+                let d = self.between(&anchor, &here);
+                if d >= self.jitter_m {
+                    self.filtered_m += d;
+                    self.anchor = Some(here);
+                }
+            }
+            None => self.anchor = Some(here),
+        }
+        true
+    }
+}
@@ -0,0 +1,254 @@
+use chrono::{Duration, NaiveDateTime, Timelike};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+// Keeps an eye on how good the GPS fix was through the file, from the fix type,
+// satellite count and HDOP in GGA, PDOP in GSA and satellites in view in GSV.
+// The results are summarised every so often (a time series) and any stretch
+// where the fix was too poor to trust is kept as a "poor period".
+pub struct FixQuality {
+    pub interval: f64,
+    pub max_hdop: f64,
+    pub series: Vec<FixBucket>,
+    pub poor: Vec<PoorPeriod>,
+    // The most satellites we saw in view at once, by constellation
+    pub constellations: BTreeMap<String, u32>,
+    pub excluded: u64,
+    poor_since: Option<PoorPeriod>,
+}
+
+pub struct FixBucket {
+    pub start: NaiveDateTime,
+    pub fixes: u64,
+    pub fix_types: BTreeMap<&'static str, u64>,
+    pub sats_min: u32,
+    sats_sum: u64,
+    sats_n: u64,
+    hdop_sum: f64,
+    hdop_n: u64,
+    pub hdop_max: f64,
+    pdop_sum: f64,
+    pdop_n: u64,
+    pub in_view: BTreeMap<String, u32>,
+}
+
+pub struct PoorPeriod {
+    pub line_no: u64,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub reason: String,
+}
+
+impl FixQuality {
+    pub fn new(interval: f64, max_hdop: f64) -> FixQuality {
+        FixQuality {
+            interval,
+            max_hdop,
+            series: vec![],
+            poor: vec![],
+            constellations: BTreeMap::new(),
+            excluded: 0,
+            poor_since: None,
+        }
+    }
+
+    // $GPGGA,020659.21,4937.8509,N,12401.4384,W,2,9,0.83,,M,,M*44
+    // Returns false when the fix in this GGA is too poor to trust.
+    pub fn gga(&mut self, fields: &[&str], now: Option<NaiveDateTime>, line_no: u64) -> bool {
+        let quality: u32 = fields.get(6).and_then(|f| FromStr::from_str(f).ok()).unwrap_or(0);
+        let sats: Option<u32> = fields.get(7).and_then(|f| FromStr::from_str(f).ok());
+        let hdop: Option<f64> = fields.get(8).and_then(|f| FromStr::from_str(f).ok());
+        let reason = if quality == 0 {
+            Some("no fix".to_string())
+        } else {
+            hdop.filter(|h| *h > self.max_hdop).map(|h| format!("HDOP {:.1}", h))
+        };
+        if let Some(now) = now {
+            let bucket = self.bucket(now);
+            bucket.fixes += 1;
+            *bucket.fix_types.entry(fix_name(quality)).or_insert(0) += 1;
+            if let Some(sats) = sats {
+                bucket.sats_min = bucket.sats_min.min(sats);
+                bucket.sats_sum += sats as u64;
+                bucket.sats_n += 1;
+            }
+            if let Some(hdop) = hdop {
+                bucket.hdop_sum += hdop;
+                bucket.hdop_n += 1;
+                bucket.hdop_max = bucket.hdop_max.max(hdop);
+            }
+            match (&reason, self.poor_since.as_mut()) {
+                (Some(_), Some(period)) => period.end = now,
+                (Some(reason), None) => {
+                    self.poor_since = Some(PoorPeriod {
+                        line_no,
+                        start: now,
+                        end: now,
+                        reason: reason.clone(),
+                    })
+                }
+                (None, _) => self.poor.extend(self.poor_since.take()),
+            }
+        }
+        reason.is_none()
+    }
+
+    // $GPGSA,A,3,04,05,,09,12,,,24,,,,,2.5,1.3,2.1*39
+    pub fn gsa(&mut self, fields: &[&str], now: Option<NaiveDateTime>) {
+        let pdop: Option<f64> = fields.get(15).and_then(|f| FromStr::from_str(f).ok());
+        if let (Some(now), Some(pdop)) = (now, pdop) {
+            let bucket = self.bucket(now);
+            bucket.pdop_sum += pdop;
+            bucket.pdop_n += 1;
+        }
+    }
+
+    // $GLGSV,2,1,08,65,27,048,32,66,71,327,40,...*6B
+    // The talker tells us which constellation these satellites belong to.
+    pub fn gsv(&mut self, talker: &str, fields: &[&str], now: Option<NaiveDateTime>) {
+        let in_view: u32 = match fields.get(3).and_then(|f| FromStr::from_str(f).ok()) {
+            Some(n) => n,
+            None => return,
+        };
+        let name = constellation_name(talker).to_string();
+        let most = self.constellations.entry(name.clone()).or_insert(0);
+        *most = (*most).max(in_view);
+        if let Some(now) = now {
+            let bucket = self.bucket(now);
+            let most = bucket.in_view.entry(name).or_insert(0);
+            *most = (*most).max(in_view);
+        }
+    }
+
+    // Close off a poor period that ran right to the end of the file
+    pub fn finish(&mut self) {
+        self.poor.extend(self.poor_since.take());
+    }
+
+    // The time series bucket that this time falls into, making new ones as needed
+    fn bucket(&mut self, now: NaiveDateTime) -> &mut FixBucket {
+        let secs = (self.interval.max(1.0)) as i64;
+        let since_midnight = now.num_seconds_from_midnight() as i64;
+        let start = now.date().and_hms_opt(0, 0, 0).unwrap() + Duration::seconds(since_midnight / secs * secs);
+        if self.series.last().map(|b| b.start) != Some(start) {
+            self.series.push(FixBucket {
+                start,
+                fixes: 0,
+                fix_types: BTreeMap::new(),
+                sats_min: u32::MAX,
+                sats_sum: 0,
+                sats_n: 0,
+                hdop_sum: 0.0,
+                hdop_n: 0,
+                hdop_max: 0.0,
+                pdop_sum: 0.0,
+                pdop_n: 0,
+                in_view: BTreeMap::new(),
+            });
+        }
+        self.series.last_mut().unwrap()
+    }
+}
+
+impl FixBucket {
+    // The fix type we had most of the time during this bucket
+    pub fn fix_type(&self) -> &'static str {
+        self.fix_types.iter().max_by_key(|(_, n)| **n).map_or("-", |(t, _)| t)
+    }
+
+    pub fn sats_min(&self) -> Option<u32> {
+        if self.sats_min == u32::MAX {
+            None
+        } else {
+            Some(self.sats_min)
+        }
+    }
+
+    pub fn sats_avg(&self) -> Option<f64> {
+        if self.sats_n > 0 {
+            Some(self.sats_sum as f64 / self.sats_n as f64)
+        } else {
+            None
+        }
+    }
+
+    pub fn hdop_avg(&self) -> Option<f64> {
+        if self.hdop_n > 0 {
+            Some(self.hdop_sum / self.hdop_n as f64)
+        } else {
+            None
+        }
+    }
+
+    pub fn pdop_avg(&self) -> Option<f64> {
+        if self.pdop_n > 0 {
+            Some(self.pdop_sum / self.pdop_n as f64)
+        } else {
+            None
+        }
+    }
+
+    pub fn in_view_str(&self) -> String {
+        let list: Vec<String> = self.in_view.iter().map(|(c, n)| format!("{} {}", c, n)).collect();
+        list.join(", ")
+    }
+}
+
+impl PoorPeriod {
+    pub fn seconds(&self) -> f64 {
+        (self.end - self.start).num_milliseconds() as f64 / 1000.0
+    }
+}
+
+// GGA fix quality indicator
+fn fix_name(quality: u32) -> &'static str {
+    match quality {
+        0 => "none",
+        1 => "GPS",
+        2 => "DGPS",
+        3 => "PPS",
+        4 => "RTK",
+        5 => "RTK float",
+        6 => "estimated",
+        7 => "manual",
+        8 => "simulated",
+        _ => "unknown",
+    }
+}
+
+fn constellation_name(talker: &str) -> &str {
+    match talker {
+        "GP" => "GPS",
+        "GL" => "GLONASS",
+        "GA" => "Galileo",
+        "GB" | "BD" => "BeiDou",
+        "GQ" => "QZSS",
+        "GI" => "NavIC",
+        "GN" => "GNSS",
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn sats_average_only_counts_fixes_that_gave_them() {
+        let mut quality = FixQuality::new(60.0, 5.0);
+        let now = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap().and_hms_opt(12, 0, 0).unwrap();
+        for line in [
+            "$GPGGA,120000,4937.8509,N,12401.4384,W,1,8,0.9,,M,,M",
+            "$GPGGA,120001,4937.8509,N,12401.4384,W,1,,0.9,,M,,M",
+            "$GPGGA,120002,4937.8509,N,12401.4384,W,1,10,0.9,,M,,M",
+        ] {
+            let fields: Vec<&str> = line.split(',').collect();
+            quality.gga(&fields, Some(now), 1);
+        }
+        let bucket = &quality.series[0];
+        assert_eq!(bucket.fixes, 3);
+        assert_eq!(bucket.sats_min(), Some(8));
+        assert_eq!(bucket.sats_avg(), Some(9.0));
+    }
+}
@@ -0,0 +1,59 @@
+use crate::udp_broadcaster::instruments::Readings;
+
+// Lowest, average and highest of each instrument reading over the whole file.
+// Give it the readings from one line at a time (not the latest of everything)
+// so a reading is only counted when a sentence actually carries it. Headings
+// and wind directions go round in a circle and don't average like this, so
+// they aren't in here.
+pub struct ReadingStats {
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub count: u64,
+    pub min: f64,
+    pub max: f64,
+    sum: f64,
+    get: fn(&Readings) -> Option<f64>,
+}
+
+pub fn all() -> Vec<ReadingStats> {
+    vec![
+        stats("STW", "kts", |r| r.stw_kts),
+        stats("SOG", "kts", |r| r.sog_kts),
+        stats("Depth", "m", |r| r.depth_m),
+        stats("Water temp", "°C", |r| r.water_temp_c),
+        stats("Log", "nm", |r| r.log_nm),
+        stats("AWS", "kts", |r| r.aws_kts),
+        stats("AWA", "° off bow", |r| r.awa.map(f64::abs)),
+        stats("Rudder", "° stbd", |r| r.rudder),
+        stats("Heel", "° stbd", |r| r.heel),
+        stats("Pitch", "° up", |r| r.pitch),
+        stats("Pressure", "hPa", |r| r.pressure_hpa),
+    ]
+}
+
+fn stats(name: &'static str, unit: &'static str, get: fn(&Readings) -> Option<f64>) -> ReadingStats {
+    ReadingStats {
+        name,
+        unit,
+        count: 0,
+        min: f64::MAX,
+        max: f64::MIN,
+        sum: 0.0,
+        get,
+    }
+}
+
+impl ReadingStats {
+    pub fn add(&mut self, readings: &Readings) {
+        if let Some(v) = (self.get)(readings) {
+            self.count += 1;
+            self.sum += v;
+            self.min = self.min.min(v);
+            self.max = self.max.max(v);
+        }
+    }
+
+    pub fn avg(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+}
@@ -0,0 +1,100 @@
+use chrono::{Duration, NaiveDateTime, Timelike};
+
+// The true wind through the file as a time series, either as the instruments
+// gave it (MWV true, MWD) or as we worked it out from the apparent wind, boat
+// speed and heading. Directions are averaged as directions, so a wind swinging
+// between 355 and 005 averages out at 000 and not 180.
+pub struct TrueWind {
+    pub interval: f64,
+    pub series: Vec<WindBucket>,
+    pub computed: u64,
+    pub measured: u64,
+}
+
+pub struct WindBucket {
+    pub start: NaiveDateTime,
+    pub samples: u64,
+    tws_sum: f64,
+    pub tws_max: f64,
+    twa_sum: f64,
+    twd_sin: f64,
+    twd_cos: f64,
+    twd_n: u64,
+}
+
+impl TrueWind {
+    pub fn new(interval: f64) -> TrueWind {
+        TrueWind {
+            interval,
+            series: vec![],
+            computed: 0,
+            measured: 0,
+        }
+    }
+
+    pub fn add(&mut self, now: NaiveDateTime, twa: f64, tws: f64, twd: Option<f64>, computed: bool) {
+        if computed {
+            self.computed += 1;
+        } else {
+            self.measured += 1;
+        }
+        let bucket = self.bucket(now);
+        bucket.samples += 1;
+        bucket.tws_sum += tws;
+        bucket.tws_max = bucket.tws_max.max(tws);
+        bucket.twa_sum += twa.abs();
+        if let Some(twd) = twd {
+            bucket.twd_sin += twd.to_radians().sin();
+            bucket.twd_cos += twd.to_radians().cos();
+            bucket.twd_n += 1;
+        }
+    }
+
+    // Where the numbers came from, for the report
+    pub fn source(&self) -> &'static str {
+        match (self.measured > 0, self.computed > 0) {
+            (true, true) => "partly from the instruments, partly worked out from apparent wind, boat speed and heading",
+            (true, false) => "from the instruments",
+            _ => "worked out from apparent wind, boat speed and heading",
+        }
+    }
+
+    // The time series bucket that this time falls into, making new ones as needed
+    fn bucket(&mut self, now: NaiveDateTime) -> &mut WindBucket {
+        let secs = (self.interval.max(1.0)) as i64;
+        let since_midnight = now.num_seconds_from_midnight() as i64;
+        let start = now.date().and_hms_opt(0, 0, 0).unwrap() + Duration::seconds(since_midnight / secs * secs);
+        if self.series.last().map(|b| b.start) != Some(start) {
+            self.series.push(WindBucket {
+                start,
+                samples: 0,
+                tws_sum: 0.0,
+                tws_max: 0.0,
+                twa_sum: 0.0,
+                twd_sin: 0.0,
+                twd_cos: 0.0,
+                twd_n: 0,
+            });
+        }
+        self.series.last_mut().unwrap()
+    }
+}
+
+impl WindBucket {
+    pub fn tws_avg(&self) -> f64 {
+        self.tws_sum / self.samples as f64
+    }
+
+    // How far off the wind we sailed on average, whichever tack we were on
+    pub fn twa_avg(&self) -> f64 {
+        self.twa_sum / self.samples as f64
+    }
+
+    pub fn twd_avg(&self) -> Option<f64> {
+        if self.twd_n > 0 {
+            Some(self.twd_sin.atan2(self.twd_cos).to_degrees().rem_euclid(360.0))
+        } else {
+            None
+        }
+    }
+}
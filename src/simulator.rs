@@ -0,0 +1,277 @@
+use crate::converter::parse_time;
+use crate::geo::{self, LatLon};
+use crate::nmea::build;
+use chrono::{Duration, NaiveDateTime, Timelike, Utc};
+use std::collections::VecDeque;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+// NMEA for a sail we never went on. The boat sets off from the first waypoint
+// and steers straight for each of the others in turn at the speed for that
+// leg, and we work out where it is a step at a time. The wind and depth come
+// from simple models: a wind that can veer steadily and swing back and forth,
+// and a depth that is either the same everywhere or worked out from a grid of
+// soundings. Out comes the sentence set a typical boat has on its bus (ZDA,
+// GGA, RMC, VTG, HDG, MWV and DPT), with ZDA once a second so it plays just
+// like a log file.
+
+#[derive(clap::Args)]
+pub struct SimulateOptions {
+    /// A waypoint to sail to, in order (give --waypoint once for each, at least two; the boat starts at the first)
+    #[arg(long, value_name = "LAT,LON", allow_hyphen_values = true, required = true, num_args = 1)]
+    pub waypoint: Vec<LatLon>,
+
+    /// Boat speed for each leg, in order (the last one is used for the rest of the legs)
+    #[arg(long, value_name = "KNOTS", value_delimiter = ',', default_value = "6")]
+    pub speed: Vec<f64>,
+
+    /// The true wind, as the direction it comes from and its speed
+    #[arg(long, value_name = "DEGREES@KNOTS", default_value = "225@12")]
+    pub wind: Wind,
+
+    /// How much the wind veers (turns clockwise) every hour; negative backs it
+    #[arg(long, value_name = "DEGREES", default_value_t = 0.0, allow_negative_numbers = true)]
+    pub wind_veer: f64,
+
+    /// How far the wind swings either side of its direction
+    #[arg(long, value_name = "DEGREES", default_value_t = 0.0)]
+    pub wind_swing: f64,
+
+    /// How long one swing of the wind takes, back and forth
+    #[arg(long, value_name = "MINUTES", default_value_t = 10.0)]
+    pub wind_period: f64,
+
+    /// The depth everywhere, or a CSV file of soundings (lat,lon,depth in metres) to work it out from
+    #[arg(long, value_name = "METRES | CSV_FILE", default_value = "20")]
+    pub depth: DepthArg,
+
+    /// Magnetic variation for HDG, east positive
+    #[arg(long, value_name = "DEGREES", default_value_t = 0.0, allow_negative_numbers = true)]
+    pub variation: f64,
+
+    /// When the sail starts [default: now]
+    #[arg(long, value_name = "yyyy-mm-ddThh:mm:ss", value_parser = parse_start)]
+    pub start: Option<NaiveDateTime>,
+
+    /// GGA/RMC/VTG/HDG/MWV/DPT sentences a second
+    #[arg(long, default_value_t = 1.0, value_name = "HZ")]
+    pub rate: f64,
+
+    /// Play this many times faster than real time
+    #[arg(long, default_value_t = 1.0, value_name = "FACTOR")]
+    pub speedup: f64,
+}
+
+fn parse_start(s: &str) -> Result<NaiveDateTime, String> {
+    parse_time(s).ok_or_else(|| format!("'{}' isn't a time, use yyyy-mm-ddThh:mm:ss", s))
+}
+
+#[derive(Clone, Copy)]
+pub struct Wind {
+    pub direction: f64,
+    pub speed: f64,
+}
+
+impl FromStr for Wind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Wind, String> {
+        let bad = || format!("'{}' should be a direction and speed like 225@12", s);
+        let (direction, speed) = s.split_once('@').ok_or_else(bad)?;
+        Ok(Wind {
+            direction: direction.trim().parse().map_err(|_| bad())?,
+            speed: speed.trim().parse().map_err(|_| bad())?,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub enum DepthArg {
+    Constant(f64),
+    Grid(PathBuf),
+}
+
+impl FromStr for DepthArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DepthArg, String> {
+        Ok(match s.parse() {
+            Ok(metres) => DepthArg::Constant(metres),
+            Err(_) => DepthArg::Grid(PathBuf::from(s)),
+        })
+    }
+}
+
+enum Depth {
+    Constant(f64),
+    Soundings(Vec<(f64, f64, f64)>),
+}
+
+// How many of the nearest soundings go into the depth at a point
+const NEAREST_SOUNDINGS: usize = 4;
+
+impl Depth {
+    fn load(arg: &DepthArg) -> io::Result<Depth> {
+        let path = match arg {
+            DepthArg::Constant(metres) => return Ok(Depth::Constant(*metres)),
+            DepthArg::Grid(path) => path,
+        };
+        let text = std::fs::read_to_string(path).map_err(|e| io::Error::other(format!("{}: {}", path.display(), e)))?;
+        // Anything that isn't three numbers (a header, say) is skipped
+        let soundings: Vec<(f64, f64, f64)> = text
+            .lines()
+            .filter_map(|line| {
+                let v: Vec<f64> = line.split(',').filter_map(|f| f.trim().parse().ok()).collect();
+                (v.len() == 3).then(|| (v[0], v[1], v[2]))
+            })
+            .collect();
+        if soundings.is_empty() {
+            return Err(io::Error::other(format!("{}: no lat,lon,depth lines in it", path.display())));
+        }
+        Ok(Depth::Soundings(soundings))
+    }
+
+    // The nearest few soundings, weighted by how close they are
+    fn at(&self, lat: f64, lon: f64) -> f64 {
+        let soundings = match self {
+            Depth::Constant(metres) => return *metres,
+            Depth::Soundings(soundings) => soundings,
+        };
+        let mut near: Vec<(f64, f64)> =
+            soundings.iter().map(|(slat, slon, d)| (geo::distance_m(lat, lon, *slat, *slon), *d)).collect();
+        near.sort_by(|a, b| a.0.total_cmp(&b.0));
+        near.truncate(NEAREST_SOUNDINGS);
+        if near[0].0 < 1.0 {
+            return near[0].1;
+        }
+        let weights: f64 = near.iter().map(|(m, _)| 1.0 / (m * m)).sum();
+        near.iter().map(|(m, d)| d / (m * m)).sum::<f64>() / weights
+    }
+}
+
+pub struct Simulation {
+    waypoints: Vec<LatLon>,
+    speeds: Vec<f64>,
+    wind: Wind,
+    veer: f64,
+    swing: f64,
+    period: f64,
+    depth: Depth,
+    variation: f64,
+    start: NaiveDateTime,
+    step: Duration,
+    // Where we are, the time, and the waypoint we're heading for
+    lat: f64,
+    lon: f64,
+    t: NaiveDateTime,
+    next: usize,
+    last_zda: Option<NaiveDateTime>,
+    pending: VecDeque<String>,
+}
+
+impl Simulation {
+    pub fn new(o: &SimulateOptions) -> io::Result<Simulation> {
+        if o.waypoint.len() < 2 {
+            return Err(io::Error::other("give at least two --waypoint, one to start from and one to sail to"));
+        }
+        if o.rate <= 0.0 || o.speedup <= 0.0 {
+            return Err(io::Error::other("--rate and --speedup have to be more than zero"));
+        }
+        if o.speed.iter().any(|s| *s <= 0.0) {
+            return Err(io::Error::other("the --speed for every leg has to be more than zero"));
+        }
+        let start = o.start.unwrap_or_else(|| Utc::now().naive_utc().with_nanosecond(0).unwrap());
+        Ok(Simulation {
+            waypoints: o.waypoint.clone(),
+            speeds: o.speed.clone(),
+            wind: o.wind,
+            veer: o.wind_veer,
+            swing: o.wind_swing,
+            period: o.wind_period,
+            depth: Depth::load(&o.depth)?,
+            variation: o.variation,
+            start,
+            step: Duration::milliseconds(((1000.0 / o.rate).round() as i64).max(10)),
+            lat: o.waypoint[0].lat,
+            lon: o.waypoint[0].lon,
+            t: start,
+            next: 1,
+            last_zda: None,
+            pending: VecDeque::new(),
+        })
+    }
+
+    // The true wind at a time: the direction it comes from and its speed
+    fn wind_at(&self, t: NaiveDateTime) -> (f64, f64) {
+        let minutes = (t - self.start).num_milliseconds() as f64 / 60_000.0;
+        let swing = if self.period > 0.0 {
+            self.swing * (minutes / self.period * std::f64::consts::TAU).sin()
+        } else {
+            0.0
+        };
+        ((self.wind.direction + self.veer * minutes / 60.0 + swing).rem_euclid(360.0), self.wind.speed)
+    }
+
+    // One step along: the sentences for where we are now, then move on.
+    // Returns false once we are at the last waypoint.
+    fn advance(&mut self) -> bool {
+        if self.next >= self.waypoints.len() {
+            return false;
+        }
+        let leg = self.next - 1;
+        let speed = self.speeds.get(leg).or(self.speeds.last()).copied().unwrap_or(6.0);
+        let to = self.waypoints[self.next];
+        let heading = geo::bearing(self.lat, self.lon, to.lat, to.lon);
+        let t = self.t;
+        if self.last_zda.and_then(|z| z.with_nanosecond(0)) != t.with_nanosecond(0) {
+            self.pending.push_back(build::zda("GP", t));
+            self.last_zda = Some(t);
+        }
+        let (twd, tws) = self.wind_at(t);
+        let twa = twd - heading;
+        let (awa, aws) = apparent_wind(twa, tws, speed);
+        self.pending.extend([
+            build::gga("GP", t, self.lat, self.lon),
+            build::rmc("GP", t, self.lat, self.lon, speed, heading),
+            build::vtg("GP", speed, heading),
+            build::hdg("HC", heading - self.variation, self.variation),
+            build::mwv("WI", awa, "R", aws),
+            build::mwv("WI", twa, "T", tws),
+            build::dpt("SD", self.depth.at(self.lat, self.lon), 0.0),
+        ]);
+        // Move on, turning for the next waypoint if we get to this one
+        let run = speed * 1852.0 * self.step.num_milliseconds() as f64 / 3_600_000.0;
+        let left = geo::distance_m(self.lat, self.lon, to.lat, to.lon);
+        if run >= left {
+            (self.lat, self.lon) = (to.lat, to.lon);
+            self.next += 1;
+        } else {
+            (self.lat, self.lon) = geo::destination(self.lat, self.lon, heading, run);
+        }
+        self.t += self.step;
+        true
+    }
+}
+
+impl Iterator for Simulation {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        while self.pending.is_empty() {
+            if !self.advance() {
+                return None;
+            }
+        }
+        self.pending.pop_front()
+    }
+}
+
+// The wind we feel: the true wind plus the wind made by our own speed, which
+// always blows straight down the boat from the bow. Gives back the apparent
+// wind angle off the bow and its speed.
+fn apparent_wind(twa: f64, tws: f64, speed: f64) -> (f64, f64) {
+    let ahead = tws * twa.to_radians().cos() + speed;
+    let across = tws * twa.to_radians().sin();
+    (across.atan2(ahead).to_degrees(), ahead.hypot(across))
+}
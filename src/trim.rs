@@ -0,0 +1,207 @@
+use crate::converter::parse_time;
+use crate::nmea;
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+// Cuts a stretch out of a NMEA file by the time in the file, for when we only
+// want the race out of a whole day's log. Lines are copied exactly as they are
+// in the file (line endings, bad checksums and all) so nothing is lost. Every
+// line belongs to the last time seen before it, as the scanner does it.
+
+// A full date and time, or just a time of day on the first day in the file
+#[derive(Clone, Copy)]
+pub enum TimeSpec {
+    At(NaiveDateTime),
+    TimeOfDay(NaiveTime),
+}
+
+impl FromStr for TimeSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TimeSpec, String> {
+        if let Some(t) = parse_time(s) {
+            return Ok(TimeSpec::At(t));
+        }
+        NaiveTime::parse_from_str(s, "%H:%M:%S%.f")
+            .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M"))
+            .map(TimeSpec::TimeOfDay)
+            .map_err(|_| format!("'{}' isn't a time, use hh:mm[:ss] or yyyy-mm-ddThh:mm:ss", s))
+    }
+}
+
+impl TimeSpec {
+    pub fn on(&self, date: NaiveDate) -> NaiveDateTime {
+        match self {
+            TimeSpec::At(t) => *t,
+            TimeSpec::TimeOfDay(t) => date.and_time(*t),
+        }
+    }
+}
+
+pub struct TrimSummary {
+    pub lines_read: u64,
+    pub lines_written: u64,
+    pub primed: usize,
+}
+
+// Copy the lines with file times from "from" up to and including "until". With
+// prime, the last ZDA, RMC and GGA from before the cut go out first so that the
+// trimmed file starts off with a time and a position.
+pub fn trim(
+    file: File,
+    out: &mut dyn Write,
+    from: Option<TimeSpec>,
+    until: Option<TimeSpec>,
+    prime: bool,
+) -> io::Result<TrimSummary> {
+    let mut reader = io::BufReader::new(file);
+    let mut clock = nmea::FileClock::new();
+    let mut range: Option<(Option<NaiveDateTime>, Option<NaiveDateTime>)> = None;
+    // The last ZDA, RMC and GGA, exactly as they were in the file
+    let mut primers: [Option<Vec<u8>>; 3] = [None, None, None];
+    let mut started = false;
+    let mut summary = TrimSummary {
+        lines_read: 0,
+        lines_written: 0,
+        primed: 0,
+    };
+    let mut raw = vec![];
+    loop {
+        raw.clear();
+        if reader.read_until(b'\n', &mut raw)? == 0 {
+            break;
+        }
+        summary.lines_read += 1;
+        let text = String::from_utf8_lossy(&raw);
+        let line = text.trim_end();
+        let now = clock.update(line);
+        // Times of day are taken to be on the first date in the file. If the
+        // end comes before the start the race must have gone past midnight.
+        if let (None, Some(now)) = (range, now) {
+            let start = from.map(|f| f.on(now.date()));
+            let mut end = until.map(|u| u.on(now.date()));
+            if let (Some(s), Some(e)) = (start, end) {
+                if e < s && matches!(until, Some(TimeSpec::TimeOfDay(_))) {
+                    end = Some(e + Duration::days(1));
+                }
+            }
+            range = Some((start, end));
+        }
+        let wanted = match (range, now) {
+            (Some((start, end)), Some(now)) => {
+                start.is_none_or(|s| now >= s) && end.is_none_or(|e| now <= e)
+            }
+            // Before the first time stamp we can only tell if there is no start
+            _ => from.is_none(),
+        };
+        if !wanted {
+            let slot = match nmea::sentence_type(line) {
+                Some("ZDA") => Some(0),
+                Some("RMC") => Some(1),
+                Some("GGA") => Some(2),
+                _ => None,
+            };
+            if let Some(i) = slot.filter(|_| !started) {
+                primers[i] = Some(raw.clone());
+            }
+            continue;
+        }
+        if !started && prime {
+            for primer in primers.iter().flatten() {
+                out.write_all(primer)?;
+                summary.primed += 1;
+            }
+        }
+        started = true;
+        out.write_all(&raw)?;
+        summary.lines_written += 1;
+    }
+    // Without a date there's nothing to put a time of day on, or to compare a
+    // full date and time with
+    if range.is_none() && (from.is_some() || until.is_some()) {
+        return Err(io::Error::other(
+            "the file has no dated times in it (ZDA, or RMC with a date), so --from and --until can't be found",
+        ));
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // trim reads a file, so put the lines in one first. They go in with CRLF
+    // so we can see that the line endings come through.
+    fn trim_lines(name: &str, lines: &[&str], from: &str, until: &str, prime: bool) -> io::Result<(Vec<String>, TrimSummary)> {
+        let path = std::env::temp_dir().join(format!("nmea_player_trim_{}_{}.nmea", std::process::id(), name));
+        std::fs::write(&path, lines.iter().map(|l| format!("{}\r\n", l)).collect::<String>()).unwrap();
+        let spec = |s: &str| (!s.is_empty()).then(|| TimeSpec::from_str(s).unwrap());
+        let mut out = vec![];
+        let summary = trim(File::open(&path).unwrap(), &mut out, spec(from), spec(until), prime);
+        std::fs::remove_file(&path).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.is_empty() || out.ends_with("\r\n"));
+        Ok((out.lines().map(|l| l.trim_end().to_string()).collect(), summary?))
+    }
+
+    #[test]
+    fn times_of_day_on_the_first_date() {
+        let lines = [
+            "$GPZDA,120000,22,02,2021,00,00",
+            "$GPGGA,120001",
+            "$SDDPT,5.2,0.0",
+            "$GPGGA,120002",
+            "$GPGGA,120003",
+            "$GPGGA,120004",
+        ];
+        let (out, summary) = trim_lines("range", &lines, "12:00:02", "12:00:03", false).unwrap();
+        assert_eq!(out, vec![lines[3], lines[4]]);
+        assert_eq!((summary.lines_read, summary.lines_written, summary.primed), (6, 2, 0));
+
+        // The depth belongs with the time before it
+        let (out, _) = trim_lines("until", &lines, "", "2021-02-22T12:00:01", false).unwrap();
+        assert_eq!(out, &lines[..3]);
+    }
+
+    #[test]
+    fn until_goes_past_midnight() {
+        let lines = ["$GPZDA,235958,22,02,2021,00,00", "$GPGGA,235959", "$GPGGA,000000", "$GPGGA,000001", "$GPGGA,000002"];
+        let (out, _) = trim_lines("midnight", &lines, "23:59:59", "00:00:01", false).unwrap();
+        assert_eq!(out, vec!["$GPGGA,235959", "$GPGGA,000000", "$GPGGA,000001"]);
+    }
+
+    #[test]
+    fn priming_with_the_last_time_and_position() {
+        let lines = [
+            "$GPZDA,120000,22,02,2021,00,00",
+            "$GPGGA,120000,4930.00,N,12400.00,W,1,8,0.9,,M,,M",
+            "$GPRMC,120001,A,4930.00,N,12400.00,W,5.0,90.0,220221,,",
+            "$GPGGA,120001,4930.00,N,12359.99,W,1,8,0.9,,M,,M",
+            "$SDDPT,5.2,0.0",
+            "$GPGGA,120002,4930.00,N,12359.98,W,1,8,0.9,,M,,M",
+        ];
+        let (out, summary) = trim_lines("prime", &lines, "12:00:02", "", true).unwrap();
+        assert_eq!(out, vec![lines[0], lines[2], lines[3], lines[5]]);
+        assert_eq!((summary.lines_written, summary.primed), (1, 3));
+    }
+
+    #[test]
+    fn no_date_to_put_the_times_on() {
+        let lines = ["$GPGGA,120001", "$GPGGA,120002"];
+        assert!(trim_lines("undated", &lines, "12:00:01", "", false).is_err());
+        // Without a range everything goes through
+        assert_eq!(trim_lines("all", &lines, "", "", false).unwrap().0.len(), 2);
+    }
+
+    #[test]
+    fn time_specs() {
+        let date = NaiveDate::from_ymd_opt(2021, 2, 22).unwrap();
+        let on = |s: &str| TimeSpec::from_str(s).map(|t| t.on(date).to_string());
+        assert_eq!(on("12:30"), Ok("2021-02-22 12:30:00".to_string()));
+        assert_eq!(on("12:30:05.5"), Ok("2021-02-22 12:30:05.500".to_string()));
+        assert_eq!(on("2021-03-01T01:00:00Z"), Ok("2021-03-01 01:00:00".to_string()));
+        assert!(on("noon").is_err());
+    }
+}
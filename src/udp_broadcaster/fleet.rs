@@ -0,0 +1,160 @@
+use crate::ais::{self, Report};
+use crate::converter;
+use crate::geo;
+use chrono::{Duration, NaiveDateTime};
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+// The rest of the fleet, from their own logs (GPX from a phone, or NMEA), sent
+// out as AIS targets so the plotter shows everybody sailing the race together.
+// The logs are lined up with ours by their times: at each report each boat is
+// wherever its log says it was at that moment of our file, worked out along the
+// straight line between its points either side. A boat only shows up while its
+// log has it.
+
+// --ais-target race/blue.gpx:235012345:BLUE MOON
+#[derive(Clone)]
+pub struct AisTarget {
+    pub path: PathBuf,
+    pub mmsi: Option<u32>,
+    pub name: Option<String>,
+}
+
+impl FromStr for AisTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AisTarget, String> {
+        let mut parts = s.splitn(3, ':');
+        let path = PathBuf::from(parts.next().unwrap_or(""));
+        let mmsi = match parts.next() {
+            Some(m) => match m.parse::<u32>() {
+                Ok(m) if m.to_string().len() == 9 => Some(m),
+                _ => return Err(format!("'{}' isn't a nine digit MMSI", m)),
+            },
+            None => None,
+        };
+        Ok(AisTarget {
+            path,
+            mmsi,
+            name: parts.next().map(|n| n.to_string()),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Default, clap::ValueEnum)]
+pub enum AisClass {
+    A,
+    #[default]
+    B,
+}
+
+// Names go out now and then rather than with every position, like the real thing
+const STATIC_EVERY_SECONDS: i64 = 60;
+
+struct Boat {
+    mmsi: u32,
+    name: String,
+    track: Vec<(NaiveDateTime, f64, f64)>,
+}
+
+pub struct Fleet {
+    boats: Vec<Boat>,
+    class: AisClass,
+    interval: Duration,
+    next: Option<NaiveDateTime>,
+    next_static: Option<NaiveDateTime>,
+}
+
+impl Fleet {
+    pub fn load(targets: &[AisTarget], class: AisClass, interval: f64) -> io::Result<Fleet> {
+        if interval <= 0.0 {
+            return Err(io::Error::other("--ais-interval has to be more than zero"));
+        }
+        let mut boats = vec![];
+        for (i, target) in targets.iter().enumerate() {
+            let points = converter::load_track(&target.path, None)
+                .map_err(|e| io::Error::other(format!("{}: {}", target.path.display(), e)))?;
+            let mut track: Vec<(NaiveDateTime, f64, f64)> =
+                points.iter().filter_map(|p| p.time.map(|t| (t, p.lat, p.lon))).collect();
+            track.sort_by_key(|(t, _, _)| *t);
+            track.dedup_by_key(|(t, _, _)| *t);
+            if track.len() < 2 {
+                return Err(io::Error::other(format!(
+                    "{}: the track needs at least two points with times on them",
+                    target.path.display()
+                )));
+            }
+            // Without an MMSI of our own each boat gets one in the 9xxxxxxxx range,
+            // which isn't given out to ships, and the name of its file
+            let stem = target.path.file_stem().map_or(String::new(), |s| s.to_string_lossy().to_string());
+            boats.push(Boat {
+                mmsi: target.mmsi.unwrap_or(900_000_001 + i as u32),
+                name: target.name.clone().unwrap_or(stem),
+                track,
+            });
+        }
+        Ok(Fleet {
+            boats,
+            class,
+            interval: Duration::milliseconds((interval * 1000.0) as i64),
+            next: None,
+            next_static: None,
+        })
+    }
+
+    // The reports that are due by this time in our file: where every boat is,
+    // and every so often who they are
+    pub fn due(&mut self, now: NaiveDateTime) -> Vec<Report> {
+        let mut reports = vec![];
+        if self.next.is_some_and(|next| now < next) {
+            return reports;
+        }
+        self.next = Some(now + self.interval);
+        let send_static = self.next_static.is_none_or(|next| now >= next);
+        if send_static {
+            self.next_static = Some(now + Duration::seconds(STATIC_EVERY_SECONDS));
+        }
+        for boat in &self.boats {
+            let (lat, lon, cog, sog) = match boat.at(now) {
+                Some(at) => at,
+                None => continue,
+            };
+            if send_static {
+                reports.push(Report {
+                    kind: 24,
+                    mmsi: boat.mmsi,
+                    name: boat.name.clone(),
+                    ship_type: Some(ais::SAILING),
+                    ..Report::default()
+                });
+            }
+            reports.push(Report {
+                kind: match self.class {
+                    AisClass::A => 1,
+                    AisClass::B => 18,
+                },
+                mmsi: boat.mmsi,
+                pos: Some((lat, lon)),
+                cog: Some(cog),
+                sog: Some(sog),
+                heading: Some(cog),
+                ..Report::default()
+            });
+        }
+        reports
+    }
+}
+
+impl Boat {
+    // Position, course and speed at this time, if the log covers it
+    fn at(&self, t: NaiveDateTime) -> Option<(f64, f64, f64, f64)> {
+        let leg = self.track.windows(2).find(|w| w[0].0 <= t && t <= w[1].0)?;
+        let ((t1, lat1, lon1), (t2, lat2, lon2)) = (leg[0], leg[1]);
+        let secs = (t2 - t1).num_milliseconds() as f64 / 1000.0;
+        let f = (t - t1).num_milliseconds() as f64 / 1000.0 / secs;
+        let sog = geo::distance_m(lat1, lon1, lat2, lon2) / secs * 3600.0 / 1852.0;
+        let cog = geo::bearing(lat1, lon1, lat2, lon2);
+        Some((lat1 + (lat2 - lat1) * f, lon1 + (lon2 - lon1) * f, cog, sog))
+    }
+}
@@ -0,0 +1,399 @@
+use crate::ais::targets::Targets;
+use crate::nmea;
+use chrono::NaiveDateTime;
+use std::str::FromStr;
+
+// The most recent values seen on the NMEA stream. The file player and the live
+// relay both push every line through update() so the dashboard looks the same
+// no matter where the data came from. The strings are already formatted for the
+// screen; the numbers in "data" are there for anything that wants to work with
+// them (the exporters, for example).
+#[derive(Default)]
+pub struct Instruments {
+    pub dt: Option<NaiveDateTime>,
+    pub lat_d: f64,
+    pub lat_s: String,
+    pub lon_d: f64,
+    pub lon_s: String,
+    pub cog: String,
+    pub sog: String,
+    pub hdg: String,
+    pub stw: String,
+    pub dpt: String,
+    pub tmp: String,
+    pub log: String,
+    pub wnd: String,
+    pub twnd: String,
+    pub rud: String,
+    pub att: String,
+    pub bar: String,
+    pub whr: String,
+    pub pos: Option<(f64, f64)>,
+    pub data: Readings,
+    // Just the readings the last line carried, with everything else None
+    pub line: Readings,
+    pub ais: Targets,
+    // True when the last line gave us a new true wind, and whether we worked
+    // it out ourselves rather than reading it from the instruments
+    pub new_true_wind: bool,
+    pub true_wind_computed: bool,
+    true_wind_measured: bool,
+}
+
+// The latest reading of everything we know how to parse, in plain numbers.
+// Each one is None until we have seen it. Angles are in degrees, speeds in
+// knots and depths in metres.
+#[derive(Clone, Default)]
+pub struct Readings {
+    pub fix_quality: Option<u32>,
+    pub sats: Option<u32>,
+    pub hdop: Option<f64>,
+    pub cog_t: Option<f64>,
+    pub sog_kts: Option<f64>,
+    pub stw_kts: Option<f64>,
+    pub heading_t: Option<f64>,
+    pub heading_m: Option<f64>,
+    pub depth_m: Option<f64>,
+    // Wind angles are off the bow, positive to starboard and negative to port
+    pub awa: Option<f64>,
+    pub aws_kts: Option<f64>,
+    pub twa: Option<f64>,
+    pub tws_kts: Option<f64>,
+    pub twd_t: Option<f64>,
+    pub water_temp_c: Option<f64>,
+    // Total and trip distance through the water, in nautical miles
+    pub log_nm: Option<f64>,
+    pub trip_nm: Option<f64>,
+    // Rudder, heel and pitch are positive to starboard and bow up
+    pub rudder: Option<f64>,
+    pub heel: Option<f64>,
+    pub pitch: Option<f64>,
+    pub pressure_hpa: Option<f64>,
+}
+
+impl Instruments {
+    pub fn new() -> Instruments {
+        Instruments::default()
+    }
+
+    // Parse one line and update whatever values it carries. Returns true when
+    // the line was a ZDA with a usable time stamp, which is what the player
+    // uses to keep itself in synch with the clock in the file.
+    pub fn update(&mut self, line: &str) -> bool {
+        self.new_true_wind = false;
+        self.line = Readings::default();
+        // !AIVDM,1,1,,B,15MgK45P3@G?fl0E`JbR0OwT0@MS,0*4E
+        if line.starts_with('!') {
+            self.ais.add(line, self.dt, self.pos);
+            return false;
+        }
+        let fields: Vec<&str> = line.split(',').collect();
+        match nmea::sentence_type(line) {
+            // $GPZDA,234626.99,22,02,2021,08,00*6A
+            Some("ZDA") => match nmea::parse_zda(&fields) {
+                Some(dt) => {
+                    self.dt = Some(dt);
+                    return true;
+                }
+                None => return false,
+            },
+            // $GPGGA,020659.21,4937.8509,N,12401.4384,W,2,9,0.83,,M,,M*44
+            Some("GGA") if fields.len() >= 6 => {
+                self.set_position(fields[2], fields[3], fields[4], fields[5]);
+                self.line.fix_quality = number(&fields, 6);
+                self.line.sats = number(&fields, 7);
+                self.line.hdop = number(&fields, 8);
+            }
+            // $GPRMC,020659.21,A,4937.8509,N,12401.4384,W,0.1,359.5,220221,,,D*7A
+            Some("RMC") if fields.len() >= 9 => {
+                if fields[2] == "A" {
+                    self.set_position(fields[3], fields[4], fields[5], fields[6]);
+                }
+                self.set_cog_sog(fields[8], fields[7]);
+            }
+            // $IIVTG,359.5,T,,M,0.1,N,0.1,K,D*15
+            Some("VTG") if fields.len() >= 6 => self.set_cog_sog(fields[1], fields[5]),
+            // $WIVWR,31.7,L,0.5,N,0.3,M,0.9,K*73
+            Some("VWR") if fields.len() >= 4 => {
+                let a: f64 = FromStr::from_str(fields[1]).unwrap_or(0.0);
+                let d = fields[2];
+                let v: f64 = FromStr::from_str(fields[3]).unwrap_or(0.0);
+                self.wnd = format!("{:3.0} degrees {} at {:2.1} knots", a, d, v);
+                self.line.awa = Some(if d == "L" { -a } else { a });
+                self.line.aws_kts = Some(v);
+                self.apparent_wind_changed();
+            }
+            // $WIMWV,214.8,R,0.1,N,A*3D  (R is relative/apparent, T is true)
+            Some("MWV") if fields.len() >= 6 && fields[5].starts_with('A') => {
+                let angle: Option<f64> = number(&fields, 1);
+                // Angles come as 0 - 360 clockwise from the bow
+                let angle = angle.map(|a| if a > 180.0 { a - 360.0 } else { a });
+                let speed = number(&fields, 3).and_then(|v| to_knots(v, fields[4]));
+                if fields[2] == "T" {
+                    self.line.twa = angle;
+                    self.line.tws_kts = speed;
+                    self.true_wind_read();
+                } else {
+                    self.line.awa = angle;
+                    self.line.aws_kts = speed;
+                    if let (Some(a), Some(v)) = (angle, speed) {
+                        let d = if a < 0.0 { "L" } else { "R" };
+                        self.wnd = format!("{:3.0} degrees {} at {:2.1} knots", a.abs(), d, v);
+                    }
+                    self.apparent_wind_changed();
+                }
+            }
+            // $WIMWD,245.1,T,226.4,M,12.3,N,6.3,M*5B
+            Some("MWD") if fields.len() >= 6 => {
+                self.line.twd_t = number(&fields, 1);
+                if let Some(tws) = number(&fields, 5) {
+                    self.line.tws_kts = Some(tws);
+                }
+                self.true_wind_read();
+            }
+            // $VWVHW,,T,,M,6.1,N,11.3,K*5E  (heading true and magnetic, speed through the water)
+            Some("VHW") if fields.len() >= 8 => {
+                let kmh: Option<f64> = number(&fields, 7);
+                self.line.stw_kts = number(&fields, 5).or(kmh.map(|v| v / 1.852));
+                if let Some(stw) = self.line.stw_kts {
+                    self.stw = format!("{:2.1} kts", stw);
+                }
+                // Most logs leave the headings empty, so only take them when they're there
+                if let Some(h) = number(&fields, 1) {
+                    self.line.heading_t = Some(h);
+                }
+                if let Some(h) = number(&fields, 3) {
+                    self.line.heading_m = Some(h);
+                }
+                self.set_hdg();
+            }
+            // $HEHDT,274.07,T*03
+            Some("HDT") if fields.len() >= 2 => {
+                self.line.heading_t = number(&fields, 1);
+                self.set_hdg();
+            }
+            // $HCHDG,98.3,0.0,E,12.6,W*57  (magnetic heading, deviation, variation)
+            Some("HDG") if fields.len() >= 6 => {
+                let heading: Option<f64> = number(&fields, 1);
+                let dev = east_west(number(&fields, 2), fields[3]).unwrap_or(0.0);
+                self.line.heading_m = heading.map(|h| (h + dev).rem_euclid(360.0));
+                if let Some(var) = east_west(number(&fields, 4), fields[5]) {
+                    self.line.heading_t = heading.map(|h| (h + dev + var).rem_euclid(360.0));
+                }
+                self.set_hdg();
+            }
+            // $YXMTW,11.2,C*17
+            Some("MTW") if fields.len() >= 2 => {
+                self.line.water_temp_c = number(&fields, 1);
+                if let Some(t) = self.line.water_temp_c {
+                    self.tmp = format!("{:2.1} °C", t);
+                }
+            }
+            // $VWVLW,1523.4,N,12.6,N*4B  (total and since reset)
+            Some("VLW") if fields.len() >= 4 => {
+                self.line.log_nm = number(&fields, 1);
+                self.line.trip_nm = number(&fields, 3);
+                if let (Some(total), Some(trip)) = (self.line.log_nm, self.line.trip_nm) {
+                    self.log = format!("{:.1} nm (trip {:.2} nm)", total, trip);
+                }
+            }
+            // $IIRSA,-5.2,A,,V*4E  (starboard, or only, rudder and port rudder)
+            Some("RSA") if fields.len() >= 3 => {
+                if fields[2].starts_with('A') {
+                    self.line.rudder = number(&fields, 1);
+                }
+                if let Some(r) = self.line.rudder {
+                    self.rud = format!("{:3.1} degrees {}", r.abs(), if r < 0.0 { "port" } else { "starboard" });
+                }
+            }
+            // $IIXDR,A,-12.3,D,HEEL,A,1.5,D,PTCH,P,1.0132,B,BARO*1A
+            Some("XDR") => {
+                self.transducers(&fields);
+            }
+            // $SDDPT,10.38,0,*6F
+            Some("DPT") if fields.len() >= 3 => {
+                let d: f64 = FromStr::from_str(fields[1]).unwrap_or(0.0);
+                let o: f64 = FromStr::from_str(fields[2]).unwrap_or(0.0);
+                self.dpt = format!("{:3.1} m", d + o);
+                self.line.depth_m = Some(d + o);
+            }
+            _ => {}
+        }
+        self.merge_line();
+        false
+    }
+
+    // Copy what this line carried over the latest readings. Anything worked
+    // out from more than one sentence does this first, so it's done as soon as
+    // the line's own readings are in and again at the end (which is harmless).
+    fn merge_line(&mut self) {
+        let (d, l) = (&mut self.data, &self.line);
+        let merge = |to: &mut Option<f64>, from: Option<f64>| *to = from.or(*to);
+        d.fix_quality = l.fix_quality.or(d.fix_quality);
+        d.sats = l.sats.or(d.sats);
+        merge(&mut d.hdop, l.hdop);
+        merge(&mut d.cog_t, l.cog_t);
+        merge(&mut d.sog_kts, l.sog_kts);
+        merge(&mut d.stw_kts, l.stw_kts);
+        merge(&mut d.heading_t, l.heading_t);
+        merge(&mut d.heading_m, l.heading_m);
+        merge(&mut d.depth_m, l.depth_m);
+        merge(&mut d.awa, l.awa);
+        merge(&mut d.aws_kts, l.aws_kts);
+        merge(&mut d.twa, l.twa);
+        merge(&mut d.tws_kts, l.tws_kts);
+        merge(&mut d.twd_t, l.twd_t);
+        merge(&mut d.water_temp_c, l.water_temp_c);
+        merge(&mut d.log_nm, l.log_nm);
+        merge(&mut d.trip_nm, l.trip_nm);
+        merge(&mut d.rudder, l.rudder);
+        merge(&mut d.heel, l.heel);
+        merge(&mut d.pitch, l.pitch);
+        merge(&mut d.pressure_hpa, l.pressure_hpa);
+    }
+
+    // Once the instruments give us true wind we go with theirs; until then we
+    // work it out from every new apparent wind
+    fn true_wind_read(&mut self) {
+        self.merge_line();
+        self.true_wind_measured = true;
+        self.true_wind_computed = false;
+        self.new_true_wind = true;
+        self.set_twnd();
+    }
+
+    // True wind from the apparent wind, the speed through the water (or over
+    // the ground if there's no log) and the heading (or the course if there's
+    // no compass). Without a heading we still get the angle and speed, just
+    // not the direction.
+    fn apparent_wind_changed(&mut self) {
+        if self.true_wind_measured {
+            return;
+        }
+        self.merge_line();
+        let d = &mut self.data;
+        let (awa, aws, speed) = match (d.awa, d.aws_kts, d.stw_kts.or(d.sog_kts)) {
+            (Some(awa), Some(aws), Some(speed)) => (awa, aws, speed),
+            _ => return,
+        };
+        let (twa, tws) = true_wind(awa, aws, speed);
+        d.twa = Some(twa);
+        d.tws_kts = Some(tws);
+        d.twd_t = d.heading_t.or(d.cog_t).map(|h| (h + twa).rem_euclid(360.0));
+        self.true_wind_computed = true;
+        self.new_true_wind = true;
+        self.set_twnd();
+    }
+
+    // Transducer readings come in fours (type, value, units, name) and there can
+    // be any number of them in one sentence. The names aren't standard, so we go
+    // by the ones the common instrument systems use.
+    fn transducers(&mut self, fields: &[&str]) {
+        for quad in fields[1..].chunks(4).filter(|q| q.len() == 4) {
+            let value: f64 = match FromStr::from_str(quad[1]) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            let name = quad[3].split('*').next().unwrap_or("").to_ascii_uppercase();
+            match (quad[0], quad[2]) {
+                ("A", "D") if name.contains("HEEL") || name.contains("ROLL") => self.line.heel = Some(value),
+                ("A", "D") if name.contains("PTCH") || name.contains("PITCH") => self.line.pitch = Some(value),
+                ("P", "B") => self.line.pressure_hpa = Some(value * 1000.0),
+                ("P", "P") => self.line.pressure_hpa = Some(value / 100.0),
+                _ => {}
+            }
+        }
+        self.merge_line();
+        let d = &self.data;
+        if let Some(heel) = d.heel {
+            self.att = format!("heel {:3.1} {}", heel.abs(), if heel < 0.0 { "port" } else { "starboard" });
+            if let Some(pitch) = d.pitch {
+                self.att += &format!(", pitch {:3.1}", pitch);
+            }
+        }
+        if let Some(p) = d.pressure_hpa {
+            self.bar = format!("{:4.1} hPa", p);
+        }
+    }
+
+    fn set_hdg(&mut self) {
+        self.merge_line();
+        let d = &self.data;
+        self.hdg = match (d.heading_t, d.heading_m) {
+            (Some(t), Some(m)) => format!("{:3.0} °T ({:3.0} °M)", t, m),
+            (Some(t), None) => format!("{:3.0} °T", t),
+            (None, Some(m)) => format!("{:3.0} °M", m),
+            (None, None) => return,
+        };
+    }
+
+    fn set_twnd(&mut self) {
+        let d = &self.data;
+        if let (Some(twa), Some(tws)) = (d.twa, d.tws_kts) {
+            let side = if twa < 0.0 { "L" } else { "R" };
+            self.twnd = format!("{:3.0} degrees {} at {:2.1} knots", twa.abs(), side, tws);
+            if let Some(twd) = d.twd_t {
+                self.twnd += &format!(", from {:3.0} °T", twd);
+            }
+        }
+    }
+
+    fn set_position(&mut self, lat: &str, n_s: &str, lon: &str, e_w: &str) {
+        // Get latitude from GPS statement
+        let x: f64 = FromStr::from_str(lat).unwrap_or(0.0);
+        let lat_deg: f64 = (x / 100.0).floor();
+        let lat_min: f64 = (x / 100.0).fract() * 100.0;
+        self.lat_d = lat_deg + (lat_min / 60.0);
+        if n_s.contains('S') {
+            self.lat_d = -self.lat_d
+        }
+        self.lat_s = format!("{:3}° {:2.4} {} ({:.4})", lat_deg, lat_min, n_s, self.lat_d);
+        // Get longitude from GPS statements
+        let x: f64 = FromStr::from_str(lon).unwrap_or(0.0);
+        let lon_deg: f64 = (x / 100.0).floor();
+        let lon_min: f64 = (x / 100.0).fract() * 100.0;
+        self.lon_d = lon_deg + (lon_min / 60.0);
+        if e_w.contains('W') {
+            self.lon_d = -self.lon_d
+        }
+        self.lon_s = format!("{:3}° {:2.4} {} ({:.4})", lon_deg, lon_min, e_w, self.lon_d);
+        self.pos = nmea::parse_lat_lon(lat, n_s, lon, e_w);
+    }
+
+    fn set_cog_sog(&mut self, cog: &str, sog: &str) {
+        let c: f64 = FromStr::from_str(cog).unwrap_or(0.0);
+        self.cog = format!("{:3.0} °T", c);
+        let s: f64 = FromStr::from_str(sog).unwrap_or(0.0);
+        self.sog = format!("{:2.1} kts", s);
+        self.line.cog_t = FromStr::from_str(cog).ok();
+        self.line.sog_kts = FromStr::from_str(sog).ok();
+    }
+}
+
+fn number<T: FromStr>(fields: &[&str], i: usize) -> Option<T> {
+    fields.get(i).and_then(|f| FromStr::from_str(f.split('*').next().unwrap_or("")).ok())
+}
+
+// Deviation and variation are east positive, west negative
+fn east_west(value: Option<f64>, e_w: &str) -> Option<f64> {
+    value.map(|v| if e_w.starts_with('W') { -v } else { v })
+}
+
+// The apparent wind less the wind made by the boat's own speed, which always
+// blows straight down the boat from the bow. Angles are off the bow, positive
+// to starboard; gives back (true wind angle, true wind speed).
+fn true_wind(awa: f64, aws: f64, speed: f64) -> (f64, f64) {
+    let ahead = aws * awa.to_radians().cos() - speed;
+    let across = aws * awa.to_radians().sin();
+    (across.atan2(ahead).to_degrees(), ahead.hypot(across))
+}
+
+// Wind speeds can come in knots (N), km/h (K) or m/s (M)
+fn to_knots(speed: f64, unit: &str) -> Option<f64> {
+    match unit {
+        "N" => Some(speed),
+        "K" => Some(speed / 1.852),
+        "M" => Some(speed * 3600.0 / 1852.0),
+        _ => None,
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Read};
+use std::net::UdpSocket;
+use std::path::Path;
+use std::time::Duration;
+
+// Live sources of NMEA sentences. Both of these hand back the same kind of
+// line iterator that BufReader::lines() gives us for a file, so the relay
+// doesn't need to care where the data is coming from.
+
+// How long to wait for some data before giving the screen a chance to redraw
+// and see if 'q' was pressed
+const QUIET_TIMEOUT: Duration = Duration::from_millis(250);
+
+// Receive sentences sent (or broadcast) to a UDP port on this machine.
+// A single datagram often holds several sentences so we split them up here.
+// When nothing has come in for a while the iterator hands back a timed out
+// error (see is_quiet) rather than sitting in recv for ever.
+pub fn udp_lines(udp_port: u16) -> io::Result<UdpLines> {
+    let socket = UdpSocket::bind(("0.0.0.0", udp_port))?;
+    socket.set_read_timeout(Some(QUIET_TIMEOUT))?;
+    Ok(UdpLines {
+        socket,
+        pending: VecDeque::new(),
+    })
+}
+
+// Read sentences from a serial port at the given speed (4800 for most
+// instruments, 38400 for AIS). Like the UDP port it times out when the line is
+// quiet, and a burst of line noise that isn't text doesn't stop it.
+pub fn serial_lines(device: &Path, baud: u32) -> io::Result<SerialLines<Box<dyn serialport::SerialPort>>> {
+    let port = serialport::new(device.to_string_lossy(), baud).timeout(QUIET_TIMEOUT).open()?;
+    Ok(SerialLines::new(port))
+}
+
+// True for the error that only means no data turned up in time. Depending on
+// the platform a read timeout comes back as either of these.
+pub fn is_quiet(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+// Lines from anything we can read bytes from. Bytes that aren't UTF-8 come
+// out as the replacement character rather than as an error, and part of a
+// line that was in when the read timed out is kept for the next go.
+pub struct SerialLines<R> {
+    reader: io::BufReader<R>,
+    partial: Vec<u8>,
+}
+
+impl<R: Read> SerialLines<R> {
+    pub fn new(reader: R) -> SerialLines<R> {
+        SerialLines {
+            reader: io::BufReader::new(reader),
+            partial: vec![],
+        }
+    }
+}
+
+impl<R: Read> Iterator for SerialLines<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<io::Result<String>> {
+        match self.reader.read_until(b'\n', &mut self.partial) {
+            Ok(0) if self.partial.is_empty() => None,
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&self.partial).trim_end().to_string();
+                self.partial.clear();
+                Some(Ok(line))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+pub struct UdpLines {
+    socket: UdpSocket,
+    pending: VecDeque<String>,
+}
+
+impl Iterator for UdpLines {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<io::Result<String>> {
+        let mut buf = [0u8; 65536];
+        while self.pending.is_empty() {
+            let len = match self.socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(e) => return Some(Err(e)),
+            };
+            let text = String::from_utf8_lossy(&buf[..len]);
+            self.pending.extend(
+                text.lines()
+                    .map(|l| l.trim_end().to_string())
+                    .filter(|l| !l.is_empty()),
+            );
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn datagrams_are_split_into_lines() {
+        let mut lines = udp_lines(0).unwrap();
+        let port = lines.socket.local_addr().unwrap().port();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(b"$GPZDA,1*00\r\n\r\n$GPGGA,2*00\r\n", ("127.0.0.1", port)).unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "$GPZDA,1*00");
+        assert_eq!(lines.next().unwrap().unwrap(), "$GPGGA,2*00");
+    }
+
+    #[test]
+    fn nothing_arriving_times_out() {
+        let mut lines = udp_lines(0).unwrap();
+        let e = lines.next().unwrap().unwrap_err();
+        assert!(is_quiet(&e), "{:?}", e);
+    }
+
+    // Hands out the bytes a bit at a time, with a timeout now and then
+    struct Trickle(VecDeque<Option<&'static [u8]>>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.pop_front() {
+                Some(Some(bytes)) => {
+                    buf[..bytes.len()].copy_from_slice(bytes);
+                    Ok(bytes.len())
+                }
+                Some(None) => Err(io::Error::from(io::ErrorKind::TimedOut)),
+                None => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn serial_noise_is_not_an_error() {
+        let lines: Vec<String> = SerialLines::new(&b"$GPGGA,1\xff\xfe,2\r\n\r\n$GPZDA,3"[..]).map(|l| l.unwrap()).collect();
+        assert_eq!(lines, vec!["$GPGGA,1\u{fffd}\u{fffd},2", "", "$GPZDA,3"]);
+    }
+
+    #[test]
+    fn serial_timeout_keeps_the_start_of_the_line() {
+        let mut lines = SerialLines::new(Trickle(VecDeque::from([Some(&b"$GPG"[..]), None, Some(&b"GA,1\r\n"[..])])));
+        assert!(is_quiet(&lines.next().unwrap().unwrap_err()));
+        assert_eq!(lines.next().unwrap().unwrap(), "$GPGGA,1");
+        assert!(lines.next().is_none());
+    }
+}
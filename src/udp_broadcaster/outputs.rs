@@ -0,0 +1,74 @@
+use pnet::datalink::NetworkInterface;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+
+// Everywhere a sentence goes once we have read it. There is always the UDP
+// broadcast on the chosen interface, plus any number of unicast destinations
+// and an optional log file that gets a copy of every line (the "tee").
+pub struct Outputs {
+    socket: UdpSocket,
+    destinations: Vec<SocketAddr>,
+    tee: Option<File>,
+    if_name: String,
+}
+
+impl Outputs {
+    pub fn broadcast(interface: &NetworkInterface, udp_port: u16) -> io::Result<Outputs> {
+        // Grab the broadcast address of the first IP address assigned to the specified interface
+        let ip_addr = interface
+            .ips
+            .first()
+            .ok_or_else(|| {
+                io::Error::other("Interface '".to_owned() + &interface.name + "' has no IP address")
+            })?
+            .broadcast();
+        // Open a UDP socket for the interface
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        // allow broadcasting on this socket...
+        socket.set_broadcast(true)?;
+        Ok(Outputs {
+            socket,
+            destinations: vec![SocketAddr::new(ip_addr, udp_port)],
+            tee: None,
+            if_name: interface.name.clone(),
+        })
+    }
+
+    // Also send every line to this address (another PC, a plotter, etc.)
+    pub fn forward(&mut self, destination: SocketAddr) {
+        self.destinations.push(destination);
+    }
+
+    // Append a copy of every line to a log file. The file is not buffered so
+    // nothing is lost when the user quits with 'q' in the middle of a session.
+    pub fn tee(&mut self, path: &Path) -> io::Result<()> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.tee = Some(file);
+        Ok(())
+    }
+
+    // Send one sentence to all of the outputs, adding the CR/LF that NMEA 0183 wants
+    pub fn send(&mut self, line: &str) -> io::Result<()> {
+        let data = format!("{}\r\n", line);
+        for destination in &self.destinations {
+            self.socket.send_to(data.as_bytes(), destination)?;
+        }
+        if let Some(tee) = self.tee.as_mut() {
+            tee.write_all(data.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Outputs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "interface '{}'", self.if_name)?;
+        for destination in &self.destinations {
+            write!(f, ", {}", destination)?;
+        }
+        Ok(())
+    }
+}
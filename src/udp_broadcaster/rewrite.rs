@@ -0,0 +1,610 @@
+use crate::converter::parse_time;
+use super::fleet::{AisClass, AisTarget, Fleet};
+use super::route::Route;
+use super::synthesis::{Synth, Synthesizer};
+use super::traffic::Traffic;
+use crate::ais;
+use crate::geo::{self, LatLon};
+use crate::nmea;
+use chrono::{Duration, NaiveDate, NaiveDateTime, Timelike, Utc};
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+// Changes made to the sentences on their way from the file to the network.
+// Everything here is off until asked for, and with none of it turned on the
+// lines go out exactly as they are in the file.
+#[derive(clap::Args, Default)]
+pub struct RewriteOptions {
+    /// Only send sentences that match one of these (GPGSV, GSV, *VWR, P* ...)
+    #[arg(long, value_name = "PATTERN", value_delimiter = ',')]
+    pub include: Vec<String>,
+
+    /// Don't send sentences that match any of these
+    #[arg(long, value_name = "PATTERN", value_delimiter = ',')]
+    pub exclude: Vec<String>,
+
+    /// Send no more than this many a second of sentences that match the pattern (GSV=1 ...)
+    #[arg(long, value_name = "PATTERN=HZ", value_delimiter = ',')]
+    pub rate_limit: Vec<RateLimit>,
+
+    /// Change the talker of sentences from the first one to the second (II=GP ...)
+    #[arg(long, value_name = "FROM=TO", value_delimiter = ',')]
+    pub rewrite_talker: Vec<TalkerMap>,
+
+    /// Make up the sentences the file doesn't have: RMC from GGA and VTG, ZDA from RMC, VTG from the positions, HDT from the course, MWV and MWD true wind from the apparent wind
+    #[arg(long, value_enum, value_name = "WHAT", value_delimiter = ',')]
+    pub synthesize: Vec<Synth>,
+
+    /// The talker to put on made up sentences, so they can be told apart from the GPS
+    #[arg(long, default_value = "II", value_name = "TALKER")]
+    pub synth_talker: String,
+
+    /// The date for made up RMC and ZDA when the file doesn't have one
+    #[arg(long, value_name = "yyyy-mm-dd")]
+    pub date: Option<NaiveDate>,
+
+    /// Move the times and dates in ZDA, RMC, GGA and GLL so the file starts now, or at this time
+    #[arg(long, value_name = "now | yyyy-mm-ddThh:mm:ss")]
+    pub retime: Option<Retime>,
+
+    /// Move every position by this many degrees of latitude and longitude
+    #[arg(long, value_name = "DLAT,DLON", allow_hyphen_values = true, conflicts_with = "move_start_to")]
+    pub translate: Option<LatLon>,
+
+    /// Move the whole track so that it starts here
+    #[arg(long, value_name = "LAT,LON", allow_hyphen_values = true, value_parser = geo::position)]
+    pub move_start_to: Option<LatLon>,
+
+    /// Turn the track this many degrees clockwise around where it starts, courses and headings too
+    #[arg(long, value_name = "DEGREES", allow_negative_numbers = true)]
+    pub rotate: Option<f64>,
+
+    /// Send another boat's GPX, KML, CSV or NMEA log as an AIS target, lined up with this file by time (may be repeated)
+    #[arg(long, value_name = "FILE[:MMSI:NAME]")]
+    pub ais_target: Vec<AisTarget>,
+
+    /// Send the --ais-target boats as class A (type 1) or class B (type 18) position reports
+    #[arg(long, value_enum, default_value_t = AisClass::B)]
+    pub ais_class: AisClass,
+
+    /// Seconds of file time between position reports for each --ais-target
+    #[arg(long, default_value_t = 3.0, value_name = "SECONDS")]
+    pub ais_interval: f64,
+
+    /// Mix in made up AIS traffic from this TOML or JSON scenario file
+    #[arg(long, value_name = "SCENARIO_FILE")]
+    pub ais_scenario: Option<PathBuf>,
+
+    /// Follow this GPX or CSV route and send RMB, APB, XTE, BWC and BOD for it after every fix
+    #[arg(long, value_name = "ROUTE_FILE")]
+    pub route: Option<PathBuf>,
+
+    /// How close to a --route waypoint counts as getting there, and moves on to the next one
+    #[arg(long, default_value_t = 0.1, value_name = "NM")]
+    pub arrival_radius: f64,
+}
+
+#[derive(Clone, Copy)]
+pub enum Retime {
+    Now,
+    At(NaiveDateTime),
+}
+
+impl FromStr for Retime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Retime, String> {
+        if s.eq_ignore_ascii_case("now") {
+            return Ok(Retime::Now);
+        }
+        parse_time(s)
+            .map(Retime::At)
+            .ok_or_else(|| format!("'{}' isn't a time, use now or yyyy-mm-ddThh:mm:ss", s))
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimit {
+    pub pattern: String,
+    pub hz: f64,
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<RateLimit, String> {
+        let (pattern, hz) = s.split_once('=').ok_or_else(|| format!("'{}' should be like GSV=1", s))?;
+        match hz.parse::<f64>() {
+            Ok(hz) if hz > 0.0 => Ok(RateLimit {
+                pattern: pattern.to_string(),
+                hz,
+            }),
+            _ => Err(format!("'{}' isn't a rate above zero", hz)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TalkerMap {
+    pub from: String,
+    pub to: String,
+}
+
+impl FromStr for TalkerMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TalkerMap, String> {
+        let talker = |t: &str| t.len() == 2 && t.chars().all(|c| c.is_ascii_alphanumeric());
+        match s.split_once('=') {
+            Some((from, to)) if talker(from) && talker(to) => Ok(TalkerMap {
+                from: from.to_ascii_uppercase(),
+                to: to.to_ascii_uppercase(),
+            }),
+            _ => Err(format!("'{}' should be two talkers like II=GP", s)),
+        }
+    }
+}
+
+// What the rate limiter knows about one pattern and talker
+#[derive(Default)]
+struct Limiter {
+    last: Option<NaiveDateTime>,
+    in_this_second: u32,
+    // Whether the first sentence of the group we are in (GSV, AIS) went out
+    group_sent: bool,
+}
+
+pub struct Rewriter<'a> {
+    options: &'a RewriteOptions,
+    clock: nmea::FileClock,
+    synthesizer: Option<Synthesizer>,
+    fleet: Option<Fleet>,
+    traffic: Option<Traffic>,
+    route: Option<Route>,
+    // A clock of our own for the lines going out, made up ones and all
+    retime_clock: nmea::FileClock,
+    // The rates go by this one, which keeps time without a date as well
+    rate_clock: nmea::FileClock,
+    // How far to move the times, worked out from the first time in the file
+    offset: Option<Duration>,
+    // The first position in the file and where it is being moved to
+    start: Option<(LatLon, LatLon)>,
+    // AIS fragments waiting for the rest of their message to be moved
+    assembler: ais::Assembler,
+    limiters: HashMap<(usize, String), Limiter>,
+}
+
+impl<'a> Rewriter<'a> {
+    // Fails if one of the --ais-target logs, the scenario or the route can't be read
+    pub fn new(options: &'a RewriteOptions) -> io::Result<Rewriter<'a>> {
+        let fleet = if options.ais_target.is_empty() {
+            None
+        } else {
+            Some(Fleet::load(&options.ais_target, options.ais_class, options.ais_interval)?)
+        };
+        Ok(Rewriter {
+            options,
+            clock: nmea::FileClock::new(),
+            synthesizer: (!options.synthesize.is_empty())
+                .then(|| Synthesizer::new(&options.synthesize, &options.synth_talker, options.date)),
+            fleet,
+            traffic: options.ais_scenario.as_deref().map(Traffic::load).transpose()?,
+            route: options
+                .route
+                .as_deref()
+                .map(|path| Route::load(path, options.arrival_radius, &options.synth_talker))
+                .transpose()?,
+            retime_clock: nmea::FileClock::new(),
+            rate_clock: nmea::FileClock::undated(),
+            offset: None,
+            start: None,
+            assembler: ais::Assembler::new(),
+            limiters: HashMap::new(),
+        })
+    }
+
+    // Returns the lines that should go out for this one: none if it has been
+    // filtered out, or more than one when sentences are being made up. The
+    // patterns are matched against the sentences as they are in the file,
+    // before any talkers are changed.
+    pub fn apply(&mut self, line: &str) -> Vec<String> {
+        let o = self.options;
+        let now = self.clock.update(line);
+        let tag = nmea::tag(line);
+        let matches = |patterns: &[String]| tag.is_some_and(|t| patterns.iter().any(|p| nmea::tag_matches(p, t)));
+        if (!o.include.is_empty() && !matches(&o.include)) || matches(&o.exclude) {
+            return vec![];
+        }
+        let rate_now = self.rate_clock.update(line);
+        if !o.rate_limit.is_empty() && !self.under_rate_limit(line, rate_now) {
+            return vec![];
+        }
+        let mut lines = match self.synthesizer.as_mut() {
+            Some(synthesizer) => synthesizer.process(line),
+            None => vec![line.to_string()],
+        };
+        if self.fleet.is_some() || self.traffic.is_some() {
+            lines.extend(self.ais_reports(line, now));
+        }
+        let lines: Vec<String> = lines.into_iter().flat_map(|line| self.rewrite(line)).collect();
+        // The route goes by the fixes as they go out, moved and retimed, so it
+        // is sailed wherever and whenever the plotter thinks we are
+        match self.route.as_mut() {
+            Some(route) => lines
+                .into_iter()
+                .flat_map(|line| {
+                    let steering = route.process(&line);
+                    std::iter::once(line).chain(steering)
+                })
+                .collect(),
+            None => lines,
+        }
+    }
+
+    // AIS from --ais-target and --ais-scenario
+    fn ais_reports(&mut self, line: &str, now: Option<NaiveDateTime>) -> Vec<String> {
+        let o = self.options;
+        let moving = o.translate.is_some() || o.move_start_to.is_some() || o.rotate.is_some();
+        if let Some(traffic) = self.traffic.as_mut() {
+            traffic.observe(line);
+        }
+        // Until we've seen where our own track starts we don't know where to
+        // move them, so nothing is due yet and nothing is missed
+        if moving && self.start.is_none() {
+            return vec![];
+        }
+        let mut reports = self.traffic.as_mut().map_or(vec![], |traffic| traffic.due(now));
+        if let (Some(fleet), Some(now)) = (self.fleet.as_mut(), now) {
+            reports.extend(fleet.due(now));
+        }
+        // They are moved on the way out along with the AIS in the file
+        reports.iter().flat_map(ais::encode).collect()
+    }
+
+    fn rewrite(&mut self, mut line: String) -> Vec<String> {
+        let o = self.options;
+        if let Some(retime) = o.retime {
+            let now = self.retime_clock.update(&line);
+            // Lines before the first full date and time in the file can't be moved
+            // because we don't know how far to move them yet.
+            if let (None, Some(first)) = (self.offset, now) {
+                let to = match retime {
+                    // To the whole second, which keeps the time stamps tidy
+                    Retime::Now => Utc::now().naive_utc().with_nanosecond(0).unwrap(),
+                    Retime::At(t) => t,
+                };
+                self.offset = Some(to - first);
+            }
+            if let Some(offset) = self.offset {
+                line = nmea::shift_times(&line, offset);
+            }
+        }
+        let mut lines = if o.translate.is_some() || o.move_start_to.is_some() || o.rotate.is_some() {
+            self.move_positions(&line)
+        } else {
+            vec![line]
+        };
+        if !o.rewrite_talker.is_empty() {
+            lines = lines.iter().map(|line| rewrite_talker(line, &o.rewrite_talker)).collect();
+        }
+        lines
+    }
+
+    // Rates go by the file clock, or just the times of day in a file without
+    // dates. A sentence that is one of a group (GSV, or
+    // the fragments of an AIS message) goes out if the first of its group did,
+    // so we never send half a group.
+    fn under_rate_limit(&mut self, line: &str, now: Option<NaiveDateTime>) -> bool {
+        let tag = match nmea::tag(line) {
+            Some(tag) => tag,
+            None => return true,
+        };
+        let fields: Vec<&str> = line.split(',').collect();
+        let part: Option<u32> = match nmea::sentence_type(line).or_else(|| tag.get(2..)) {
+            Some("GSV" | "VDM" | "VDO") => fields.get(2).and_then(|f| f.parse().ok()),
+            _ => None,
+        };
+        let mut ok = true;
+        for (i, limit) in self.options.rate_limit.iter().enumerate() {
+            if !nmea::tag_matches(&limit.pattern, tag) {
+                continue;
+            }
+            let limiter = self.limiters.entry((i, tag.to_string())).or_default();
+            if part.is_some_and(|p| p > 1) {
+                ok &= limiter.group_sent;
+                continue;
+            }
+            let allowed = match (now, limiter.last) {
+                (Some(now), Some(last)) if limit.hz >= 1.0 => {
+                    now.with_nanosecond(0) != last.with_nanosecond(0) || limiter.in_this_second < limit.hz as u32
+                }
+                (Some(now), Some(last)) => (now - last).num_milliseconds() as f64 >= 1000.0 / limit.hz,
+                // No time yet, so nothing to go by
+                _ => true,
+            };
+            if allowed {
+                if let Some(now) = now {
+                    if limiter.last.map(|l| l.with_nanosecond(0)) != Some(now.with_nanosecond(0)) {
+                        limiter.in_this_second = 0;
+                    }
+                    limiter.last = Some(now);
+                }
+                limiter.in_this_second += 1;
+            }
+            limiter.group_sent = allowed;
+            ok &= allowed;
+        }
+        ok
+    }
+
+    // Every position is put the same distance and bearing (turned by --rotate)
+    // from the new start as it was from the old one, so the boat sails the same
+    // track just somewhere else and the speeds and distances don't change.
+    fn move_positions(&mut self, line: &str) -> Vec<String> {
+        let rotate = self.options.rotate.unwrap_or(0.0);
+        if line.starts_with('!') && nmea::tag(line).is_some_and(|t| t.ends_with("VDM") || t.ends_with("VDO")) {
+            return match self.assembler.add(line) {
+                Some(group) => self.move_ais(&group, rotate),
+                None => vec![],
+            };
+        }
+        let mut map = |lat: f64, lon: f64| Some(self.move_position(lat, lon));
+        vec![nmea::positions::rewrite(line, &mut map, rotate)]
+    }
+
+    // The other boats (and our own VDO) go along with the track. Only our own
+    // fixes can say where the track starts, so AIS with a position in it is
+    // left out until one has.
+    fn move_ais(&mut self, group: &[String], rotate: f64) -> Vec<String> {
+        let mut bits = match ais::message_bits(group) {
+            Some(bits) if bits.len() >= 38 => bits,
+            _ => return group.to_vec(),
+        };
+        if ais::motion_at(ais::uint(&bits, 0, 6)).0.is_none() {
+            return group.to_vec();
+        }
+        if self.start.is_none() {
+            return vec![];
+        }
+        ais::move_motion(&mut bits, |lat, lon| Some(self.move_position(lat, lon)), rotate);
+        ais::with_bits(group, &bits)
+    }
+
+    fn move_position(&mut self, lat: f64, lon: f64) -> (f64, f64) {
+        let o = self.options;
+        let (from, to) = *self.start.get_or_insert_with(|| {
+            let from = LatLon { lat, lon };
+            let to = match (o.move_start_to, o.translate) {
+                (Some(to), _) => to,
+                (None, Some(d)) => {
+                    let (lat, lon) = geo::normalize(lat + d.lat, lon + d.lon);
+                    LatLon { lat, lon }
+                }
+                (None, None) => from,
+            };
+            (from, to)
+        });
+        let d = geo::distance_m(from.lat, from.lon, lat, lon);
+        let b = geo::bearing(from.lat, from.lon, lat, lon);
+        let (lat, lon) = geo::destination(to.lat, to.lon, b + o.rotate.unwrap_or(0.0), d);
+        geo::normalize(lat, lon)
+    }
+}
+
+// "$IIVWR,..." -> "$GPVWR,..." with a new checksum. Proprietary sentences
+// don't have a talker so they are left alone.
+fn rewrite_talker(line: &str, maps: &[TalkerMap]) -> String {
+    let tag = match nmea::tag(line) {
+        Some(tag) if !tag.starts_with('P') => tag,
+        _ => return line.to_string(),
+    };
+    let map = match maps.iter().find(|m| tag[..2].eq_ignore_ascii_case(&m.from)) {
+        Some(map) => map,
+        None => return line.to_string(),
+    };
+    match nmea::split_fields(line) {
+        Some((mut fields, with_sum)) => {
+            fields[0] = format!("{}{}", map.to, &tag[2..]);
+            nmea::rebuild(line, &fields, with_sum)
+        }
+        None => line.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ais_position(lat: f64, lon: f64, cog: f64) -> Vec<String> {
+        ais::encode(&ais::Report {
+            kind: 1,
+            mmsi: 235_000_001,
+            pos: Some((lat, lon)),
+            sog: Some(5.0),
+            cog: Some(cog),
+            heading: Some(cog),
+            ..ais::Report::default()
+        })
+    }
+
+    fn decoded(line: &str) -> ais::Report {
+        ais::decode(&ais::message_bits(&[line.to_string()]).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn rate_limit_without_a_date() {
+        let options = RewriteOptions {
+            rate_limit: vec!["GSV=1".parse().unwrap()],
+            ..RewriteOptions::default()
+        };
+        let mut rewriter = Rewriter::new(&options).unwrap();
+        let mut sent = vec![];
+        // Two whole GSV groups a second, and no ZDA or RMC
+        for s in 0..3 {
+            for line in [
+                format!("$GPGGA,1200{:02},,,,,1,8,0.9,,M,,M", s),
+                "$GPGSV,2,1,08,01,40,083,46".to_string(),
+                "$GPGSV,2,2,08,02,17,308,41".to_string(),
+                "$GPGSV,2,1,08,01,40,083,46".to_string(),
+                "$GPGSV,2,2,08,02,17,308,41".to_string(),
+            ] {
+                sent.extend(rewriter.apply(&line));
+            }
+        }
+        assert_eq!(sent.iter().filter(|l| l.contains("GGA")).count(), 3);
+        assert_eq!(sent.iter().filter(|l| l.starts_with("$GPGSV,2,1")).count(), 3);
+        assert_eq!(sent.iter().filter(|l| l.starts_with("$GPGSV,2,2")).count(), 3);
+    }
+
+    // Everything that goes out for these lines
+    fn sent(options: &RewriteOptions, lines: &[String]) -> Vec<String> {
+        let mut rewriter = Rewriter::new(options).unwrap();
+        lines.iter().flat_map(|line| rewriter.apply(line)).collect()
+    }
+
+    #[test]
+    fn include_and_exclude() {
+        let lines: Vec<String> = ["$GPGGA,120000", "$GPGSV,1,1,08", "$GLGSV,1,1,08", "$IIVWR,1", "$PGRME,1", "not nmea"]
+            .iter()
+            .map(|l| l.to_string())
+            .collect();
+        let options = RewriteOptions {
+            include: vec!["GSV".to_string(), "*VWR".to_string()],
+            exclude: vec!["GL*".to_string()],
+            ..RewriteOptions::default()
+        };
+        assert_eq!(sent(&options, &lines), vec!["$GPGSV,1,1,08", "$IIVWR,1"]);
+        let options = RewriteOptions {
+            exclude: vec!["P*".to_string(), "gga".to_string()],
+            ..RewriteOptions::default()
+        };
+        assert_eq!(sent(&options, &lines), vec!["$GPGSV,1,1,08", "$GLGSV,1,1,08", "$IIVWR,1", "not nmea"]);
+    }
+
+    #[test]
+    fn rate_limits_by_talker_and_below_once_a_second() {
+        let mut lines = vec!["$GPZDA,120000,22,02,2021,00,00".to_string()];
+        for s in 0..4 {
+            lines.push(format!("$GPGGA,1200{:02},,,,,1", s));
+            lines.push(format!("$GNGGA,1200{:02},,,,,1", s));
+            lines.push(format!("$GPGGA,1200{:02},,,,,1", s));
+            lines.push("$SDDPT,5.2,0.0".to_string());
+        }
+        let count = |options: &RewriteOptions, start: &str| {
+            sent(options, &lines).iter().filter(|l| l.starts_with(start)).count()
+        };
+        let options = RewriteOptions {
+            rate_limit: vec!["GGA=1".parse().unwrap()],
+            ..RewriteOptions::default()
+        };
+        // Each talker has a limit of its own
+        assert_eq!((count(&options, "$GPGGA"), count(&options, "$GNGGA"), count(&options, "$SDDPT")), (4, 4, 4));
+        let options = RewriteOptions {
+            rate_limit: vec!["GPGGA=0.5".parse().unwrap()],
+            ..RewriteOptions::default()
+        };
+        let each_second: Vec<usize> = (0..4).map(|s| count(&options, &format!("$GPGGA,1200{:02}", s))).collect();
+        assert_eq!(each_second, vec![1, 0, 1, 0]);
+        assert_eq!(count(&options, "$GNGGA"), 4);
+        let options = RewriteOptions {
+            rate_limit: vec!["*=2".parse().unwrap()],
+            ..RewriteOptions::default()
+        };
+        assert_eq!((count(&options, "$GPGGA"), count(&options, "$GNGGA")), (8, 4));
+    }
+
+    #[test]
+    fn rate_limits_keep_ais_messages_whole() {
+        let statics = ais::encode(&ais::Report {
+            kind: 5,
+            mmsi: 235_000_001,
+            name: "NORMAN".to_string(),
+            ..ais::Report::default()
+        });
+        let mut lines = vec!["$GPZDA,120000,22,02,2021,00,00".to_string()];
+        for _ in 0..3 {
+            lines.extend(statics.iter().cloned());
+        }
+        lines.push("$GPZDA,120001,22,02,2021,00,00".to_string());
+        lines.extend(statics.iter().cloned());
+        let options = RewriteOptions {
+            rate_limit: vec!["AIVDM=1".parse().unwrap()],
+            ..RewriteOptions::default()
+        };
+        let out = sent(&options, &lines);
+        // One whole message in each second
+        assert_eq!(out, [&lines[..1], &statics[..], &lines[7..8], &statics[..]].concat());
+    }
+
+    #[test]
+    fn talkers_are_changed_with_a_new_checksum() {
+        let options = RewriteOptions {
+            rewrite_talker: vec!["ii=GP".parse().unwrap()],
+            ..RewriteOptions::default()
+        };
+        let lines = [
+            nmea::with_checksum("IIVWR,30.0,R,10.0,N,,,,"),
+            "$IIMWV,30,R".to_string(),
+            nmea::with_checksum("PIIXX,1"),
+        ];
+        assert_eq!(
+            sent(&options, &lines),
+            vec![nmea::with_checksum("GPVWR,30.0,R,10.0,N,,,,"), "$GPMWV,30,R".to_string(), lines[2].clone()]
+        );
+    }
+
+    #[test]
+    fn option_values() {
+        assert!("GSV=1".parse::<RateLimit>().is_ok());
+        assert!("GSV".parse::<RateLimit>().is_err());
+        assert!("GSV=0".parse::<RateLimit>().is_err());
+        assert!("GSV=fast".parse::<RateLimit>().is_err());
+        let map: TalkerMap = "ii=gp".parse().unwrap();
+        assert_eq!((map.from.as_str(), map.to.as_str()), ("II", "GP"));
+        assert!("II=GPS".parse::<TalkerMap>().is_err());
+        assert!("II".parse::<TalkerMap>().is_err());
+    }
+
+    #[test]
+    fn recorded_ais_moves_with_the_track() {
+        let options = RewriteOptions {
+            move_start_to: Some(LatLon { lat: 50.0, lon: -1.0 }),
+            rotate: Some(90.0),
+            ..RewriteOptions::default()
+        };
+        let mut rewriter = Rewriter::new(&options).unwrap();
+        // Before our first fix there's nowhere to put it
+        assert!(rewriter.apply(&ais_position(49.0, -123.0, 10.0)[0]).is_empty());
+        let fix = nmea::with_checksum("GPGGA,120000,4900.0000,N,12300.0000,W,1,8,0.9,,M,,M");
+        assert_eq!(rewriter.apply(&fix), vec![nmea::with_checksum("GPGGA,120000,5000.0000,N,00100.0000,W,1,8,0.9,,M,,M")]);
+        // A mile north of us ends up a mile east of the new start, heading 100
+        let lines = rewriter.apply(&ais_position(49.0 + 1.0 / 60.0, -123.0, 10.0)[0]);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(nmea::checksum_ok(&lines[0]), Some(true));
+        let r = decoded(&lines[0]);
+        let (lat, lon) = r.pos.unwrap();
+        assert!((geo::distance_m(50.0, -1.0, lat, lon) - 1852.0).abs() < 5.0);
+        assert!((geo::bearing(50.0, -1.0, lat, lon) - 90.0).abs() < 0.1);
+        assert_eq!((r.cog, r.heading, r.sog), (Some(100.0), Some(100.0), Some(5.0)));
+    }
+
+    #[test]
+    fn ais_without_a_position_goes_as_it_is() {
+        let options = RewriteOptions {
+            translate: Some(LatLon { lat: 1.0, lon: 1.0 }),
+            ..RewriteOptions::default()
+        };
+        let mut rewriter = Rewriter::new(&options).unwrap();
+        let lines = ais::encode(&ais::Report {
+            kind: 5,
+            mmsi: 235_000_001,
+            name: "NORMAN".to_string(),
+            ..ais::Report::default()
+        });
+        // Both fragments come out together once the second is in
+        assert!(rewriter.apply(&lines[0]).is_empty());
+        assert_eq!(rewriter.apply(&lines[1]), lines);
+    }
+}
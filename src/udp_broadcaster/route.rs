@@ -0,0 +1,212 @@
+use super::instruments::Instruments;
+use crate::converter::{self, Waypoint};
+use crate::geo;
+use crate::nmea::{self, build};
+use std::io;
+use std::path::Path;
+
+// Sailing a route the file knows nothing about, for trying out an autopilot
+// against a track we recorded. After every fix that goes out we work out where
+// we are with respect to the leg we're on (the cross track error, and the
+// bearing and distance to the waypoint at the end of it) and send it the way a
+// plotter would, as RMB, APB, XTE, BWC and BOD. The first leg starts from
+// wherever we are when the route starts. Getting inside the arrival circle
+// of a waypoint moves us on to the next one; at the last one we stay put.
+
+pub struct Route {
+    waypoints: Vec<Waypoint>,
+    arrival_nm: f64,
+    talker: String,
+    // Where we were when we started the route, for the start of the first leg
+    start: Option<(f64, f64)>,
+    // The waypoint we're heading for
+    active: usize,
+    // Whichever of GGA and RMC turns up first is the one we go by
+    fix_sentence: Option<String>,
+    clock: nmea::FileClock,
+    inst: Instruments,
+}
+
+impl Route {
+    pub fn load(path: &Path, arrival_nm: f64, talker: &str) -> io::Result<Route> {
+        let waypoints = converter::load_route(path).map_err(|e| io::Error::other(format!("{}: {}", path.display(), e)))?;
+        if waypoints.is_empty() {
+            return Err(io::Error::other(format!("{}: there are no waypoints in it", path.display())));
+        }
+        if arrival_nm <= 0.0 {
+            return Err(io::Error::other("--arrival-radius has to be more than zero"));
+        }
+        Ok(Route {
+            waypoints,
+            arrival_nm,
+            talker: talker.to_string(),
+            start: None,
+            active: 0,
+            fix_sentence: None,
+            clock: nmea::FileClock::new(),
+            inst: Instruments::new(),
+        })
+    }
+
+    // Hand it every line as it goes out; gives back the sentences to send
+    // after it, which is nothing unless it was a fix
+    pub fn process(&mut self, line: &str) -> Vec<String> {
+        let now = self.clock.update(line);
+        self.inst.update(line);
+        let kind = match nmea::sentence_type(line) {
+            Some(kind @ ("GGA" | "RMC")) => kind,
+            _ => return vec![],
+        };
+        if self.fix_sentence.get_or_insert_with(|| kind.to_string()) != kind {
+            return vec![];
+        }
+        let (lat, lon) = match self.inst.pos {
+            Some(pos) => pos,
+            None => return vec![],
+        };
+        let (start_lat, start_lon) = *self.start.get_or_insert((lat, lon));
+        let dest = &self.waypoints[self.active];
+        let (origin, from_lat, from_lon) = match self.active {
+            0 => ("", start_lat, start_lon),
+            i => (self.waypoints[i - 1].name.as_str(), self.waypoints[i - 1].lat, self.waypoints[i - 1].lon),
+        };
+        let range_m = geo::distance_m(lat, lon, dest.lat, dest.lon);
+        let bearing = geo::bearing(lat, lon, dest.lat, dest.lon);
+        let bod = geo::bearing(from_lat, from_lon, dest.lat, dest.lon);
+        // How far we are off the line from the origin to the waypoint, and
+        // whether we are past the line square to it through the waypoint
+        let off = angle_between(geo::bearing(from_lat, from_lon, lat, lon), bod);
+        let xte_m = geo::distance_m(from_lat, from_lon, lat, lon) * off.to_radians().sin();
+        let passed = angle_between(geo::bearing(dest.lat, dest.lon, lat, lon), bod).abs() < 90.0;
+        let d = &self.inst.data;
+        let vmg_kts = match (d.sog_kts, d.cog_t) {
+            (Some(sog), Some(cog)) => sog * angle_between(cog, bearing).to_radians().cos(),
+            _ => 0.0,
+        };
+        let arrived = range_m <= self.arrival_nm * 1852.0;
+        let steering = build::Steering {
+            origin,
+            dest: &dest.name,
+            dest_lat: dest.lat,
+            dest_lon: dest.lon,
+            xte_nm: xte_m / 1852.0,
+            range_nm: range_m / 1852.0,
+            bearing,
+            bod,
+            vmg_kts,
+            arrived,
+            passed,
+        };
+        let mut lines = vec![
+            build::rmb(&self.talker, &steering),
+            build::apb(&self.talker, &steering),
+            build::xte(&self.talker, &steering),
+        ];
+        if let Some(now) = now {
+            lines.push(build::bwc(&self.talker, now, &steering));
+        }
+        lines.push(build::bod(&self.talker, &steering));
+        // The arrival goes out once, then it's on to the next leg
+        if arrived && self.active + 1 < self.waypoints.len() {
+            self.active += 1;
+        }
+        lines
+    }
+}
+
+// From b round to a, -180 to 180 clockwise
+fn angle_between(a: f64, b: f64) -> f64 {
+    (a - b + 180.0).rem_euclid(360.0) - 180.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    fn route(name: &str, text: &str, arrival_nm: f64) -> io::Result<Route> {
+        let path = std::env::temp_dir().join(format!("nmea_player_route_{}_{}", std::process::id(), name));
+        std::fs::write(&path, text).unwrap();
+        let route = Route::load(&path, arrival_nm, "II");
+        std::fs::remove_file(&path).unwrap();
+        route
+    }
+
+    fn at(s: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2021, 2, 22).unwrap().and_hms_opt(12, 0, s).unwrap()
+    }
+
+    // A fix so many miles north and east of 50N 1W
+    fn fix(s: u32, north: f64, east: f64) -> String {
+        let (lat, lon) = geo::destination(50.0, -1.0, 0.0, north * 1852.0);
+        let (lat, lon) = geo::destination(lat, lon, 90.0, east * 1852.0);
+        build::rmc("GP", at(s), lat, lon, 6.0, 0.0)
+    }
+
+    fn field(line: &str, i: usize) -> String {
+        line.split(['*', ',']).nth(i).unwrap().to_string()
+    }
+
+    // A mile north of the start and then a mile east of that
+    fn two_waypoints() -> Route {
+        let (lat, lon) = geo::destination(50.0, -1.0, 0.0, 1852.0);
+        let (lat2, lon2) = geo::destination(lat, lon, 90.0, 1852.0);
+        let text = format!("name,lat,lon\nNORTH,{},{}\nEAST,{},{}\n", lat, lon, lat2, lon2);
+        route("two.csv", &text, 0.1).unwrap()
+    }
+
+    #[test]
+    fn steering_along_the_first_leg() {
+        let mut route = two_waypoints();
+        let lines = route.process(&fix(0, 0.0, 0.0));
+        let kinds: Vec<&str> = lines.iter().filter_map(|l| nmea::sentence_type(l)).collect();
+        assert_eq!(kinds, vec!["RMB", "APB", "XTE", "BWC", "BOD"]);
+        assert!(lines.iter().all(|l| l.starts_with("$II") && nmea::checksum_ok(l) == Some(true)));
+        // Right of the line steers left, left of it steers right
+        let lines = route.process(&fix(1, 0.5, 0.1));
+        assert_eq!(lines[2], nmea::with_checksum("IIXTE,A,A,0.100,L,N,A"));
+        assert_eq!((field(&lines[0], 4), field(&lines[0], 5)), (String::new(), "NORTH".to_string()));
+        let lines = route.process(&fix(2, 0.5, -0.1));
+        assert_eq!((field(&lines[2], 3), field(&lines[2], 4)), ("0.100".to_string(), "R".to_string()));
+        assert_eq!(field(&lines[4], 1), "0.0");
+    }
+
+    #[test]
+    fn arriving_moves_on_to_the_next_leg() {
+        let mut route = two_waypoints();
+        route.process(&fix(0, 0.0, 0.0));
+        let lines = route.process(&fix(1, 0.95, 0.0));
+        // Arrived, but not past the waypoint yet
+        assert_eq!(field(&lines[0], 13), "A");
+        assert_eq!((field(&lines[1], 6), field(&lines[1], 7)), ("A".to_string(), "V".to_string()));
+        let lines = route.process(&fix(2, 1.0, 0.5));
+        assert_eq!((field(&lines[0], 4), field(&lines[0], 5)), ("NORTH".to_string(), "EAST".to_string()));
+        assert_eq!(field(&lines[4], 1), "90.0");
+        assert_eq!(field(&lines[0], 13), "V");
+        // The last one stays the one we're heading for
+        route.process(&fix(3, 1.0, 0.95));
+        let lines = route.process(&fix(4, 1.0, 1.5));
+        assert_eq!(field(&lines[0], 5), "EAST");
+        assert_eq!(field(&lines[1], 7), "A");
+    }
+
+    #[test]
+    fn only_the_first_kind_of_fix_counts() {
+        let mut route = two_waypoints();
+        assert!(route.process("$SDDPT,5.2,0.0").is_empty());
+        // A GGA without a date makes no BWC
+        let gga = "$GPGGA,120000,5000.00,N,00100.00,W,1,8,0.9,,M,,M";
+        assert_eq!(route.process(gga).len(), 4);
+        assert!(route.process(&fix(1, 0.0, 0.0)).is_empty());
+        assert_eq!(route.process(gga).len(), 5);
+    }
+
+    #[test]
+    fn loading_a_route() {
+        let error = |name: &str, text: &str, arrival_nm: f64| route(name, text, arrival_nm).err().unwrap().to_string();
+        assert!(error("empty.gpx", "<gpx></gpx>", 0.1).ends_with("there are no waypoints in it"));
+        assert!(error("none.csv", "name,lat\nA,50\n", 0.1).ends_with("the CSV needs lat and lon columns"));
+        assert!(error("route.kml", "", 0.1).ends_with("a route has to be a GPX or CSV file"));
+        assert_eq!(error("zero.csv", "lat,lon\n50,-1\n", 0.0), "--arrival-radius has to be more than zero");
+    }
+}
@@ -1,10 +1,41 @@
+use super::instruments::Instruments;
+use crate::geo::{self, Motion};
 use chrono::{Utc, NaiveDateTime, Duration};
 use pancurses::{noecho, initscr, endwin, Input::Character, Window, A_REVERSE};
+use std::ops::Deref;
 use std::process::exit;
 
-pub fn new() -> Window {
+// The clocks shown at the top of the screen. For a file these are the start
+// times of the file and of playback; for live data there is nothing to keep in
+// synch with, so the difference line just stays at zero.
+pub struct Timing {
+    pub file_start: NaiveDateTime,
+    pub locl_start: NaiveDateTime,
+    pub file_time: NaiveDateTime,
+    pub sleep: Duration,
+}
+
+// The curses window, which puts the terminal back when it goes out of scope,
+// so an error part way through doesn't leave the terminal in a mess
+pub struct Screen(Window);
+
+impl Deref for Screen {
+    type Target = Window;
+
+    fn deref(&self) -> &Window {
+        &self.0
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        window_cleanup(&self.0);
+    }
+}
+
+pub fn new() -> Screen {
     // Initialize curses
-    initscr()
+    Screen(initscr())
 }
 
 pub fn window_cleanup(win: &Window) -> bool {
@@ -14,7 +45,7 @@ pub fn window_cleanup(win: &Window) -> bool {
     true
 }
 
-pub fn paint(window: &Window, fst: NaiveDateTime, lst: NaiveDateTime, dt: NaiveDateTime, sleep: Duration, lat_s: &str, lon_s: &str, cog: &str, sog: &str, dpt: &str, wnd: &str, loc: &str, msg: &str,) -> bool {
+pub fn paint(window: &Window, timing: &Timing, inst: &Instruments, msg: &str) -> bool {
     // Start Date and Time for file and local clock
     window.mv(0, 0);
     window.clrtoeol();
@@ -22,14 +53,14 @@ pub fn paint(window: &Window, fst: NaiveDateTime, lst: NaiveDateTime, dt: NaiveD
     window.addstr("File Start :");
     window.attroff(A_REVERSE);
     window.addstr(" ");
-    window.addstr(fst.format("%Y-%m-%d %H:%M:%S").to_string());
+    window.addstr(timing.file_start.format("%Y-%m-%d %H:%M:%S").to_string());
     window.addstr(" UTC");
     window.mv(0, 40);
     window.attron(A_REVERSE);
     window.addstr("Local Start :");
     window.attroff(A_REVERSE);
     window.addstr(" ");
-    window.addstr(lst.format("%Y-%m-%d %H:%M:%S").to_string());
+    window.addstr(timing.locl_start.format("%Y-%m-%d %H:%M:%S").to_string());
     window.addstr(" UTC");
     // Date and Time
     window.mv(1, 0);
@@ -38,7 +69,7 @@ pub fn paint(window: &Window, fst: NaiveDateTime, lst: NaiveDateTime, dt: NaiveD
     window.addstr("File Time  :");
     window.attroff(A_REVERSE);
     window.addstr(" ");
-    window.addstr(dt.to_string());
+    window.addstr(timing.file_time.to_string());
     window.addstr(" UTC");
     window.mv(1, 40);
     window.attron(A_REVERSE);
@@ -50,7 +81,7 @@ pub fn paint(window: &Window, fst: NaiveDateTime, lst: NaiveDateTime, dt: NaiveD
     window.mv(2, 0);
     window.clrtoeol();
     window.addstr("Difference between real elapsed time and file elapsed time = ");
-    window.addstr(sleep.num_milliseconds().to_string());
+    window.addstr(timing.sleep.num_milliseconds().to_string());
     window.addstr(" ms");
     // Latitude
     window.mv(4, 0);
@@ -59,75 +90,128 @@ pub fn paint(window: &Window, fst: NaiveDateTime, lst: NaiveDateTime, dt: NaiveD
     window.addstr("Latitude");
     window.attroff(A_REVERSE);
     window.addstr(" ");
-    window.addstr(lat_s.to_string());
+    window.addstr(&inst.lat_s);
     // Longitude
     window.mv(4, 40);
     window.attron(A_REVERSE);
     window.addstr("Longitude");
     window.attroff(A_REVERSE);
     window.addstr(" ");
-    window.addstr(lon_s.to_string());
-    // COG and SOG
-    window.mv(6, 0);
-    window.clrtoeol();
-    window.attron(A_REVERSE);
-    window.addstr("COG:");
-    window.attroff(A_REVERSE);
-    window.addstr(" ");
-    window.addstr(cog.to_string());
-    window.mv(6, 40);
-    window.attron(A_REVERSE);
-    window.addstr("SOG:");
-    window.attroff(A_REVERSE);
-    window.addstr(" ");
-    window.addstr(sog.to_string());
-    // Depth
-    window.mv(8, 0);
-    window.clrtoeol();
-    window.attron(A_REVERSE);
-    window.addstr("Depth :");
-    window.attroff(A_REVERSE);
-    window.addstr(" ");
-    window.addstr(dpt.to_string());
+    window.addstr(&inst.lon_s);
+    // Navigation
+    panel(window, 6, 0, "COG:", &inst.cog);
+    panel(window, 6, 40, "SOG:", &inst.sog);
+    panel(window, 7, 0, "HDG:", &inst.hdg);
+    panel(window, 7, 40, "STW:", &inst.stw);
+    // Depth, water and log
+    panel(window, 8, 0, "Depth :", &inst.dpt);
+    panel(window, 8, 40, "Water :", &inst.tmp);
+    panel(window, 9, 0, "Log   :", &inst.log);
     // Wind
-    window.mv(10, 0);
-    window.clrtoeol();
-    window.attron(A_REVERSE);
-    window.addstr("Wind :");
-    window.attroff(A_REVERSE);
-    window.addstr(" ");
-    window.addstr(wnd.to_string());
+    panel(window, 10, 0, "Wind :", &inst.wnd);
+    panel(window, 11, 0, "True Wind :", &inst.twnd);
+    // Boat
+    panel(window, 12, 0, "Rudder :", &inst.rud);
+    panel(window, 12, 40, "Attitude :", &inst.att);
+    panel(window, 13, 0, "Barometer :", &inst.bar);
     // Location and Random message
-    window.mv(12, 0);
-    window.clrtoeol();
-    window.attron(A_REVERSE);
-    window.addstr("Location :");
-    window.attroff(A_REVERSE);
-    window.addstr(" ");
-    window.addstr(loc.to_string());
-    window.mv(13, 0);
-    window.clrtoeol();
-    window.attron(A_REVERSE);
-    window.addstr("Message  :");
-    window.attroff(A_REVERSE);
-    window.addstr(" ");
-    window.addstr(msg.to_string());
-    
+    panel(window, 15, 0, "Location :", &inst.whr);
+    panel(window, 16, 0, "Message  :", msg);
+    ais_targets(window, 18, inst);
+
     // Cursor back to home position
     window.mv(0, 0);
     window.nodelay(true);
     noecho(); // set terminal echo mode off
 
-    let char = window.getch();
-    match char {
-        Some(x) => {
-            if x == Character('q') {
-                window_cleanup(window);
-                exit(0);
-            }
-        }
-        None => {}
+    if let Some(Character('q')) = window.getch() {
+        window_cleanup(window);
+        exit(0);
     }
     window.refresh();
     true
 }
+
+// A label and its reading. The one at the start of a row clears the row first.
+fn panel(window: &Window, y: i32, x: i32, label: &str, value: &str) {
+    window.mv(y, x);
+    if x == 0 {
+        window.clrtoeol();
+    }
+    window.attron(A_REVERSE);
+    window.addstr(label);
+    window.attroff(A_REVERSE);
+    window.addstr(" ");
+    window.addstr(value);
+}
+
+// How long a target can go unheard before we stop showing it
+const AIS_TARGET_TIMEOUT_MINUTES: i64 = 10;
+
+// The AIS targets heard from lately, nearest first, with range and bearing
+// from us and CPA/TCPA if both of us keep going as we are. As many as fit.
+fn ais_targets(window: &Window, top: i32, inst: &Instruments) {
+    let own = inst.pos.map(|(lat, lon)| Motion {
+        lat,
+        lon,
+        cog: inst.data.cog_t.unwrap_or(0.0),
+        sog: inst.data.sog_kts.unwrap_or(0.0),
+    });
+    let recent = |seen: Option<NaiveDateTime>| match (seen, inst.dt) {
+        (Some(seen), Some(now)) => now - seen <= Duration::minutes(AIS_TARGET_TIMEOUT_MINUTES),
+        _ => true,
+    };
+    let mut rows: Vec<(f64, String)> = vec![];
+    for t in inst.ais.list.values().filter(|t| recent(t.last_seen)) {
+        let opt = |v: Option<f64>, places: usize| v.map_or("-".to_string(), |v| format!("{:.*}", places, v));
+        let (range, brg, cpa, tcpa) = match (own, t.motion()) {
+            (Some(own), Some(other)) => {
+                let (cpa, tcpa) = geo::cpa(own, other);
+                (
+                    Some(geo::distance_m(own.lat, own.lon, other.lat, other.lon) / 1852.0),
+                    Some(geo::bearing(own.lat, own.lon, other.lat, other.lon)),
+                    Some(cpa),
+                    Some(tcpa),
+                )
+            }
+            _ => (None, None, None, None),
+        };
+        let tcpa = match tcpa {
+            Some(m) if m < 0.0 => "passed".to_string(),
+            m => opt(m, 1),
+        };
+        let line = format!(
+            "{:<9} {:<20} {:>6} {:>4} {:>4} {:>5} {:>6} {:>7}",
+            t.mmsi,
+            t.display_name(),
+            opt(range, 2),
+            opt(brg, 0),
+            opt(t.cog, 0),
+            opt(t.sog, 1),
+            opt(cpa, 2),
+            tcpa
+        );
+        rows.push((range.unwrap_or(f64::MAX), line));
+    }
+    rows.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let count = format!("{}  (range and CPA in nm, TCPA in minutes)", rows.len());
+    panel(window, top, 0, "AIS targets :", &count);
+    window.mv(top + 1, 0);
+    window.clrtoeol();
+    window.addstr(format!(
+        "{:<9} {:<20} {:>6} {:>4} {:>4} {:>5} {:>6} {:>7}",
+        "MMSI", "Name", "Range", "Brg", "COG", "SOG", "CPA", "TCPA"
+    ));
+    let mut y = top + 2;
+    for (_, line) in &rows {
+        if y >= window.get_max_y() {
+            break;
+        }
+        window.mv(y, 0);
+        window.clrtoeol();
+        window.addstr(line);
+        y += 1;
+    }
+    window.mv(y, 0);
+    window.clrtobot();
+}
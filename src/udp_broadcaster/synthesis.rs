@@ -0,0 +1,215 @@
+use super::instruments::Instruments;
+use crate::geo;
+use crate::nmea::{self, build};
+use chrono::{Duration, NaiveDate, NaiveDateTime, Timelike};
+
+// Fills in sentences that a plotter needs but the file doesn't have: RMC from
+// GGA and VTG (and a date), ZDA from RMC, VTG from one position to the next,
+// HDT from the course when there's no heading, and true wind (MWV and MWD)
+// from the apparent wind, speed and heading. Each one is only made up
+// until the file turns out to have the real thing, so a file that has it all
+// goes out just as it is.
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Synth {
+    All,
+    Rmc,
+    Zda,
+    Vtg,
+    Hdt,
+    TrueWind,
+}
+
+pub struct Synthesizer {
+    talker: String,
+    rmc: bool,
+    zda: bool,
+    vtg: bool,
+    hdt: bool,
+    true_wind: bool,
+    date: Option<NaiveDate>,
+    // What the file itself has had so far
+    has_rmc: bool,
+    has_zda: bool,
+    has_vtg: bool,
+    has_heading: bool,
+    // Whichever of GGA and RMC turns up first is the one we make the others from
+    fix_sentence: Option<String>,
+    // Course and speed from VTG or RMC, if the file has them
+    cog_sog: Option<(f64, f64)>,
+    last_fix: Option<(NaiveDateTime, f64, f64)>,
+    last_zda: Option<NaiveDateTime>,
+    // Everything else we need for true wind
+    inst: Instruments,
+}
+
+impl Synthesizer {
+    pub fn new(what: &[Synth], talker: &str, date: Option<NaiveDate>) -> Synthesizer {
+        let want = |s: Synth| what.contains(&s) || what.contains(&Synth::All);
+        Synthesizer {
+            talker: talker.to_string(),
+            rmc: want(Synth::Rmc),
+            zda: want(Synth::Zda),
+            vtg: want(Synth::Vtg),
+            hdt: want(Synth::Hdt),
+            true_wind: want(Synth::TrueWind),
+            date,
+            has_rmc: false,
+            has_zda: false,
+            has_vtg: false,
+            has_heading: false,
+            fix_sentence: None,
+            cog_sog: None,
+            last_fix: None,
+            last_zda: None,
+            inst: Instruments::new(),
+        }
+    }
+
+    // The line itself, with anything made up from it before or after it
+    pub fn process(&mut self, line: &str) -> Vec<String> {
+        let fields: Vec<&str> = line.split([',', '*']).collect();
+        let kind = nmea::sentence_type(line);
+        // True wind goes out right after each apparent wind it was worked out
+        // from, for as long as the file hasn't got true wind of its own
+        self.inst.update(line);
+        if self.true_wind && self.inst.new_true_wind && self.inst.true_wind_computed {
+            let d = &self.inst.data;
+            let mut lines = vec![line.to_string()];
+            if let (Some(twa), Some(tws)) = (d.twa, d.tws_kts) {
+                lines.push(build::mwv(&self.talker, twa, "T", tws));
+                if let Some(twd) = d.twd_t {
+                    lines.push(build::mwd(&self.talker, twd, tws));
+                }
+            }
+            return lines;
+        }
+        match kind {
+            // $GPZDA,234626.99,22,02,2021,08,00*6A
+            Some("ZDA") => {
+                self.has_zda = true;
+                if let Some(dt) = nmea::parse_zda(&fields) {
+                    self.date = Some(dt.date());
+                }
+            }
+            // $GPRMC,020659.21,A,4937.8509,N,12401.4384,W,0.1,359.5,220221,,,D*7A
+            Some("RMC") if fields.len() > 9 => {
+                self.has_rmc = true;
+                if let Some(date) = nmea::parse_ddmmyy(fields[9]) {
+                    self.date = Some(date);
+                }
+                if let (Ok(sog), Ok(cog)) = (fields[7].parse(), fields[8].parse()) {
+                    self.cog_sog = Some((cog, sog));
+                }
+            }
+            // $IIVTG,359.5,T,,M,0.1,N,0.1,K,D*15
+            Some("VTG") if fields.len() > 5 => {
+                self.has_vtg = true;
+                if let (Ok(cog), Ok(sog)) = (fields[1].parse(), fields[5].parse()) {
+                    self.cog_sog = Some((cog, sog));
+                }
+            }
+            Some("HDT" | "HDG" | "HDM") => self.has_heading = true,
+            // $IIVHW,101.1,T,,M,5.2,N,9.6,K*55 only has a heading when it is filled in
+            Some("VHW") if fields.len() > 3 && (!fields[1].is_empty() || !fields[3].is_empty()) => {
+                self.has_heading = true
+            }
+            _ => {}
+        }
+        let (time_field, pos) = match kind {
+            Some("GGA") if fields.len() > 6 && fields[6] != "0" => {
+                (fields[1], nmea::parse_lat_lon(fields[2], fields[3], fields[4], fields[5]))
+            }
+            Some("RMC") if fields.len() > 9 && fields[2] == "A" => {
+                (fields[1], nmea::parse_lat_lon(fields[3], fields[4], fields[5], fields[6]))
+            }
+            _ => return vec![line.to_string()],
+        };
+        let kind = kind.unwrap_or_default();
+        if self.fix_sentence.get_or_insert_with(|| kind.to_string()) != kind {
+            return vec![line.to_string()];
+        }
+        let (time, (lat, lon)) = match (self.time(time_field), pos) {
+            (Some(time), Some(pos)) => (time, pos),
+            _ => return vec![line.to_string()],
+        };
+
+        // Course and speed from the last fix, when the file doesn't say
+        let derived = self.last_fix.and_then(|(t, lat1, lon1)| {
+            let secs = (time - t).num_milliseconds() as f64 / 1000.0;
+            (secs > 0.0).then(|| {
+                let sog = geo::distance_m(lat1, lon1, lat, lon) / secs * 3600.0 / 1852.0;
+                (geo::bearing(lat1, lon1, lat, lon), sog)
+            })
+        });
+        self.last_fix = Some((time, lat, lon));
+        let cog_sog = self.cog_sog.or(derived);
+        let talker = self.talker.as_str();
+
+        let dated = self.date.is_some();
+        let mut lines = vec![];
+        // A ZDA each second ahead of the fix, so the player can keep time
+        let new_second = self.last_zda.and_then(|z| z.with_nanosecond(0)) != time.with_nanosecond(0);
+        if self.zda && !self.has_zda && dated && new_second {
+            lines.push(build::zda(talker, time));
+            self.last_zda = Some(time);
+        }
+        lines.push(line.to_string());
+        if self.rmc && !self.has_rmc && dated {
+            let (cog, sog) = cog_sog.unwrap_or((0.0, 0.0));
+            lines.push(build::rmc(talker, time, lat, lon, sog, cog));
+        }
+        if let Some((cog, sog)) = cog_sog {
+            if self.vtg && !self.has_vtg {
+                lines.push(build::vtg(talker, sog, cog));
+            }
+            if self.hdt && !self.has_heading {
+                lines.push(build::hdt(talker, cog));
+            }
+        }
+        lines
+    }
+
+    // The time of a fix on the date we know, moving on a day at midnight. Without
+    // a date any day does for working out speeds, but not for RMC or ZDA.
+    fn time(&mut self, field: &str) -> Option<NaiveDateTime> {
+        let any_day = NaiveDate::from_ymd_opt(2000, 1, 1).unwrap();
+        let mut t = self.date.unwrap_or(any_day).and_time(nmea::parse_hms_fraction(field)?);
+        if let Some((last, _, _)) = self.last_fix {
+            if last - t > Duration::hours(12) {
+                t += Duration::days(1);
+                self.date = self.date.map(|_| t.date());
+            }
+        }
+        Some(t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(synthesizer: &mut Synthesizer, lines: &[&str]) -> Vec<String> {
+        lines.iter().flat_map(|line| synthesizer.process(line)).collect()
+    }
+
+    #[test]
+    fn heading_from_the_course_only_without_a_real_one() {
+        let vtg = "$IIVTG,359.5,T,,M,5.1,N,9.4,K";
+        let gga = "$GPGGA,120000,4937.8509,N,12401.4384,W,1,8,0.9,,M,,M";
+        let mut synthesizer = Synthesizer::new(&[Synth::Hdt], "II", None);
+        let lines = run(&mut synthesizer, &[vtg, gga]);
+        assert_eq!(lines, vec![vtg.to_string(), gga.to_string(), build::hdt("II", 359.5)]);
+        assert!(lines[2].starts_with("$IIHDT,"));
+
+        // The heading in VHW is the real thing
+        let mut synthesizer = Synthesizer::new(&[Synth::Hdt], "II", None);
+        let vhw = "$IIVHW,101.1,T,,M,5.2,N,9.6,K";
+        assert_eq!(run(&mut synthesizer, &[vhw, vtg, gga]), vec![vhw, vtg, gga]);
+
+        // but not when it's only got the speed through the water
+        let mut synthesizer = Synthesizer::new(&[Synth::Hdt], "II", None);
+        let vhw = "$IIVHW,,T,,M,5.2,N,9.6,K";
+        assert_eq!(run(&mut synthesizer, &[vhw, vtg, gga]).len(), 4);
+    }
+}
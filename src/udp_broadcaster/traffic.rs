@@ -0,0 +1,417 @@
+use super::instruments::Instruments;
+use crate::ais::Report;
+use crate::geo::{self, Motion};
+use chrono::{Duration, NaiveDateTime};
+use std::io;
+use std::path::Path;
+
+// Made up AIS traffic from a scenario file, for trying out the collision
+// alarms on a plotter. Each vessel either sails a straight line from where it
+// starts, follows a list of waypoints, or is set up to cross us (in which case
+// it has no start, course or waypoints of its own): it comes in
+// at an angle to our course and passes at a given distance after a given time,
+// worked out from where we are and where we're going when the scenario starts.
+// In TOML:
+//
+//     [[vessel]]
+//     mmsi = 235000001
+//     name = "NORMAN"
+//     start = [50.77, -1.25]
+//     course = 250
+//     speed = 14
+//
+//     [[vessel]]
+//     name = "SHORTCUT"
+//     class = "B"
+//     start = [50.76, -1.32]
+//     waypoints = [[50.77, -1.30], [50.78, -1.26]]
+//     speed = 6
+//
+//     [[vessel]]
+//     name = "TROUBLE"
+//     crossing = { angle = 90, cpa = 0.05, tcpa = 8 }
+//     speed = 12
+//
+// or the same in JSON as {"vessel": [{...}, ...]}. Speeds are knots, angles
+// degrees, cpa nautical miles (negative passes on the other side) and tcpa
+// minutes. Reports go out as often as the real thing would send them, by the
+// file clock.
+
+struct Vessel {
+    mmsi: u32,
+    name: String,
+    class_b: bool,
+    ship_type: u8,
+    speed: f64,
+    plan: Plan,
+    next_report: Option<NaiveDateTime>,
+    next_static: Option<NaiveDateTime>,
+}
+
+enum Plan {
+    Straight { lat: f64, lon: f64, course: f64 },
+    Waypoints(Vec<(f64, f64)>),
+    // Becomes Straight once we know where we are
+    Crossing { angle: f64, cpa: f64, tcpa: f64 },
+}
+
+pub struct Traffic {
+    vessels: Vec<Vessel>,
+    own: Instruments,
+    start: Option<NaiveDateTime>,
+}
+
+// Static data goes out every six minutes, for class A and B alike
+const STATIC_EVERY_MINUTES: i64 = 6;
+
+impl Traffic {
+    pub fn load(path: &Path) -> io::Result<Traffic> {
+        let text = std::fs::read_to_string(path)?;
+        let is_json = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json"));
+        let scenario = if is_json {
+            json::parse(&text).map_err(|e| io::Error::other(format!("{}: {}", path.display(), e)))?
+        } else {
+            let table: toml::Table = text.parse().map_err(|e| io::Error::other(format!("{}: {}", path.display(), e)))?;
+            toml_to_json(&toml::Value::Table(table))
+        };
+        let list = if scenario.has_key("vessels") { &scenario["vessels"] } else { &scenario["vessel"] };
+        let mut vessels = vec![];
+        for (i, v) in list.members().enumerate() {
+            let vessel = vessel(i, v).map_err(|e| io::Error::other(format!("{}: vessel {}: {}", path.display(), i + 1, e)))?;
+            vessels.push(vessel);
+        }
+        if vessels.is_empty() {
+            return Err(io::Error::other(format!("{}: there are no vessels in it", path.display())));
+        }
+        Ok(Traffic {
+            vessels,
+            own: Instruments::new(),
+            start: None,
+        })
+    }
+
+    // Hand it every line from the file, to keep up with where we are
+    pub fn observe(&mut self, line: &str) {
+        self.own.update(line);
+    }
+
+    // The reports that are due by the file time. The scenario starts once the
+    // file has given us a time, a position and a course and speed.
+    pub fn due(&mut self, now: Option<NaiveDateTime>) -> Vec<Report> {
+        let now = match now {
+            Some(now) => now,
+            None => return vec![],
+        };
+        if self.start.is_none() {
+            let d = &self.own.data;
+            let own = match (self.own.pos, d.cog_t, d.sog_kts) {
+                (Some((lat, lon)), Some(cog), Some(sog)) => Motion { lat, lon, cog, sog },
+                _ => return vec![],
+            };
+            for v in self.vessels.iter_mut() {
+                if let Plan::Crossing { angle, cpa, tcpa } = v.plan {
+                    v.plan = crossing(own, angle, cpa, tcpa, v.speed);
+                }
+            }
+            self.start = Some(now);
+        }
+        let hours = self.start.map_or(0.0, |start| (now - start).num_milliseconds() as f64 / 3_600_000.0);
+        let mut reports = vec![];
+        for v in self.vessels.iter_mut() {
+            let (lat, lon, cog, sog) = v.at(hours);
+            if v.next_static.is_none_or(|next| now >= next) {
+                v.next_static = Some(now + Duration::minutes(STATIC_EVERY_MINUTES));
+                reports.push(Report {
+                    kind: if v.class_b { 24 } else { 5 },
+                    mmsi: v.mmsi,
+                    name: v.name.clone(),
+                    ship_type: Some(v.ship_type),
+                    ..Report::default()
+                });
+            }
+            if v.next_report.is_none_or(|next| now >= next) {
+                v.next_report = Some(now + reporting_interval(v.class_b, sog));
+                reports.push(Report {
+                    kind: if v.class_b { 18 } else { 1 },
+                    mmsi: v.mmsi,
+                    pos: Some((lat, lon)),
+                    cog: Some(cog),
+                    sog: Some(sog),
+                    heading: Some(cog),
+                    ..Report::default()
+                });
+            }
+        }
+        reports
+    }
+}
+
+impl Vessel {
+    // Position, course and speed this many hours into the scenario
+    fn at(&self, hours: f64) -> (f64, f64, f64, f64) {
+        let run_m = self.speed * hours * 1852.0;
+        match &self.plan {
+            Plan::Straight { lat, lon, course } => {
+                let (lat, lon) = geo::destination(*lat, *lon, *course, run_m);
+                (lat, lon, *course, self.speed)
+            }
+            Plan::Waypoints(points) => {
+                let mut left = run_m;
+                let mut course = 0.0;
+                for leg in points.windows(2) {
+                    let ((lat1, lon1), (lat2, lon2)) = (leg[0], leg[1]);
+                    let length = geo::distance_m(lat1, lon1, lat2, lon2);
+                    course = geo::bearing(lat1, lon1, lat2, lon2);
+                    if left <= length {
+                        let (lat, lon) = geo::destination(lat1, lon1, course, left);
+                        return (lat, lon, course, self.speed);
+                    }
+                    left -= length;
+                }
+                // Stopped at the last waypoint
+                let (lat, lon) = points[points.len() - 1];
+                (lat, lon, course, 0.0)
+            }
+            Plan::Crossing { .. } => (0.0, 0.0, 0.0, 0.0),
+        }
+    }
+}
+
+// Where a vessel has to start to pass cpa miles off us tcpa minutes from now.
+// Its course is ours turned by the angle, so 90 crosses from our port side to
+// starboard.
+fn crossing(own: Motion, angle: f64, cpa: f64, tcpa: f64, speed: f64) -> Plan {
+    let course = (own.cog + angle).rem_euclid(360.0);
+    let hours = tcpa / 60.0;
+    // Where we will be at the time of the CPA
+    let (lat, lon) = geo::destination(own.lat, own.lon, own.cog, own.sog * hours * 1852.0);
+    // The closest point is square on to the way it moves relative to us
+    let velocity = |c: f64, s: f64| (s * c.to_radians().sin(), s * c.to_radians().cos());
+    let ((ox, oy), (tx, ty)) = (velocity(own.cog, own.sog), velocity(course, speed));
+    let relative = (tx - ox).atan2(ty - oy).to_degrees();
+    let (lat, lon) = geo::destination(lat, lon, relative + 90.0, cpa * 1852.0);
+    // and back along its course to where it starts
+    let (lat, lon) = geo::destination(lat, lon, course + 180.0, speed * hours * 1852.0);
+    Plan::Straight { lat, lon, course }
+}
+
+// How often the real thing reports: class A by its speed, class B (the usual
+// carrier sense kind) every 30 seconds when moving and 3 minutes when not
+fn reporting_interval(class_b: bool, sog: f64) -> Duration {
+    match (class_b, sog) {
+        (_, s) if s < 2.0 => Duration::minutes(3),
+        (true, _) => Duration::seconds(30),
+        (false, s) if s <= 14.0 => Duration::seconds(10),
+        (false, s) if s <= 23.0 => Duration::seconds(6),
+        _ => Duration::seconds(2),
+    }
+}
+
+fn vessel(i: usize, v: &json::JsonValue) -> Result<Vessel, String> {
+    let number = |key: &str| v[key].as_f64();
+    let lat_lon = |p: &json::JsonValue| match (p[0].as_f64(), p[1].as_f64()) {
+        (Some(lat), Some(lon)) => Ok((lat, lon)),
+        _ => Err(format!("'{}' should be [lat, lon]", p.dump())),
+    };
+    let class_b = match v["class"].as_str() {
+        None | Some("A" | "a") => false,
+        Some("B" | "b") => true,
+        Some(other) => return Err(format!("class '{}' should be A or B", other)),
+    };
+    let speed = number("speed").ok_or("it needs a speed")?;
+    let plan = if v["crossing"].is_object() {
+        // A crossing works out its own start and course from ours
+        if let Some(key) = ["start", "course", "waypoints"].into_iter().find(|k| v.has_key(k)) {
+            return Err(format!("it can have a crossing or a {}, not both", key));
+        }
+        let c = &v["crossing"];
+        Plan::Crossing {
+            angle: c["angle"].as_f64().ok_or("the crossing needs an angle")?,
+            cpa: c["cpa"].as_f64().unwrap_or(0.0),
+            tcpa: c["tcpa"].as_f64().ok_or("the crossing needs a tcpa")?,
+        }
+    } else {
+        let (lat, lon) = lat_lon(&v["start"]).map_err(|e| format!("it needs a start or a crossing: {}", e))?;
+        if v["waypoints"].is_array() {
+            let mut points = vec![(lat, lon)];
+            for p in v["waypoints"].members() {
+                points.push(lat_lon(p)?);
+            }
+            Plan::Waypoints(points)
+        } else {
+            let course = number("course").ok_or("it needs a course or waypoints")?;
+            Plan::Straight { lat, lon, course }
+        }
+    };
+    let mmsi = match number("mmsi") {
+        Some(m) if (100_000_000.0..1_000_000_000.0).contains(&m) => m as u32,
+        Some(m) => return Err(format!("{} isn't a nine digit MMSI", m)),
+        // Made up ones are in the 9xxxxxxxx range, which isn't given out to ships
+        None => 900_000_101 + i as u32,
+    };
+    Ok(Vessel {
+        mmsi,
+        name: v["name"].as_str().map_or(format!("TARGET {}", i + 1), |n| n.to_string()),
+        class_b,
+        // Cargo ships for class A and pleasure craft for class B, unless it says
+        ship_type: number("ship_type").map_or(if class_b { 37 } else { 70 }, |t| t as u8),
+        speed,
+        plan,
+        next_report: None,
+        next_static: None,
+    })
+}
+
+fn toml_to_json(value: &toml::Value) -> json::JsonValue {
+    match value {
+        toml::Value::String(s) => s.as_str().into(),
+        toml::Value::Integer(i) => (*i).into(),
+        toml::Value::Float(f) => (*f).into(),
+        toml::Value::Boolean(b) => (*b).into(),
+        toml::Value::Datetime(d) => d.to_string().into(),
+        toml::Value::Array(list) => json::JsonValue::Array(list.iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => {
+            let mut object = json::JsonValue::new_object();
+            for (k, v) in table {
+                object[k.as_str()] = toml_to_json(v);
+            }
+            object
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_vessel_needs_a_start_or_a_crossing() {
+        let crossing = json::parse(r#"{"speed": 12, "crossing": {"angle": 90, "tcpa": 8}}"#).unwrap();
+        assert!(matches!(vessel(0, &crossing).unwrap().plan, Plan::Crossing { .. }));
+        let both = json::parse(r#"{"speed": 12, "start": [50.7, -1.2], "crossing": {"angle": 90, "tcpa": 8}}"#).unwrap();
+        assert_eq!(vessel(0, &both).err().unwrap(), "it can have a crossing or a start, not both");
+        let course = json::parse(r#"{"speed": 12, "course": 10, "crossing": {"angle": 90, "tcpa": 8}}"#).unwrap();
+        assert_eq!(vessel(0, &course).err().unwrap(), "it can have a crossing or a course, not both");
+        let neither = json::parse(r#"{"speed": 12, "course": 10}"#).unwrap();
+        assert!(vessel(0, &neither).err().unwrap().starts_with("it needs a start or a crossing"));
+    }
+
+    const OWN: Motion = Motion {
+        lat: 50.0,
+        lon: -1.0,
+        cog: 30.0,
+        sog: 6.0,
+    };
+
+    fn vessel_at(plan: Plan, speed: f64, hours: f64) -> (f64, f64, f64, f64) {
+        let v = Vessel {
+            mmsi: 900_000_101,
+            name: String::new(),
+            class_b: false,
+            ship_type: 70,
+            speed,
+            plan,
+            next_report: None,
+            next_static: None,
+        };
+        v.at(hours)
+    }
+
+    fn scenario(name: &str, text: &str) -> io::Result<Traffic> {
+        let path = std::env::temp_dir().join(format!("nmea_player_scenario_{}_{}", std::process::id(), name));
+        std::fs::write(&path, text).unwrap();
+        let traffic = Traffic::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        traffic
+    }
+
+    fn at(h: u32, m: u32, s: u32) -> Option<NaiveDateTime> {
+        chrono::NaiveDate::from_ymd_opt(2021, 2, 22).unwrap().and_hms_opt(h, m, s)
+    }
+
+    #[test]
+    fn crossing_passes_at_the_cpa_after_the_tcpa() {
+        let hours = 8.0 / 60.0;
+        let (lat, lon) = geo::destination(OWN.lat, OWN.lon, OWN.cog, OWN.sog * hours * 1852.0);
+        let mut sides = vec![];
+        for cpa in [0.5, -0.5] {
+            let (tlat, tlon, cog, sog) = vessel_at(crossing(OWN, 90.0, cpa, 8.0, 12.0), 12.0, hours);
+            assert_eq!((cog, sog), (120.0, 12.0));
+            assert!((geo::distance_m(lat, lon, tlat, tlon) - 926.0).abs() < 2.0);
+            sides.push((tlat, tlon));
+            // and it was nearer then than at any other time
+            let (start_lat, start_lon, _, _) = vessel_at(crossing(OWN, 90.0, cpa, 8.0, 12.0), 12.0, 0.0);
+            let other = Motion { lat: start_lat, lon: start_lon, cog, sog };
+            let (miles, minutes) = geo::cpa(OWN, other);
+            assert!((miles - 0.5).abs() < 0.01 && (minutes - 8.0).abs() < 0.1);
+        }
+        // A negative cpa passes on the other side
+        let ((a_lat, a_lon), (b_lat, b_lon)) = (sides[0], sides[1]);
+        assert!((geo::distance_m(a_lat, a_lon, b_lat, b_lon) - 1852.0).abs() < 4.0);
+    }
+
+    #[test]
+    fn waypoints_one_after_the_other() {
+        let north = geo::destination(50.0, -1.0, 0.0, 1852.0);
+        let east = geo::destination(north.0, north.1, 90.0, 1852.0);
+        let plan = || Plan::Waypoints(vec![(50.0, -1.0), north, east]);
+        let (lat, lon, cog, sog) = vessel_at(plan(), 6.0, 0.25);
+        // A mile and a half in: the whole first leg and half of the second
+        assert!((geo::distance_m(north.0, north.1, lat, lon) - 926.0).abs() < 2.0);
+        assert!((cog - 90.0).abs() < 0.1 && sog == 6.0);
+        // Stopped at the end
+        let (lat, lon, _, sog) = vessel_at(plan(), 6.0, 1.0);
+        assert_eq!(((lat, lon), sog), (east, 0.0));
+    }
+
+    #[test]
+    fn reporting_intervals() {
+        assert_eq!(reporting_interval(false, 1.0), Duration::minutes(3));
+        assert_eq!(reporting_interval(true, 1.0), Duration::minutes(3));
+        assert_eq!(reporting_interval(true, 30.0), Duration::seconds(30));
+        assert_eq!(reporting_interval(false, 12.0), Duration::seconds(10));
+        assert_eq!(reporting_interval(false, 20.0), Duration::seconds(6));
+        assert_eq!(reporting_interval(false, 30.0), Duration::seconds(2));
+    }
+
+    #[test]
+    fn reports_wait_for_our_own_course_and_speed() {
+        let mut traffic = scenario(
+            "due.toml",
+            "[[vessel]]\nmmsi = 235000001\nname = \"NORMAN\"\nstart = [50.0, -1.1]\ncourse = 90\nspeed = 12\n\n\
+             [[vessel]]\nclass = \"B\"\ncrossing = { angle = 90, cpa = 0.1, tcpa = 5 }\nspeed = 6\n",
+        )
+        .unwrap();
+        assert!(traffic.due(at(12, 0, 0)).is_empty());
+        traffic.observe("$GPRMC,120000,A,5000.00,N,00100.00,W,6.0,30.0,220221,,");
+        assert!(traffic.due(None).is_empty());
+        let kinds = |reports: &[Report]| reports.iter().map(|r| (r.kind, r.mmsi)).collect::<Vec<_>>();
+        let first = traffic.due(at(12, 0, 0));
+        assert_eq!(kinds(&first), vec![(5, 235_000_001), (1, 235_000_001), (24, 900_000_102), (18, 900_000_102)]);
+        assert_eq!((first[0].name.as_str(), first[0].ship_type), ("NORMAN", Some(70)));
+        assert_eq!((first[2].name.as_str(), first[2].ship_type), ("TARGET 2", Some(37)));
+        // Class A at 12 knots every 10 seconds, class B every 30
+        assert!(traffic.due(at(12, 0, 9)).is_empty());
+        assert_eq!(kinds(&traffic.due(at(12, 0, 10))), vec![(1, 235_000_001)]);
+        assert_eq!(kinds(&traffic.due(at(12, 0, 30))), vec![(1, 235_000_001), (18, 900_000_102)]);
+        // and the names again every six minutes
+        let later = traffic.due(at(12, 6, 0));
+        assert_eq!(kinds(&later), vec![(5, 235_000_001), (1, 235_000_001), (24, 900_000_102), (18, 900_000_102)]);
+        let (lat, lon) = later[1].pos.unwrap();
+        assert!((geo::distance_m(50.0, -1.1, lat, lon) - 1.2 * 1852.0).abs() < 2.0);
+    }
+
+    #[test]
+    fn scenario_files() {
+        let json = scenario("list.json", r#"{"vessels": [{"speed": 5, "start": [50, -1], "course": 0}]}"#).unwrap();
+        assert_eq!(json.vessels.len(), 1);
+        let error = |name: &str, text: &str| scenario(name, text).err().unwrap().to_string();
+        assert!(error("empty.toml", "").ends_with("there are no vessels in it"));
+        assert!(error("class.json", r#"{"vessel": [{"class": "C", "speed": 5}]}"#).ends_with("vessel 1: class 'C' should be A or B"));
+        assert!(error("mmsi.toml", "[[vessel]]\nmmsi = 1234\nspeed = 5\nstart = [50, -1]\ncourse = 0")
+            .ends_with("1234 isn't a nine digit MMSI"));
+        assert!(error("speed.toml", "[[vessel]]\nstart = [50, -1]").ends_with("it needs a speed"));
+        assert!(error("start.toml", "[[vessel]]\nspeed = 5\nstart = [50]").contains("should be [lat, lon]"));
+        assert!(error("broken.toml", "[[vessel]\n").contains("broken.toml"));
+    }
+}